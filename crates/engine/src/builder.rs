@@ -0,0 +1,337 @@
+//! Creating a download with its options in one chain of calls
+
+use crate::{
+    filename_from_url, sanitize_filename, Checksum, Download, DownloadError, DownloadId,
+    FALLBACK_FILENAME,
+};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Collects a download's options and checks them together, see `Download::builder`
+///
+/// Only the URL is required. Anything left unset falls back to the
+/// global `ChunkConfig` and `ClientConfig` when the download runs.
+#[derive(Debug, Clone)]
+pub struct DownloadBuilder {
+    id: DownloadId,
+    url: Option<String>,
+    file_path: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    move_to: Option<PathBuf>,
+    extract_to: Option<PathBuf>,
+    category: Option<String>,
+    checksum: Option<Checksum>,
+    sha256: Option<String>,
+    chunk_count: Option<u8>,
+    max_retries: Option<u32>,
+    retry_delay_ms: Option<u64>,
+    deadline_ms: Option<u64>,
+    user_agent: Option<String>,
+    scheduled_at: Option<SystemTime>,
+}
+
+impl DownloadBuilder {
+    /// Starts a download with ID `id` and no options set
+    pub fn new(id: DownloadId) -> Self {
+        Self {
+            id,
+            url: None,
+            file_path: None,
+            output_dir: None,
+            move_to: None,
+            extract_to: None,
+            category: None,
+            checksum: None,
+            sha256: None,
+            chunk_count: None,
+            max_retries: None,
+            retry_delay_ms: None,
+            deadline_ms: None,
+            user_agent: None,
+            scheduled_at: None,
+        }
+    }
+
+    /// Downloads from `url`
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Saves the file at exactly `path`
+    pub fn file_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file_path = Some(path.into());
+        self
+    }
+
+    /// Saves the file into `dir`, named after the last segment of the URL
+    ///
+    /// Can't be combined with `file_path`.
+    pub fn output_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = Some(dir.into());
+        self
+    }
+
+    /// Moves the finished file into `dir`
+    pub fn move_to(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.move_to = Some(dir.into());
+        self
+    }
+
+    /// Extracts the finished archive into `dir`
+    pub fn extract_to(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.extract_to = Some(dir.into());
+        self
+    }
+
+    /// Files the download under `category`
+    pub fn category(mut self, category: impl Into<String>) -> Self {
+        self.category = Some(category.into());
+        self
+    }
+
+    /// Verifies the finished file against `checksum`
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = Some(checksum);
+        self.sha256 = None;
+        self
+    }
+
+    /// Verifies the finished file against a SHA-256 digest in hex
+    ///
+    /// A malformed digest is reported by `build`.
+    pub fn sha256(mut self, hex: impl Into<String>) -> Self {
+        self.sha256 = Some(hex.into());
+        self.checksum = None;
+        self
+    }
+
+    /// Splits the download into `count` parallel chunks
+    pub fn chunks(mut self, count: u8) -> Self {
+        self.chunk_count = Some(count);
+        self
+    }
+
+    /// Retries each chunk up to `max_retries` times
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Waits `delay_ms` before the first retry
+    pub fn retry_delay_ms(mut self, delay_ms: u64) -> Self {
+        self.retry_delay_ms = Some(delay_ms);
+        self
+    }
+
+    /// Gives up if the download isn't done within `deadline_ms`
+    pub fn deadline_ms(mut self, deadline_ms: u64) -> Self {
+        self.deadline_ms = Some(deadline_ms);
+        self
+    }
+
+    /// Sends `user_agent` instead of the default User-Agent
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Holds the download back until `at`
+    pub fn schedule(mut self, at: SystemTime) -> Self {
+        self.scheduled_at = Some(at);
+        self
+    }
+
+    /// Checks the options and creates the download
+    ///
+    /// Fails with `DownloadError::InvalidUrl` if the URL is missing or
+    /// malformed, and with `DownloadError::InvalidConfig` if both a file
+    /// path and an output directory are set, the SHA-256 digest is
+    /// malformed, or zero chunks are asked for.
+    pub fn build(self) -> Result<Download, DownloadError> {
+        let invalid = |reason: &str| Err(DownloadError::InvalidConfig(reason.to_string()));
+
+        let url = self
+            .url
+            .ok_or_else(|| DownloadError::InvalidUrl("No URL given".to_string()))?;
+        if let Err(e) = reqwest::Url::parse(&url) {
+            return Err(DownloadError::InvalidUrl(format!("{}: {}", url, e)));
+        }
+        if self.file_path.is_some() && self.output_dir.is_some() {
+            return invalid("Set either a file path or an output directory, not both");
+        }
+        if self.chunk_count == Some(0) {
+            return invalid("Chunk count must be at least 1");
+        }
+
+        let checksum = match self.sha256.as_deref().map(Checksum::from_hex).transpose()? {
+            Some(Checksum::Sha256(digest)) => Some(Checksum::Sha256(digest)),
+            Some(_) => return invalid("SHA-256 digests are 64 hex digits"),
+            None => self.checksum,
+        };
+
+        let file_path = match self.output_dir {
+            Some(dir) => {
+                let name = filename_from_url(&url).unwrap_or_else(|| FALLBACK_FILENAME.to_string());
+                Some(dir.join(sanitize_filename(&name)))
+            }
+            None => self.file_path,
+        };
+
+        let mut download = Download::new(self.id, url);
+        if let Some(path) = file_path {
+            download.set_file_path(path);
+        }
+        if let Some(dir) = self.move_to {
+            download.set_move_to(dir);
+        }
+        if let Some(dir) = self.extract_to {
+            download.set_extract_to(dir);
+        }
+        if let Some(category) = self.category {
+            download.set_category(category);
+        }
+        if let Some(checksum) = checksum {
+            download.set_checksum(checksum);
+        }
+        if let Some(count) = self.chunk_count {
+            download.set_chunk_count(count);
+        }
+        if let Some(max_retries) = self.max_retries {
+            download.set_max_retries(max_retries);
+        }
+        if let Some(delay_ms) = self.retry_delay_ms {
+            download.set_retry_delay_ms(delay_ms);
+        }
+        if let Some(deadline_ms) = self.deadline_ms {
+            download.set_deadline_ms(deadline_ms);
+        }
+        if let Some(user_agent) = self.user_agent {
+            download.set_user_agent(user_agent);
+        }
+        if let Some(at) = self.scheduled_at {
+            download.schedule(at);
+        }
+
+        Ok(download)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChunkConfig, ClientConfig, DownloadStatus};
+    use std::path::Path;
+    use std::time::Duration;
+
+    const DIGEST: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn test_build_full_spec() {
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let download = Download::builder(DownloadId::new(7))
+            .url("https://mirror.example/pub/linux%20x64.iso")
+            .output_dir("/downloads")
+            .move_to("/archive")
+            .category("Software")
+            .sha256(DIGEST)
+            .chunks(16)
+            .max_retries(10)
+            .retry_delay_ms(250)
+            .deadline_ms(60_000)
+            .user_agent("Mozilla/5.0")
+            .schedule(at)
+            .build()
+            .unwrap();
+
+        assert_eq!(download.id(), DownloadId::new(7));
+        assert_eq!(download.status(), DownloadStatus::Pending);
+        assert_eq!(
+            download.file_path().map(PathBuf::as_path),
+            Some(Path::new("/downloads/linux x64.iso"))
+        );
+        assert_eq!(download.move_to(), Some(&PathBuf::from("/archive")));
+        assert_eq!(download.category(), Some("Software"));
+        assert_eq!(
+            download.checksum(),
+            Some(&Checksum::from_hex(DIGEST).unwrap())
+        );
+        assert_eq!(download.scheduled_at(), Some(at));
+
+        // the overrides reach the per-download configs
+        let chunk = ChunkConfig::default().for_download(&download);
+        assert_eq!(
+            (chunk.chunk_count, chunk.max_retries, chunk.retry_delay_ms),
+            (16, 10, 250)
+        );
+        assert_eq!(chunk.deadline_ms, Some(60_000));
+        let client = ClientConfig::default().for_download(&download);
+        assert_eq!(client.user_agent.as_deref(), Some("Mozilla/5.0"));
+    }
+
+    #[test]
+    fn test_build_minimal_spec() {
+        let download = Download::builder(DownloadId::new(8))
+            .url("https://a.example/")
+            .build()
+            .unwrap();
+
+        assert_eq!(download.url(), "https://a.example/");
+        assert!(download.file_path().is_none());
+        assert!(download.checksum().is_none());
+        assert!(download.chunk_count().is_none());
+        assert!(download.is_due(SystemTime::now()));
+
+        // an output dir still names a file when the URL doesn't
+        let download = Download::builder(DownloadId::new(9))
+            .url("https://a.example/")
+            .output_dir("dl")
+            .checksum(Checksum::Md5(vec![0; 16]))
+            .build()
+            .unwrap();
+        assert_eq!(
+            download.file_path(),
+            Some(&Path::new("dl").join(FALLBACK_FILENAME))
+        );
+        assert_eq!(download.checksum().unwrap().algorithm(), "MD5");
+
+        // a name from the URL is made safe for this OS
+        let download = Download::builder(DownloadId::new(10))
+            .url("https://a.example/bad%07name.iso")
+            .output_dir("dl")
+            .build()
+            .unwrap();
+        assert_eq!(
+            download.file_path(),
+            Some(&Path::new("dl").join("bad_name.iso"))
+        );
+    }
+
+    #[test]
+    fn test_build_rejects_bad_specs() {
+        let builder = || Download::builder(DownloadId::new(10));
+
+        let missing_url = builder().output_dir("dl").build();
+        assert!(matches!(missing_url, Err(DownloadError::InvalidUrl(_))));
+        let bad_url = builder().url("not a url").build();
+        assert!(matches!(bad_url, Err(DownloadError::InvalidUrl(_))));
+
+        let url = "https://a.example/x.bin";
+        let builders = [
+            builder().url(url).file_path("x.bin").output_dir("dl"),
+            builder().url(url).sha256("not hex"),
+            builder()
+                .url(url)
+                .sha256("d41d8cd98f00b204e9800998ecf8427e"), // an MD5
+            builder().url(url).chunks(0),
+        ];
+        for builder in builders {
+            let debug = format!("{:?}", builder);
+            assert!(
+                matches!(builder.build(), Err(DownloadError::InvalidConfig(_))),
+                "{}",
+                debug
+            );
+        }
+    }
+}
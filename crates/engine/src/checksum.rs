@@ -0,0 +1,273 @@
+//! Checksum verification of downloaded files
+
+use crate::disk::check_length;
+use crate::DownloadError;
+use sha2::digest::DynDigest;
+use std::io::Read;
+use std::path::Path;
+
+/// Expected digest of a file, tagged with its algorithm
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checksum {
+    /// MD5 digest (16 bytes)
+    Md5(Vec<u8>),
+    /// SHA-1 digest (20 bytes)
+    Sha1(Vec<u8>),
+    /// SHA-256 digest (32 bytes)
+    Sha256(Vec<u8>),
+    /// SHA-512 digest (64 bytes)
+    Sha512(Vec<u8>),
+}
+
+impl Checksum {
+    /// Parses a hex digest, picking the algorithm from its length
+    ///
+    /// 32, 40, 64 and 128 hex digits mean MD5, SHA-1, SHA-256 and SHA-512.
+    pub fn from_hex(hex: &str) -> Result<Self, DownloadError> {
+        let bytes = decode_hex(hex.trim())
+            .ok_or_else(|| DownloadError::InvalidConfig(format!("Invalid checksum: {}", hex)))?;
+
+        match bytes.len() {
+            16 => Ok(Checksum::Md5(bytes)),
+            20 => Ok(Checksum::Sha1(bytes)),
+            32 => Ok(Checksum::Sha256(bytes)),
+            64 => Ok(Checksum::Sha512(bytes)),
+            len => Err(DownloadError::InvalidConfig(format!(
+                "Unknown checksum length: {} bytes",
+                len
+            ))),
+        }
+    }
+
+    /// Finds the checksum for `file_name` in the contents of a `*sums` file
+    ///
+    /// Understands the `<hex>  <name>` lines written by `sha256sum` and
+    /// friends, including the `*<name>` binary marker.
+    pub fn from_sums_file(contents: &str, file_name: &str) -> Option<Self> {
+        contents.lines().find_map(|line| {
+            let (hex, name) = line.trim().split_once(char::is_whitespace)?;
+            let name = name.trim_start();
+            let name = name.strip_prefix('*').unwrap_or(name);
+
+            (name == file_name)
+                .then(|| Self::from_hex(hex).ok())
+                .flatten()
+        })
+    }
+
+    /// Returns the algorithm's name, e.g. "SHA-256"
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            Checksum::Md5(_) => "MD5",
+            Checksum::Sha1(_) => "SHA-1",
+            Checksum::Sha256(_) => "SHA-256",
+            Checksum::Sha512(_) => "SHA-512",
+        }
+    }
+
+    /// Returns the expected digest bytes
+    pub fn expected(&self) -> &[u8] {
+        match self {
+            Checksum::Md5(bytes)
+            | Checksum::Sha1(bytes)
+            | Checksum::Sha256(bytes)
+            | Checksum::Sha512(bytes) => bytes,
+        }
+    }
+
+    /// Creates an empty hasher for this algorithm
+    fn hasher(&self) -> Box<dyn DynDigest + Send> {
+        match self {
+            Checksum::Md5(_) => Box::new(md5::Md5::default()),
+            Checksum::Sha1(_) => Box::new(sha1::Sha1::default()),
+            Checksum::Sha256(_) => Box::new(sha2::Sha256::default()),
+            Checksum::Sha512(_) => Box::new(sha2::Sha512::default()),
+        }
+    }
+}
+
+/// Hashes the file at `path` and compares it with `expected`
+///
+/// Fails with `DownloadError::ChecksumMismatch` if the digests differ.
+pub async fn verify_checksum(path: &Path, expected: &Checksum) -> Result<(), DownloadError> {
+    let path = path.to_path_buf();
+    let mut hasher = expected.hasher();
+
+    // hashing a large file is CPU bound, keep it off the runtime
+    let actual = tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)?;
+        let mut buffer = vec![0u8; 64 * 1024];
+
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+
+        Ok::<_, std::io::Error>(hasher.finalize())
+    })
+    .await
+    .map_err(|e| DownloadError::FileError(format!("Task failed: {}", e)))?
+    .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+    if *actual != *expected.expected() {
+        return Err(DownloadError::ChecksumMismatch {
+            expected: encode_hex(expected.expected()),
+            actual: encode_hex(&actual),
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks a file already on disk against a known checksum, without downloading
+///
+/// Useful after a manual copy or to re-validate a library of files. Fails
+/// with `DownloadError::FileError` if `path` doesn't exist and
+/// `DownloadError::ChecksumMismatch` if the contents differ.
+pub async fn verify_file(path: &Path, expected: &Checksum) -> Result<(), DownloadError> {
+    verify_checksum(path, expected).await
+}
+
+/// Like `verify_file`, but also checks the file is `size` bytes
+///
+/// The size is checked first, so a truncated file fails with
+/// `DownloadError::LengthMismatch` without being hashed.
+pub async fn verify_file_with_size(
+    path: &Path,
+    expected: &Checksum,
+    size: u64,
+) -> Result<(), DownloadError> {
+    check_length(path, size).await?;
+    verify_checksum(path, expected).await
+}
+
+/// Decodes a hex string, `None` if it isn't valid hex
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Encodes bytes as lowercase hex
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // digests of "abc" from the FIPS 180 / RFC 1321 test vectors
+    const MD5_ABC: &str = "900150983cd24fb0d6963f7d28e17f72";
+    const SHA1_ABC: &str = "a9993e364706816aba3e25717850c26c9cd0d89d";
+    const SHA256_ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const SHA512_ABC: &str = "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                              2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f";
+
+    async fn abc_file(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(name);
+        tokio::fs::write(&path, b"abc").await.unwrap();
+        path
+    }
+
+    #[test]
+    fn test_from_hex_detects_algorithm() {
+        assert_eq!(Checksum::from_hex(MD5_ABC).unwrap().algorithm(), "MD5");
+        assert_eq!(Checksum::from_hex(SHA1_ABC).unwrap().algorithm(), "SHA-1");
+        assert_eq!(
+            Checksum::from_hex(SHA256_ABC).unwrap().algorithm(),
+            "SHA-256"
+        );
+        assert_eq!(
+            Checksum::from_hex(SHA512_ABC).unwrap().algorithm(),
+            "SHA-512"
+        );
+
+        // upper case is fine too
+        let upper = Checksum::from_hex(&MD5_ABC.to_uppercase()).unwrap();
+        assert_eq!(upper, Checksum::from_hex(MD5_ABC).unwrap());
+    }
+
+    #[test]
+    fn test_from_hex_rejects_bad_input() {
+        assert!(Checksum::from_hex("xyz").is_err());
+        assert!(Checksum::from_hex("abc").is_err()); // odd length
+        assert!(Checksum::from_hex("abcd").is_err()); // no such algorithm
+    }
+
+    #[test]
+    fn test_from_sums_file() {
+        let sums = format!("{}  other.iso\n{} *ubuntu.iso\n", SHA1_ABC, SHA256_ABC);
+
+        let checksum = Checksum::from_sums_file(&sums, "ubuntu.iso").unwrap();
+        assert_eq!(checksum, Checksum::from_hex(SHA256_ABC).unwrap());
+
+        assert!(Checksum::from_sums_file(&sums, "missing.iso").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_verify_each_algorithm() {
+        let path = abc_file("test_verify_checksum.bin").await;
+
+        for hex in [MD5_ABC, SHA1_ABC, SHA256_ABC, SHA512_ABC] {
+            let checksum = Checksum::from_hex(hex).unwrap();
+            verify_checksum(&path, &checksum).await.unwrap();
+        }
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_verify_file() {
+        let path = abc_file("test_verify_file.bin").await;
+        let checksum = Checksum::from_hex(SHA256_ABC).unwrap();
+
+        verify_file(&path, &checksum).await.unwrap();
+        verify_file_with_size(&path, &checksum, 3).await.unwrap();
+
+        // size is checked before hashing
+        assert_eq!(
+            verify_file_with_size(&path, &checksum, 4).await,
+            Err(DownloadError::LengthMismatch {
+                expected: 4,
+                actual: 3
+            })
+        );
+
+        let _ = tokio::fs::remove_file(&path).await;
+
+        // a missing file is a file error, not a mismatch
+        assert!(matches!(
+            verify_file(&path, &checksum).await,
+            Err(DownloadError::FileError(_))
+        ));
+        assert!(matches!(
+            verify_file_with_size(&path, &checksum, 3).await,
+            Err(DownloadError::FileError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_verify_mismatch() {
+        let path = abc_file("test_verify_checksum_mismatch.bin").await;
+        let checksum = Checksum::Md5(vec![0; 16]);
+
+        match verify_checksum(&path, &checksum).await {
+            Err(DownloadError::ChecksumMismatch { expected, actual }) => {
+                assert_eq!(expected, "0".repeat(32));
+                assert_eq!(actual, MD5_ABC);
+            }
+            other => panic!("expected ChecksumMismatch, got {:?}", other),
+        }
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+}
@@ -1,17 +1,169 @@
 //! Multi-part (chunked) download implementation
 
-use crate::DownloadError;
-use reqwest::Client;
-use std::path::Path;
-use std::time::Duration;
+use crate::disk::{
+    available_space, check_length, claim_free_name, discard_segments_from, layout_path,
+    load_layout, merge_segments, move_into_place, preallocate, save_layout, segment_path,
+    set_modified,
+};
+use crate::filename::{
+    filename_from_content_disposition, filename_from_url, sanitize_filename, FALLBACK_FILENAME,
+};
+use crate::checksum::{verify_checksum, Checksum};
+use crate::{ClientConfig, Download, DownloadError, MetalinkFile, RetryStatusPolicy};
+use crate::transport::{BodyStream, HttpTransport, ReqwestTransport, TransportResponse};
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::fs::File;
 use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
 use tokio::time::sleep;
+use tracing::{debug, info, instrument, warn, Instrument};
+
+/// Bytes fetched per connection when probing for the best chunk count
+const PROBE_SAMPLE_BYTES: u64 = 256 * 1024;
+
+/// Time limit for each probe round
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Throughput gain needed to consider more connections worthwhile
+const PROBE_MIN_GAIN: f64 = 1.2;
+
+/// Live chunk state shared by a download's connections
+type ChunkPlan = Arc<Mutex<Vec<Chunk>>>;
+
+/// Byte ranges of a download known to be written
+///
+/// Kept as merged `start -> end` (exclusive) ranges, so chunks finishing
+/// out of order join up once the gaps between them are filled.
+#[derive(Debug, Default)]
+struct Availability {
+    written: BTreeMap<u64, u64>,
+}
+
+impl Availability {
+    /// Starts over from the progress recorded in `chunks`
+    fn reset(&mut self, chunks: &[Chunk]) {
+        self.written.clear();
+        for chunk in chunks.iter().filter(|c| c.downloaded > 0) {
+            self.insert(chunk.start, chunk.resume_position());
+        }
+    }
+
+    /// Records bytes `start..end` as written
+    fn insert(&mut self, mut start: u64, mut end: u64) {
+        if let Some((&before, &before_end)) = self.written.range(..=start).next_back() {
+            if before_end >= start {
+                start = before;
+                end = end.max(before_end);
+            }
+        }
+
+        let overlapping: Vec<u64> = self.written.range(start..=end).map(|(&s, _)| s).collect();
+        for range_start in overlapping {
+            end = end.max(self.written.remove(&range_start).unwrap_or(end));
+        }
+
+        self.written.insert(start, end);
+    }
+
+    /// Length of the written range starting at byte 0
+    fn contiguous(&self) -> u64 {
+        self.written.get(&0).copied().unwrap_or(0)
+    }
+}
+
+/// State kept for one download, shared by its chunk tasks
+#[derive(Debug, Default)]
+struct DownloadRun {
+    failures: Mutex<VecDeque<Instant>>, // recent chunk failures, see `failure_limit`
+    retries: AtomicU32,                 // see `DownloadOutcome::retries`
+    wasted: AtomicU64,                  // see `DownloadOutcome::wasted_bytes`
+    timings: Mutex<Vec<RequestTiming>>, // see `DownloadOutcome::timings`
+    available: Mutex<Availability>,     // bytes written so far, if readable is set
+    readable: Option<watch::Sender<u64>>, // see `download_watched`
+}
+
+/// Waits for the next buffer of a response body
+///
+/// A connection can stay open while sending nothing; with a stall timeout
+/// set, that becomes a `DownloadError::Timeout` instead of a hang.
+async fn next_with_stall_timeout<S>(
+    stream: &mut S,
+    stall_timeout_ms: Option<u64>,
+) -> Result<Option<S::Item>, DownloadError>
+where
+    S: futures_util::Stream + Unpin,
+{
+    use futures_util::StreamExt;
+
+    let Some(stall_ms) = stall_timeout_ms else {
+        return Ok(stream.next().await);
+    };
+
+    tokio::time::timeout(Duration::from_millis(stall_ms), stream.next())
+        .await
+        .map_err(|_| DownloadError::Timeout(format!("No data for {}ms", stall_ms)))
+}
+
+/// Locks the plan, the data stays consistent even if a holder panicked
+fn lock_plan(plan: &ChunkPlan) -> MutexGuard<'_, Vec<Chunk>> {
+    plan.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Locks a run's request timings, like `lock_plan`
+fn lock_timings(timings: &Mutex<Vec<RequestTiming>>) -> MutexGuard<'_, Vec<RequestTiming>> {
+    timings.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns the file size the plan was made for
+fn plan_size(plan: &ChunkPlan) -> u64 {
+    lock_plan(plan).iter().map(|c| c.end + 1).max().unwrap_or(0)
+}
+
+/// Reads the total size from a `Content-Range: bytes <start>-<end>/<total>` header
+fn content_range_total(response: &TransportResponse) -> Option<u64> {
+    let value = response.headers().get(reqwest::header::CONTENT_RANGE)?.to_str().ok()?;
+    let (_, total) = value.rsplit_once('/')?;
+
+    // "*" means the server doesn't know the total
+    total.trim().parse().ok()
+}
+
+/// Reads the first and last byte from a `Content-Range: bytes <start>-<end>/<total>` header
+fn content_range_span(response: &TransportResponse) -> Option<(u64, u64)> {
+    let value = response.headers().get(reqwest::header::CONTENT_RANGE)?.to_str().ok()?;
+    let (span, _) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (first, last) = span.split_once('-')?;
+
+    Some((first.trim().parse().ok()?, last.trim().parse().ok()?))
+}
+
+/// Where chunk data is written while downloading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SegmentMode {
+    /// All chunks write into one preallocated file at their offsets
+    #[default]
+    SingleFile,
+    /// Each chunk writes its own `<name>.part<index>` file, and the segments
+    /// are concatenated into the destination once all are complete
+    ///
+    /// Avoids seeking within one large file and makes each segment resume
+    /// on its own.
+    SeparateFiles,
+}
 
 /// Configuration for chunked downloads
 #[derive(Debug, Clone)]
 pub struct ChunkConfig {
     /// Number of chunks to split the file into
+    ///
+    /// Each chunk is one request at a time. Over HTTP/1.1 that's one
+    /// connection per chunk; over HTTP/2 the chunks share a connection as
+    /// concurrent streams, see `ClientConfig::http_version`.
     pub chunk_count: u8,
     /// Minimum size in bytes for a chunk (don't split if file is smaller)
     pub min_chunk_size: u64,
@@ -21,17 +173,215 @@ pub struct ChunkConfig {
     pub retry_delay_ms: u64,
     /// Whether to use exponential backoff (doubles delay each retry)
     pub exponential_backoff: bool,
+    /// Whether to reserve disk blocks up front instead of creating a sparse file
+    ///
+    /// Catches a full disk before downloading, but can be slow on some
+    /// network filesystems.
+    pub preallocate: bool,
+    /// Whether to send `Accept-Encoding: identity` on probes and range requests
+    ///
+    /// Some servers omit Content-Length when they might compress, which
+    /// disables chunking. Byte ranges also refer to the encoded body, so
+    /// ranged downloads need the uncompressed form. Turn this off only when
+    /// compressed transfer matters more than parallel chunks.
+    pub identity_encoding: bool,
+    /// Maximum time in milliseconds for one attempt at a chunk (None = no limit)
+    ///
+    /// Bounds the whole attempt including a slow trickle, unlike the
+    /// client's connect/read timeouts. A timed-out attempt is retried from
+    /// where it stopped.
+    pub per_chunk_timeout_ms: Option<u64>,
+    /// Whether a connection that finishes early takes over half of the
+    /// largest remaining chunk
+    pub rebalance_chunks: bool,
+    /// Maximum time in milliseconds to wait for the next bytes of a chunk
+    /// (None = wait indefinitely)
+    ///
+    /// Catches a connection that stays open but stops sending, which a
+    /// trickle would keep alive under `per_chunk_timeout_ms`. The stalled
+    /// attempt is dropped and retried from the current position.
+    pub stall_timeout_ms: Option<u64>,
+    /// Whether chunks share the destination file or use segment files
+    pub segment_mode: SegmentMode,
+    /// Directory for partial files (None = next to the destination)
+    ///
+    /// The finished file is moved to its destination, copying if the
+    /// directory is on another filesystem. Partials are named after the
+    /// whole destination path, see `ChunkedDownloader::work_path`.
+    pub temp_dir: Option<PathBuf>,
+    /// Number of full re-downloads after a checksum mismatch
+    ///
+    /// A corrupt chunk is usually transient, so one retry lets it heal
+    /// itself. Chunks carry no digests of their own, so the whole file is
+    /// fetched again.
+    pub max_verify_retries: u32,
+    /// Number of received buffers a chunk may hold while its file write catches up
+    ///
+    /// Lets the network keep reading during a slow write, e.g. to a network
+    /// share or USB stick, while bounding memory per connection. With 0 each
+    /// buffer is written before the next is read, which is fastest on local
+    /// disks.
+    pub write_buffer_chunks: usize,
+    /// Most bytes held in `write_buffer_chunks` buffers at once, across all
+    /// chunks and downloads of this downloader (None = no limit)
+    ///
+    /// `write_buffer_chunks` bounds each connection, but many downloads of
+    /// many chunks still add up. Once the budget is used, connections stop
+    /// reading until the disk catches up. Has no effect when
+    /// `write_buffer_chunks` is 0, as nothing is buffered then.
+    pub max_buffer_memory: Option<usize>,
+    /// Bytes before each resume point to fetch again and compare with the partial file
+    ///
+    /// Catches local data that doesn't match the server's, e.g. from an
+    /// off-by-one seek in an earlier run; a chunk that fails the check starts
+    /// over. Costs one small request per resumed chunk, 4096 is plenty.
+    pub resume_check_bytes: Option<u64>,
+    /// Whether to start over once if the remote file changes mid-download
+    ///
+    /// A change is noticed when a chunk's Content-Range reports a different
+    /// total size. The partial data mixes two versions of the file and is
+    /// discarded either way; without this the download then fails with
+    /// `DownloadError::ResourceChanged`.
+    pub restart_on_change: bool,
+    /// Free space in bytes a download must leave on its disk (None = fill it)
+    ///
+    /// A download that fits but leaves only a few megabytes can break other
+    /// programs or the OS itself. Checked against the directory the data is
+    /// written to before anything is fetched.
+    pub min_free_space_after: Option<u64>,
+    /// Most chunk files open at once across this downloader's downloads
+    /// (None = no limit)
+    ///
+    /// Each chunk holds its file open while it downloads, so separate
+    /// segment files or a large `download_batch` can run into the process's
+    /// file descriptor limit. Chunks over the budget wait for a free slot
+    /// instead of failing with "too many open files".
+    pub max_open_files: Option<usize>,
+    /// Smallest file size in bytes accepted as a real download (None = any size)
+    ///
+    /// Some endpoints answer 200 with an empty body instead of a 401, e.g.
+    /// behind a login wall, which would otherwise pass as a finished empty
+    /// file. A smaller Content-Length fails before anything is fetched, a
+    /// smaller body once it has arrived; either way with
+    /// `DownloadError::EmptyResponse` and no file left behind. Off by
+    /// default, as empty files do exist.
+    pub min_expected_size: Option<u64>,
+    /// Bytes each connection writes between flushes (None = flush at the end)
+    ///
+    /// Without it, written data may sit in buffers until the download
+    /// finishes, and a crash or power cut loses it even though the partial
+    /// file looks long enough to resume. Smaller intervals lose less but
+    /// cost throughput.
+    pub flush_interval_bytes: Option<u64>,
+    /// Whether each interval flush also waits for the data to reach the disk
+    ///
+    /// Only matters with `flush_interval_bytes`. Survives power loss rather
+    /// than just a crash, at a much higher cost per flush.
+    pub sync_on_flush: bool,
+    /// Longest a whole download may take in milliseconds (None = no limit)
+    ///
+    /// A wall-clock cap over everything, retries included, unlike
+    /// `per_chunk_timeout_ms` and `stall_timeout_ms`. When it runs out the
+    /// chunks are cancelled and the download fails with
+    /// `DownloadError::DeadlineExceeded`; the partial file is kept for a
+    /// later `download_resumable`.
+    pub deadline_ms: Option<u64>,
+    /// Status codes to retry or not regardless of the default classification
+    ///
+    /// Checked before each chunk retry; see `RetryStatusPolicy` for the
+    /// precedence.
+    pub retry_statuses: RetryStatusPolicy,
+    /// Most HEAD requests in flight at once across this downloader's
+    /// downloads (None = no limit)
+    ///
+    /// Starting many downloads at once, e.g. with `download_batch`, would
+    /// otherwise probe every URL at the same moment, a burst of connections
+    /// before any data moves. Only the metadata requests wait; transfers
+    /// are bounded separately.
+    pub max_concurrent_probes: Option<usize>,
+    /// Round chunk boundaries to the nearest multiple of this many bytes
+    /// (None = split evenly)
+    ///
+    /// Block-based origins and CDN caches often store files in fixed-size
+    /// blocks, e.g. 1MB, and serve aligned ranges from cache more often.
+    /// The last chunk still ends at the end of the file, and chunks that
+    /// rounding would leave empty are dropped, so there may be fewer than
+    /// `chunk_count`.
+    pub chunk_alignment: Option<u64>,
+    /// Give up with `DownloadError::TooManyFailures` once more than this
+    /// many chunk attempts of one download fail within `failure_window_ms`
+    /// (None = never)
+    ///
+    /// Meant for pausing a flaky download instead of failing it, see
+    /// `Download::fail_with`, so a queue can move on and come back to it.
+    /// Only failures that would be retried count.
+    pub failure_limit: Option<u32>,
+    /// Window `failure_limit` counts failures in, in milliseconds
+    pub failure_window_ms: u64,
+    /// Whether to give the finished file the server's `Last-Modified` time
+    ///
+    /// For mirrors and sync tools that compare timestamps. The time is set
+    /// once the file is in place; without a parseable header the file keeps
+    /// the time it was written.
+    pub preserve_timestamps: bool,
 }
 
 impl Default for ChunkConfig {
     fn default() -> Self {
         Self {
-            chunk_count: 8,               // 8 parallel connections (like IDM)
-            min_chunk_size: 1_048_576,    // 1MB minimum per chunk
-            max_retries: 3,               // retry up to 3 times
-            retry_delay_ms: 1000,         // start with 1 second delay
-            exponential_backoff: true,    // 1s, 2s, 4s, 8s...
+            chunk_count: 8,                        // 8 parallel connections (like IDM)
+            min_chunk_size: 1_048_576,             // 1MB minimum per chunk
+            max_retries: 3,                        // retry up to 3 times
+            retry_delay_ms: 1000,                  // start with 1 second delay
+            exponential_backoff: true,             // 1s, 2s, 4s, 8s...
+            preallocate: true,                     // reserve space before downloading
+            identity_encoding: true,               // ask for the uncompressed length
+            per_chunk_timeout_ms: None,            // chunks may take as long as they need
+            rebalance_chunks: true,                // keep every connection busy
+            stall_timeout_ms: Some(30_000),        // reconnect after 30s of silence
+            segment_mode: SegmentMode::SingleFile, // chunks seek within one file
+            temp_dir: None,                        // partials sit next to the destination
+            max_verify_retries: 1,                 // one more try after a bad checksum
+            write_buffer_chunks: 0,                // write each buffer as it arrives
+            max_buffer_memory: Some(64 << 20),     // 64MB waiting for the disk
+            resume_check_bytes: None,              // trust partial data as is
+            restart_on_change: false,              // report the change, let the caller decide
+            min_free_space_after: Some(100 << 20), // leave 100MB for everything else
+            max_open_files: Some(512),             // half the usual 1024 descriptor limit
+            min_expected_size: None,               // empty files are fine
+            flush_interval_bytes: None,            // flush once, at the end
+            sync_on_flush: false,                  // leave syncing to the OS
+            deadline_ms: None,                     // take as long as it takes
+            retry_statuses: RetryStatusPolicy::default(), // is_retryable decides
+            max_concurrent_probes: Some(8),        // smooth out startup bursts
+            chunk_alignment: None,                 // split evenly
+            failure_limit: None,                   // retries decide
+            failure_window_ms: 60_000,             // 1 minute
+            preserve_timestamps: false,            // modified when downloaded
+        }
+    }
+}
+
+impl ChunkConfig {
+    /// Returns a copy of this config with the download's retry, deadline and
+    /// chunk count overrides applied
+    pub fn for_download(&self, download: &Download) -> Self {
+        let mut config = self.clone();
+
+        if let Some(max_retries) = download.max_retries() {
+            config.max_retries = max_retries;
+        }
+        if let Some(delay_ms) = download.retry_delay_ms() {
+            config.retry_delay_ms = delay_ms;
+        }
+        if let Some(deadline_ms) = download.deadline_ms() {
+            config.deadline_ms = Some(deadline_ms);
         }
+        if let Some(chunk_count) = download.chunk_count() {
+            config.chunk_count = chunk_count;
+        }
+
+        config
     }
 }
 
@@ -68,45 +418,380 @@ impl Chunk {
     pub fn resume_position(&self) -> u64 {
         self.start + self.downloaded
     }
+
+    /// Splits off the back half of the remaining bytes as a new chunk
+    ///
+    /// This chunk keeps the front half (plus any odd byte) and its end is
+    /// moved back. Returns `None` if the new chunk would be smaller than
+    /// `min_size`.
+    pub fn split_off(&mut self, new_index: u8, min_size: u64) -> Option<Chunk> {
+        if self.is_complete() {
+            return None;
+        }
+
+        let remaining = self.remaining();
+        let back_half = remaining / 2;
+
+        if back_half == 0 || back_half < min_size {
+            return None;
+        }
+
+        let split_at = self.resume_position() + (remaining - back_half);
+        let stolen = Chunk {
+            index: new_index,
+            start: split_at,
+            end: self.end,
+            downloaded: 0,
+        };
+
+        self.end = split_at - 1;
+        Some(stolen)
+    }
+}
+
+/// A received buffer waiting for its write, counted against `max_buffer_memory`
+struct Buffered {
+    bytes: bytes::Bytes,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl AsRef<[u8]> for Buffered {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Returns true if `chunks` are all complete and cover bytes `0..file_size` exactly
+///
+/// A preallocated file already has its full length, so its size says
+/// nothing about which bytes were written; the chunk progress does. Use
+/// this on progress saved alongside the file, e.g. from `merge_partials`,
+/// rather than trusting the length. Chunks may come in any order, but any
+/// gap, overlap or unfinished chunk means the file isn't done.
+pub fn is_fully_downloaded(chunks: &[Chunk], file_size: u64) -> bool {
+    covers_exactly(chunks, file_size) && chunks.iter().all(Chunk::is_complete)
+}
+
+/// Returns true if `chunks` cover bytes `0..file_size` with no gaps or overlaps
+fn covers_exactly(chunks: &[Chunk], file_size: u64) -> bool {
+    let mut ordered = chunks.to_vec();
+    ordered.sort_by_key(|c| c.start);
+
+    let mut next = 0;
+    for chunk in &ordered {
+        if chunk.start != next || chunk.end < chunk.start {
+            return false;
+        }
+        next = chunk.end + 1;
+    }
+
+    next == file_size
+}
+
+/// How a download came by its file, see `DownloadOutcome`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OutcomeKind {
+    /// Downloaded from the first byte
+    Fresh,
+    /// Continued from a partial file
+    Resumed,
+    /// The file was already complete, nothing was downloaded
+    AlreadyComplete,
+}
+
+/// What a finished download did, e.g. for reporting "already up to date"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadOutcome {
+    /// Bytes received by this call, not counting ones already on disk
+    pub bytes_transferred: u64,
+    /// Size of the finished file
+    pub total_size: u64,
+    /// Whether the file was downloaded fresh, resumed or already there
+    pub kind: OutcomeKind,
+    /// When the server says the file was last modified, if it sent `Last-Modified`
+    pub last_modified: Option<SystemTime>,
+    /// Chunk retries this download needed, counting every extra attempt
+    pub retries: u32,
+    /// Received bytes this download threw away
+    ///
+    /// These are bytes that added nothing new to the file: the part of a
+    /// response past a chunk's end, a whole body re-sent by a server that
+    /// ignored the Range, and attempts discarded after a checksum mismatch.
+    pub wasted_bytes: u64,
+    /// How quickly each chunk request got going
+    ///
+    /// One entry per request, retries included, in the order they were
+    /// answered. Comparing `headers` with `first_byte` separates a server
+    /// that is slow to respond from a link that is slow to deliver.
+    pub timings: Vec<RequestTiming>,
+}
+
+impl DownloadOutcome {
+    fn new(kind: OutcomeKind, bytes_transferred: u64, total_size: u64) -> Self {
+        Self {
+            bytes_transferred,
+            total_size,
+            kind,
+            last_modified: None,
+            retries: 0,
+            wasted_bytes: 0,
+            timings: Vec::new(),
+        }
+    }
+
+    /// Returns the chunk request that was slowest to deliver its first byte
+    ///
+    /// A request that never delivered one counts by its headers time.
+    pub fn slowest_start(&self) -> Option<RequestTiming> {
+        self.timings
+            .iter()
+            .copied()
+            .max_by_key(|timing| timing.first_byte.unwrap_or(timing.headers))
+    }
+
+    /// Sets `last_modified`, from the HEAD probe
+    fn modified_at(mut self, last_modified: Option<SystemTime>) -> Self {
+        self.last_modified = last_modified;
+        self
+    }
+}
+
+/// What `download_resumable` would do for a URL and path, see `inspect`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadPlan {
+    /// URL after following redirects
+    pub final_url: String,
+    /// Remote file size, if the server reports it
+    pub total_size: Option<u64>,
+    /// Whether the server accepts byte ranges
+    pub supports_ranges: bool,
+    /// Whether the file would be split into parallel chunks
+    pub chunked: bool,
+    /// Chunk layout with resume progress (a single chunk when not split,
+    /// empty when the size is unknown)
+    pub chunks: Vec<Chunk>,
+    /// Bytes already on disk that would be kept
+    pub existing_bytes: u64,
+    /// Bytes left to transfer, if the size is known
+    pub bytes_remaining: Option<u64>,
+    /// Size the file occupies on disk once complete, if known
+    pub disk_usage: Option<u64>,
+    /// HTTP version the server answered the HEAD request over
+    pub http_version: reqwest::Version,
+    /// How long the HEAD request took to answer
+    ///
+    /// Includes DNS, connecting and TLS unless a pooled connection was
+    /// reused, so a slow first probe points at the link or the resolver.
+    pub head_latency: Duration,
+}
+
+impl DownloadPlan {
+    /// Returns true if an existing partial file would be continued
+    pub fn is_resume(&self) -> bool {
+        self.existing_bytes > 0 && self.bytes_remaining != Some(0)
+    }
+}
+
+/// What `preflight` found out about a reachable URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteInfo {
+    /// URL after following redirects
+    pub final_url: String,
+    /// Remote file size, if the server reports it
+    pub size: Option<u64>,
+    /// Whether the server accepts byte ranges
+    pub supports_ranges: bool,
+}
+
+impl RemoteInfo {
+    /// Returns true if an interrupted download of this URL could be resumed
+    pub fn is_resumable(&self) -> bool {
+        self.supports_ranges && self.size.is_some()
+    }
+}
+
+/// How long one chunk request took to get going, see `DownloadOutcome::timings`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestTiming {
+    /// Index of the chunk the request was for
+    pub chunk: u8,
+    /// From sending the request to having the response headers
+    ///
+    /// Covers DNS, connecting and TLS when a new connection was needed,
+    /// which reqwest doesn't report separately.
+    pub headers: Duration,
+    /// From sending the request to the first body byte, if one arrived
+    pub first_byte: Option<Duration>,
+}
+
+/// What a HEAD request reports about a URL
+struct HeadProbe {
+    final_url: String, // after following redirects
+    content_length: Option<u64>,
+    supports_ranges: bool,
+    http_version: reqwest::Version,
+    latency: Duration, // not counting the wait for a probe slot
+    last_modified: Option<SystemTime>, // None if missing or unparseable
 }
 
 /// Chunked downloader for multi-part downloads
+///
+/// Cloning is cheap: clones share the transport and the budgets for open
+/// files, probes and buffered bytes.
+#[derive(Clone)]
 pub struct ChunkedDownloader {
-    client: Client,
+    transport: Arc<dyn HttpTransport>,
     config: ChunkConfig,
+    open_files: Arc<Semaphore>, // chunk file budget, shared with chunk tasks
+    probes: Arc<Semaphore>, // HEAD request budget, see `max_concurrent_probes`
+    run: Arc<DownloadRun>, // state of the download in progress, see `start_run`
+    buffer_memory: Arc<Semaphore>, // buffered bytes budget, see `max_buffer_memory`
+    buffer_peak: Arc<AtomicU64>, // see `peak_buffered_bytes`
 }
 
 impl ChunkedDownloader {
     /// Creates a new chunked downloader with default config
     pub fn new() -> Self {
-        let client = Client::builder()
-            .user_agent("FluxDM/0.1.0")
-            .build()
-            .expect("failed to create HTTP client"); // temporary
-        
-        Self {
-            client,
-            config: ChunkConfig::default(),
-        }
+        Self::with_config(ChunkConfig::default())
     }
 
     /// Creates a new chunked downloader with custom config
     pub fn with_config(config: ChunkConfig) -> Self {
-        let client = Client::builder()
-            .user_agent("FluxDM/0.1.0")
-            .build()
-            .expect("failed to create HTTP client");
-        
-        Self { client, config }
+        Self::with_client_config(config, &ClientConfig::default())
+            .expect("failed to create HTTP client") // temporary
+    }
+
+    /// Creates a new chunked downloader with custom config and client settings
+    ///
+    /// Fails with `DownloadError::InvalidConfig` if the client settings are invalid.
+    pub fn with_client_config(
+        config: ChunkConfig,
+        client_config: &ClientConfig,
+    ) -> Result<Self, DownloadError> {
+        // keep every chunk's connection warm for retries and rebalancing
+        let client = client_config
+            .with_pool_for(config.chunk_count as usize)
+            .build()?;
+        let transport = ReqwestTransport::new(client, config.identity_encoding);
+
+        Ok(Self::with_transport(config, Arc::new(transport)))
+    }
+
+    /// Creates a chunked downloader sending its requests through `transport`
+    ///
+    /// Client settings and `identity_encoding` are up to the transport.
+    pub fn with_transport(config: ChunkConfig, transport: Arc<dyn HttpTransport>) -> Self {
+        let permits = |limit: Option<usize>| {
+            limit.map_or(Semaphore::MAX_PERMITS, |max| max.clamp(1, Semaphore::MAX_PERMITS))
+        };
+        let open_files = permits(config.max_open_files);
+        let probes = permits(config.max_concurrent_probes);
+        let buffer_memory = permits(config.max_buffer_memory);
+
+        Self {
+            transport,
+            config,
+            open_files: Arc::new(Semaphore::new(open_files)),
+            probes: Arc::new(Semaphore::new(probes)),
+            run: Arc::default(),
+            buffer_memory: Arc::new(Semaphore::new(buffer_memory)),
+            buffer_peak: Arc::default(),
+        }
+    }
+
+    /// Returns a copy of this downloader for a new download
+    ///
+    /// The copy shares the transport and budgets, but starts the state kept
+    /// for one download afresh, so downloads run with the same downloader,
+    /// one after another or at once, can't affect each other.
+    fn start_run(&self) -> Self {
+        Self {
+            run: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    /// Adds what the current run counted to `outcome`
+    fn finish_run(&self, mut outcome: DownloadOutcome) -> DownloadOutcome {
+        outcome.retries = self.run.retries.load(Ordering::Relaxed);
+        outcome.wasted_bytes = self.run.wasted.load(Ordering::Relaxed);
+        outcome.timings = lock_timings(&self.run.timings).clone();
+        outcome
+    }
+
+    /// Whether the current run reports how much can be read, see `download_watched`
+    fn tracks_available(&self) -> bool {
+        self.run.readable.is_some()
+    }
+
+    /// Records bytes `start..end` of the current download as written
+    fn mark_available(&self, start: u64, end: u64) {
+        if let Some(readable) = &self.run.readable {
+            let mut available = self.run.available.lock().unwrap_or_else(PoisonError::into_inner);
+            available.insert(start, end);
+            readable.send_replace(available.contiguous());
+        }
+    }
+
+    /// Starts tracking a new download from the progress in `chunks`
+    fn reset_available(&self, chunks: &[Chunk]) {
+        if let Some(readable) = &self.run.readable {
+            let mut available = self.run.available.lock().unwrap_or_else(PoisonError::into_inner);
+            available.reset(chunks);
+            readable.send_replace(available.contiguous());
+        }
+    }
+
+    /// Returns the most bytes that were waiting in write buffers at once
+    ///
+    /// Counted across all chunks and downloads of this downloader, and never
+    /// above `max_buffer_memory`. Stays 0 unless `write_buffer_chunks` is set.
+    pub fn peak_buffered_bytes(&self) -> u64 {
+        self.buffer_peak.load(Ordering::Relaxed)
+    }
+
+    /// Waits until `len` more buffered bytes fit under `max_buffer_memory`
+    ///
+    /// The bytes count as buffered until the returned permit is dropped. A
+    /// buffer larger than the whole budget waits for all of it.
+    async fn reserve_buffer(&self, len: usize) -> Option<OwnedSemaphorePermit> {
+        let total = self
+            .config
+            .max_buffer_memory
+            .map_or(Semaphore::MAX_PERMITS, |max| max.clamp(1, Semaphore::MAX_PERMITS));
+        let wanted = len.clamp(1, total.min(u32::MAX as usize)) as u32;
+        let permit = self.buffer_memory.clone().acquire_many_owned(wanted).await.ok()?;
+
+        let in_use = total - self.buffer_memory.available_permits();
+        self.buffer_peak.fetch_max(in_use as u64, Ordering::Relaxed);
+        Some(permit)
+    }
+
+    /// Adds to the current download's `DownloadOutcome::wasted_bytes`
+    fn add_wasted(&self, bytes: u64) {
+        if bytes > 0 {
+            debug!(bytes, "received bytes discarded");
+            self.run.wasted.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Sends a HEAD request once a slot under `max_concurrent_probes` is free
+    async fn head(&self, url: &str) -> Result<TransportResponse, DownloadError> {
+        Ok(self.head_timed(url).await?.0)
+    }
+
+    /// Like `head`, also returning how long the server took to answer
+    async fn head_timed(&self, url: &str) -> Result<(TransportResponse, Duration), DownloadError> {
+        let _permit = self.probes.acquire().await;
+        let sent = Instant::now();
+        let response = self.transport.head(url).await?;
+        Ok((response, sent.elapsed()))
     }
 
     /// Checks if the server supports Range requests
     pub async fn supports_ranges(&self, url: &str) -> Result<bool, DownloadError> {
-        let response = self.client
-            .head(url)
-            .send()
-            .await
-            .map_err(|e| DownloadError::NetworkError(e.to_string()))?;
+        let response = self.head(url).await?;
 
         if !response.status().is_success() {
             return Err(DownloadError::HttpError(response.status().as_u16()));
@@ -123,19 +808,37 @@ impl ChunkedDownloader {
 
     /// Gets the content length and whether ranges are supported
     pub async fn get_file_info(&self, url: &str) -> Result<(u64, bool), DownloadError> {
-        let response = self.client
-            .head(url)
-            .send()
-            .await
-            .map_err(|e| DownloadError::NetworkError(e.to_string()))?;
+        let (content_length, supports_ranges) = self.probe(url).await?;
+
+        let content_length = content_length
+            .ok_or_else(|| DownloadError::InvalidUrl("No content length".to_string()))?;
+
+        Ok((content_length, supports_ranges))
+    }
+
+    /// Like `get_file_info`, but a missing content length isn't an error
+    ///
+    /// Servers using chunked transfer encoding often don't send one.
+    async fn probe(&self, url: &str) -> Result<(Option<u64>, bool), DownloadError> {
+        let head = self.probe_head(url).await?;
+
+        Ok((head.content_length, head.supports_ranges))
+    }
+
+    /// Sends a HEAD request, see `HeadProbe`
+    async fn probe_head(&self, url: &str) -> Result<HeadProbe, DownloadError> {
+        let (response, latency) = self.head_timed(url).await?;
 
         if !response.status().is_success() {
             return Err(DownloadError::HttpError(response.status().as_u16()));
         }
 
+        // read the header directly, a HEAD response has no body to size
         let content_length = response
-            .content_length()
-            .ok_or_else(|| DownloadError::InvalidUrl("No content length".to_string()))?;
+            .headers()
+            .get("content-length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
 
         let supports_ranges = response
             .headers()
@@ -144,7 +847,136 @@ impl ChunkedDownloader {
             .map(|v| v == "bytes")
             .unwrap_or(false);
 
-        Ok((content_length, supports_ranges))
+        let last_modified = response
+            .headers()
+            .get("last-modified")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| httpdate::parse_http_date(v).ok());
+
+        Ok(HeadProbe {
+            final_url: response.url().to_string(),
+            content_length,
+            supports_ranges,
+            http_version: response.version(),
+            latency,
+            last_modified,
+        })
+    }
+
+    /// Asks for the first byte with a ranged GET, returning the file size if
+    /// the server answers with a 206 that reports it
+    ///
+    /// A second opinion for when a HEAD said ranges aren't supported.
+    async fn probe_range(&self, url: &str) -> Option<u64> {
+        let _permit = self.probes.acquire().await;
+        let response = self.transport.get_range(url, 0, Some(0)).await.ok()?;
+
+        if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            return None;
+        }
+        content_range_total(&response)
+    }
+
+    /// Estimates a good chunk count by sampling the server at 1, 2 and 4 connections
+    ///
+    /// Each round fetches at most `PROBE_SAMPLE_BYTES` per connection and is
+    /// capped at `PROBE_TIMEOUT`, so the probe never turns into a real
+    /// download. Stops at the first round that doesn't improve throughput by
+    /// at least 20%. Returns 1 if the server can't do ranges, and the
+    /// configured `chunk_count` if sampling fails partway. Errors only if the
+    /// initial HEAD request fails.
+    pub async fn probe_optimal_chunks(&self, url: &str) -> Result<u8, DownloadError> {
+        let (file_size, supports_ranges) = self.probe(url).await?;
+
+        let file_size = match file_size {
+            Some(size) if supports_ranges && size > 0 => size,
+            _ => return Ok(1), // can't split anyway
+        };
+
+        let mut best = 1u8;
+        let mut best_speed = match self.measure_throughput(url, file_size, 1).await {
+            Ok(speed) => speed,
+            Err(e) => {
+                debug!(error = %e, "speed probe failed, using default");
+                return Ok(self.config.chunk_count);
+            }
+        };
+
+        for connections in [2u8, 4] {
+            match self.measure_throughput(url, file_size, connections).await {
+                Ok(speed) if speed > best_speed * PROBE_MIN_GAIN => {
+                    best = connections;
+                    best_speed = speed;
+                }
+                Ok(_) => break, // more connections stopped helping
+                Err(e) => {
+                    debug!(error = %e, "speed probe failed, using default");
+                    return Ok(self.config.chunk_count);
+                }
+            }
+        }
+
+        debug!(chunks = best, bytes_per_sec = best_speed, "speed probe finished");
+
+        Ok(best)
+    }
+
+    /// Measures aggregate bytes per second over `connections` parallel samples
+    async fn measure_throughput(
+        &self,
+        url: &str,
+        file_size: u64,
+        connections: u8,
+    ) -> Result<f64, DownloadError> {
+        let sample = (file_size / connections as u64).min(PROBE_SAMPLE_BYTES);
+
+        if sample == 0 {
+            return Err(DownloadError::InvalidUrl("File too small to probe".to_string()));
+        }
+
+        let started = Instant::now();
+        let samples = (0..connections as u64).map(|i| self.fetch_sample(url, i * sample, sample));
+
+        let results = tokio::time::timeout(PROBE_TIMEOUT, futures_util::future::join_all(samples))
+            .await
+            .map_err(|_| DownloadError::Timeout("Speed probe timed out".to_string()))?;
+
+        let mut total = 0u64;
+        for result in results {
+            total += result?;
+        }
+
+        let elapsed = started.elapsed().as_secs_f64().max(f64::EPSILON);
+
+        Ok(total as f64 / elapsed)
+    }
+
+    /// Reads and discards `len` bytes starting at `start`, returning bytes read
+    async fn fetch_sample(&self, url: &str, start: u64, len: u64) -> Result<u64, DownloadError> {
+        let response = self
+            .transport
+            .get_range(url, start, Some(start + len - 1))
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(DownloadError::HttpError(response.status().as_u16()));
+        }
+
+        let mut read = 0u64;
+        let mut stream = response.bytes_stream();
+
+        use futures_util::StreamExt;
+
+        while let Some(data) = stream.next().await {
+            read += data?.len() as u64;
+
+            // server may ignore the range, don't read the whole file
+            if read >= len {
+                break;
+            }
+        }
+
+        Ok(read)
     }
 
     /// Calculates optimal chunks for a file
@@ -159,33 +991,136 @@ impl ChunkedDownloader {
             }];
         }
 
-        // calculate chunk size
-        let chunk_size = file_size / self.config.chunk_count as u64;
+        // calculate chunk size, a count of 0 still needs one chunk
+        let count = (self.config.chunk_count as u64).max(1);
+        let chunk_size = file_size / count;
         
-        let mut chunks = Vec::new();
+        let mut chunks: Vec<Chunk> = Vec::new();
         let mut start = 0u64;
 
-        for i in 0..self.config.chunk_count {
-            let end = if i == self.config.chunk_count - 1 {
-                file_size - 1 // last chunk gets remainder
+        for i in 1..=count {
+            let boundary = if i == count {
+                file_size // last chunk gets remainder
             } else {
-                start + chunk_size - 1
+                self.align_boundary(i * chunk_size).min(file_size)
             };
 
+            // rounding can pull two boundaries together, skip the empty chunk
+            if boundary <= start {
+                continue;
+            }
+
             chunks.push(Chunk {
-                index: i,
+                index: chunks.len() as u8,
                 start,
-                end,
+                end: boundary - 1,
                 downloaded: 0,
             });
 
-            start = end + 1;
+            start = boundary;
+            if start == file_size {
+                break;
+            }
         }
 
         chunks
     }
 
+    /// Rounds `offset` to the nearest multiple of `chunk_alignment`, if set
+    fn align_boundary(&self, offset: u64) -> u64 {
+        match self.config.chunk_alignment {
+            Some(alignment) if alignment > 1 => {
+                (offset + alignment / 2) / alignment * alignment
+            }
+            _ => offset,
+        }
+    }
+
+    /// Re-plans the bytes still missing from `chunks` over `new_count` chunks
+    ///
+    /// Downloaded bytes stay where they are: each old chunk keeps its
+    /// finished part under its own index, so segment files stay valid. The
+    /// missing ranges, which are scattered after parallel progress, are
+    /// shared out into roughly equal chunks with new indices. A chunk never
+    /// spans two ranges, so there's at least one per range, and none is made
+    /// smaller than `min_chunk_size`. The plan is returned unchanged if the
+    /// chunk indices would run out.
+    pub fn replan_chunks(&self, chunks: &[Chunk], new_count: u8) -> Vec<Chunk> {
+        let mut replanned: Vec<Chunk> = Vec::new();
+        let mut missing = Vec::new();
+
+        for chunk in chunks {
+            if chunk.downloaded > 0 {
+                replanned.push(Chunk {
+                    end: chunk.resume_position().min(chunk.end + 1) - 1,
+                    ..*chunk
+                });
+            }
+            if !chunk.is_complete() {
+                missing.push((chunk.resume_position(), chunk.end));
+            }
+        }
+
+        // a chunk's missing tail often runs straight into the next chunk
+        missing.sort_unstable();
+        missing.dedup_by(|next, prev| {
+            let adjacent = prev.1 + 1 == next.0;
+            if adjacent {
+                prev.1 = next.1;
+            }
+            adjacent
+        });
+
+        // each range gets one chunk, the rest go wherever chunks are largest
+        let len = |(start, end): (u64, u64)| end - start + 1;
+        let min_size = self.config.min_chunk_size.max(1);
+        let mut counts = vec![1u64; missing.len()];
+
+        for _ in missing.len()..new_count as usize {
+            let largest = (0..missing.len())
+                .filter(|&i| len(missing[i]) / (counts[i] + 1) >= min_size)
+                .max_by_key(|&i| len(missing[i]) / counts[i]);
+
+            match largest {
+                Some(i) => counts[i] += 1,
+                None => break,
+            }
+        }
+
+        let first_index = chunks.iter().map(|c| c.index as u64 + 1).max().unwrap_or(0);
+        if first_index + counts.iter().sum::<u64>() > u8::MAX as u64 + 1 {
+            return chunks.to_vec();
+        }
+
+        let mut index = first_index;
+        for (&(start, end), &count) in missing.iter().zip(&counts) {
+            let size = len((start, end)) / count;
+            let mut piece_start = start;
+
+            for i in 0..count {
+                // the last piece takes the remainder
+                let piece_end = if i == count - 1 { end } else { piece_start + size - 1 };
+
+                replanned.push(Chunk {
+                    index: index as u8,
+                    start: piece_start,
+                    end: piece_end,
+                    downloaded: 0,
+                });
+
+                index += 1;
+                piece_start = piece_end + 1;
+            }
+        }
+
+        replanned.sort_by_key(|c| c.start);
+        replanned
+    }
+
     /// Detects if a partial file exists and updates chunks with already-downloaded bytes
+    ///
+    /// Progress recorded by an earlier chunked download wins over the file's
+    /// length, which it set in full before writing anything.
     pub async fn detect_resume(
         &self,
         path: &Path,
@@ -200,6 +1135,22 @@ impl ChunkedDownloader {
             Err(_) => return Ok(chunks), // no file exists, start fresh
         };
 
+        // a chunked download records its progress, as the file's length says
+        // nothing about which bytes were written
+        if let Some(recorded) = load_layout(path).await {
+            if is_fully_downloaded(&recorded, file_size) {
+                debug!("recorded chunks all finished");
+                return Ok(recorded);
+            }
+            if covers_exactly(&recorded, file_size) {
+                debug!("resuming from recorded chunk progress");
+                return Ok(recorded);
+            }
+
+            warn!("recorded chunk progress doesn't fit the file, starting over");
+            return Ok(chunks);
+        }
+
         let existing_size = metadata.len();
 
         // if file is already the correct size, all chunks are done
@@ -234,27 +1185,162 @@ impl ChunkedDownloader {
         Ok(chunks)
     }
 
-    /// Downloads a single chunk with retry logic and exponential backoff
-    async fn download_chunk_with_retry(
+    /// Works out resume progress for the configured segment mode
+    async fn resume_chunks(
         &self,
-        url: &str,
-        chunk: Chunk,
-        file: &mut File,
-    ) -> Result<u64, DownloadError> {
-        let mut attempt = 0;
-        let mut last_error;
+        path: &Path,
+        file_size: u64,
+    ) -> Result<Vec<Chunk>, DownloadError> {
+        match self.config.segment_mode {
+            SegmentMode::SingleFile => self.detect_resume(path, file_size).await,
+            SegmentMode::SeparateFiles => Ok(self.detect_segments(path, file_size).await),
+        }
+    }
 
-        loop {
-            match self.download_chunk(url, chunk, file).await {
-                Ok(bytes) => return Ok(bytes),
-                Err(e) => {
-                    last_error = e;
-                    attempt += 1;
+    /// Like `detect_resume`, but reads progress from each chunk's segment file
+    ///
+    /// If no segments exist but the destination already has the full size,
+    /// the download was merged and every chunk counts as complete.
+    async fn detect_segments(&self, path: &Path, file_size: u64) -> Vec<Chunk> {
+        let mut chunks = self.calculate_chunks(file_size);
+        let mut found_segment = false;
 
-                    // check if we've exhausted retries
-                    if attempt > self.config.max_retries {
-                        break;
-                    }
+        for chunk in &mut chunks {
+            if let Ok(meta) = tokio::fs::metadata(segment_path(path, chunk.index)).await {
+                chunk.downloaded = meta.len().min(chunk.size());
+                found_segment = true;
+            }
+        }
+
+        if !found_segment {
+            let merged = tokio::fs::metadata(path).await.map(|m| m.len()).unwrap_or(0);
+            if merged == file_size {
+                for chunk in &mut chunks {
+                    chunk.downloaded = chunk.size();
+                }
+            }
+        }
+
+        chunks
+    }
+
+    /// Compares the last `overlap` bytes before each resume point with the server's
+    ///
+    /// A chunk whose local bytes differ is reset to start over, as nothing
+    /// it holds can be trusted. Chunks the server answers without a range
+    /// can't be checked and are kept.
+    async fn check_resume_seams(
+        &self,
+        url: &str,
+        path: &Path,
+        chunks: &mut [Chunk],
+        overlap: u64,
+    ) -> Result<(), DownloadError> {
+        use tokio::io::AsyncReadExt;
+
+        for chunk in chunks.iter_mut().filter(|c| c.downloaded > 0 && !c.is_complete()) {
+            let seam = chunk.resume_position();
+            let from = seam - overlap.min(chunk.downloaded);
+
+            let response = match self.transport.get_range(url, from, Some(seam - 1)).await {
+                Ok(response) => response,
+                Err(error) => {
+                    warn!(index = chunk.index, error = %error, "can't check resume point");
+                    continue;
+                }
+            };
+
+            if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+                warn!(
+                    index = chunk.index,
+                    status = response.status().as_u16(),
+                    "can't check resume point, range not honoured"
+                );
+                continue;
+            }
+
+            let remote = match response.bytes().await {
+                Ok(remote) => remote,
+                Err(error) => {
+                    warn!(index = chunk.index, error = %error, "can't check resume point");
+                    continue;
+                }
+            };
+
+            let (local_path, offset) = match self.config.segment_mode {
+                SegmentMode::SingleFile => (path.to_path_buf(), from),
+                SegmentMode::SeparateFiles => (segment_path(path, chunk.index), from - chunk.start),
+            };
+
+            let mut local = vec![0u8; (seam - from) as usize];
+            let mut file = File::open(&local_path)
+                .await
+                .map_err(|e| DownloadError::FileError(e.to_string()))?;
+            file.seek(std::io::SeekFrom::Start(offset))
+                .await
+                .map_err(|e| DownloadError::FileError(e.to_string()))?;
+            file.read_exact(&mut local)
+                .await
+                .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+            if remote[..] != local[..] {
+                warn!(index = chunk.index, seam, "partial data doesn't match, refetching chunk");
+                chunk.downloaded = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Downloads a single chunk with retry logic and exponential backoff
+    #[instrument(name = "chunk", skip(self, url, plan, file), fields(index = slot))]
+    async fn download_chunk_with_retry(
+        &self,
+        url: &str,
+        plan: &ChunkPlan,
+        slot: usize,
+        file: &mut File,
+    ) -> Result<u64, DownloadError> {
+        let mut attempt = 0;
+        let mut last_error;
+        let chunk = lock_plan(plan)[slot];
+        let already_downloaded = chunk.downloaded;
+
+        debug!(start = chunk.start, end = chunk.end, "chunk started");
+
+        loop {
+            match self.download_chunk_timed(url, plan, slot, file).await {
+                Ok(_) => {
+                    // count bytes from every attempt, not just the last one
+                    let bytes = lock_plan(plan)[slot].downloaded - already_downloaded;
+                    debug!(bytes, "chunk complete");
+                    return Ok(bytes);
+                }
+                Err(e) => {
+                    last_error = e;
+                    attempt += 1;
+
+                    // a 404 or a full disk won't fix itself, so don't wait on it
+                    if !self.config.retry_statuses.should_retry(&last_error) {
+                        warn!(error = %last_error, "chunk failed, not retryable");
+                        break;
+                    }
+
+                    if let Err(e) = self.record_failure() {
+                        warn!(error = %e, "chunk failing too often, giving up");
+                        last_error = e;
+                        break;
+                    }
+
+                    // check if we've exhausted retries
+                    if attempt > self.config.max_retries {
+                        warn!(
+                            attempts = attempt,
+                            error = %last_error,
+                            "chunk failed, retries exhausted"
+                        );
+                        break;
+                    }
 
                     // calculate backoff delay
                     let delay = if self.config.exponential_backoff {
@@ -265,6 +1351,15 @@ impl ChunkedDownloader {
                         self.config.retry_delay_ms
                     };
 
+                    self.run.retries.fetch_add(1, Ordering::Relaxed);
+
+                    warn!(
+                        attempt,
+                        delay_ms = delay,
+                        error = %last_error,
+                        "chunk failed, retrying"
+                    );
+
                     // wait before retrying
                     sleep(Duration::from_millis(delay)).await;
                 }
@@ -275,14 +1370,63 @@ impl ChunkedDownloader {
         Err(last_error)
     }
 
+    /// Notes a failed chunk attempt, returning an error once `failure_limit` is crossed
+    fn record_failure(&self) -> Result<(), DownloadError> {
+        let Some(limit) = self.config.failure_limit else {
+            return Ok(());
+        };
+        let window_ms = self.config.failure_window_ms;
+        let window = Duration::from_millis(window_ms);
+        let now = Instant::now();
+
+        let mut failures = self.run.failures.lock().unwrap_or_else(PoisonError::into_inner);
+        while failures.front().is_some_and(|&at| now.duration_since(at) > window) {
+            failures.pop_front();
+        }
+        failures.push_back(now);
+
+        let count = failures.len() as u32;
+        if count > limit {
+            return Err(DownloadError::TooManyFailures { failures: count, window_ms });
+        }
+        Ok(())
+    }
+
+    /// Runs one chunk attempt, bounded by the per-chunk timeout if set
+    async fn download_chunk_timed(
+        &self,
+        url: &str,
+        plan: &ChunkPlan,
+        slot: usize,
+        file: &mut File,
+    ) -> Result<u64, DownloadError> {
+        let Some(timeout_ms) = self.config.per_chunk_timeout_ms else {
+            return self.download_chunk(url, plan, slot, file).await;
+        };
+
+        // progress written before the deadline stays recorded in the plan
+        tokio::time::timeout(
+            Duration::from_millis(timeout_ms),
+            self.download_chunk(url, plan, slot, file),
+        )
+        .await
+        .map_err(|_| DownloadError::Timeout(format!("Chunk timed out after {}ms", timeout_ms)))?
+    }
+
     /// Downloads a single chunk and writes it to the file at the correct position
-    /// Supports resuming from chunk.downloaded bytes
+    /// Supports resuming from chunk.downloaded bytes, and advances it as data is written
+    ///
+    /// The chunk's end may shrink while this runs if another connection takes
+    /// over its tail; writing stops at the current end.
     async fn download_chunk(
         &self,
         url: &str,
-        chunk: Chunk,
+        plan: &ChunkPlan,
+        slot: usize,
         file: &mut File,
     ) -> Result<u64, DownloadError> {
+        let chunk = lock_plan(plan)[slot];
+
         // skip if chunk is already complete
         if chunk.is_complete() {
             return Ok(0);
@@ -291,241 +1435,1538 @@ impl ChunkedDownloader {
         // calculate range to download (resume from where we left off)
         let start_byte = chunk.resume_position();
         let end_byte = chunk.end;
-        let range_header = format!("bytes={}-{}", start_byte, end_byte);
 
-        let response = self.client
-            .get(url)
-            .header("Range", range_header)
-            .send()
-            .await
-            .map_err(|e| DownloadError::NetworkError(e.to_string()))?;
+        let sent = Instant::now();
+        let response = self
+            .transport
+            .get_range(url, start_byte, Some(end_byte))
+            .await?;
+        let timing = self.record_timing(chunk.index, sent.elapsed());
+
+        debug!(
+            status = response.status().as_u16(),
+            range_start = start_byte,
+            "range response"
+        );
 
         // check for 206 Partial Content or 200 OK (some servers)
         if !response.status().is_success() && response.status().as_u16() != 206 {
             return Err(DownloadError::HttpError(response.status().as_u16()));
         }
 
+        // a different total means the file was replaced since the download started,
+        // and its bytes must not be mixed with the ones already written
+        if let Some(total) = content_range_total(&response) {
+            let expected = plan_size(plan);
+            if total != expected {
+                return Err(DownloadError::ResourceChanged {
+                    expected,
+                    actual: total,
+                });
+            }
+        }
+
+        // segment files start at the chunk's first byte
+        let file_offset = match self.config.segment_mode {
+            SegmentMode::SingleFile => 0,
+            SegmentMode::SeparateFiles => chunk.start,
+        };
+
         // seek to resume position in file
-        file.seek(std::io::SeekFrom::Start(start_byte))
+        file.seek(std::io::SeekFrom::Start(start_byte - file_offset))
             .await
             .map_err(|e| DownloadError::FileError(e.to_string()))?;
 
-        // stream chunk to file
-        let mut bytes_written = 0u64;
-        let mut stream = response.bytes_stream();
+        let ignored_range = response.status() == reqwest::StatusCode::OK && start_byte > 0;
+        let mut body = self.time_first_byte(response.bytes_stream(), sent, timing);
+
+        if ignored_range {
+            // the body starts at byte 0, so drop what comes before ours
+            use futures_util::StreamExt;
+
+            debug!(skip = start_byte, "range ignored, skipping to chunk");
+            let run = self.run.clone();
+            let mut skip = start_byte;
+            body = body
+                .map(move |buffer| {
+                    let buffer = buffer?;
+                    let skipped = skip.min(buffer.len() as u64);
+                    skip -= skipped;
+                    run.wasted.fetch_add(skipped, Ordering::Relaxed);
+                    Ok(buffer.slice(skipped as usize..))
+                })
+                .boxed();
+        }
 
+        let bytes_written = if self.config.write_buffer_chunks == 0 {
+            self.write_chunk_body(body, plan, slot, start_byte, file).await?
+        } else {
+            // the network may run up to `write_buffer_chunks` buffers ahead
+            // of the disk, bounding memory when the disk is the slower side
+            let (sender, receiver) = mpsc::channel(self.config.write_buffer_chunks);
+
+            let forward = async move {
+                use futures_util::StreamExt;
+
+                let mut body = body;
+                loop {
+                    tokio::select! {
+                        item = body.next() => {
+                            let Some(item) = item else { break };
+                            // held until the writer is done with the buffer
+                            let item = match item {
+                                Ok(bytes) => {
+                                    let permit = self.reserve_buffer(bytes.len()).await;
+                                    Ok(Buffered { bytes, _permit: permit })
+                                }
+                                Err(e) => Err(e),
+                            };
+                            if sender.send(item).await.is_err() {
+                                break;
+                            }
+                        }
+                        // the writer stopped early, e.g. at a moved end
+                        _ = sender.closed() => break,
+                    }
+                }
+            };
+
+            let buffered = futures_util::stream::unfold(receiver, |mut receiver| async move {
+                receiver.recv().await.map(|item| (item, receiver))
+            });
+
+            let (written, ()) = tokio::join!(
+                self.write_chunk_body(buffered, plan, slot, start_byte, file),
+                forward
+            );
+            written?
+        };
+
+        if !lock_plan(plan)[slot].is_complete() {
+            return Err(DownloadError::NetworkError(
+                "Connection closed before chunk finished".to_string(),
+            ));
+        }
+
+        Ok(bytes_written)
+    }
+
+    /// Records a chunk request's response time, returning its place in the run's timings
+    fn record_timing(&self, chunk: u8, headers: Duration) -> usize {
+        let mut timings = lock_timings(&self.run.timings);
+        timings.push(RequestTiming {
+            chunk,
+            headers,
+            first_byte: None,
+        });
+        timings.len() - 1
+    }
+
+    /// Passes `body` through, noting when its first byte arrives in timing `slot`
+    fn time_first_byte(&self, body: BodyStream, sent: Instant, slot: usize) -> BodyStream {
         use futures_util::StreamExt;
 
-        while let Some(chunk_data) = stream.next().await {
-            let chunk_data = chunk_data.map_err(|e| DownloadError::NetworkError(e.to_string()))?;
-            
-            file.write_all(&chunk_data)
+        let run = self.run.clone();
+        let mut waiting = true;
+
+        body.map(move |buffer| {
+            if waiting && buffer.as_ref().is_ok_and(|b| !b.is_empty()) {
+                waiting = false;
+                let mut timings = lock_timings(&run.timings);
+                if let Some(timing) = timings.get_mut(slot) {
+                    timing.first_byte = Some(sent.elapsed());
+                }
+            }
+            buffer
+        })
+        .boxed()
+    }
+
+    /// Writes a chunk's response body to `file`, starting at `start_byte`
+    ///
+    /// Bytes are claimed in the plan before they are written and writing
+    /// stops at the chunk's current end. Returns the number of bytes written.
+    async fn write_chunk_body<S, B>(
+        &self,
+        body: S,
+        plan: &ChunkPlan,
+        slot: usize,
+        start_byte: u64,
+        file: &mut File,
+    ) -> Result<u64, DownloadError>
+    where
+        S: futures_util::Stream<Item = Result<B, DownloadError>>,
+        B: AsRef<[u8]>,
+    {
+        let mut body = std::pin::pin!(body);
+        let mut bytes_written = 0u64;
+        let mut position = start_byte;
+        let mut unflushed = 0u64;
+
+        while let Some(chunk_data) =
+            next_with_stall_timeout(&mut body, self.config.stall_timeout_ms).await?
+        {
+            let chunk_data = chunk_data?;
+            let chunk_data = chunk_data.as_ref();
+
+            // claim the bytes that still belong to this chunk before writing,
+            // so a concurrent split never hands them to another connection
+            let take = {
+                let mut chunks = lock_plan(plan);
+                let chunk = &mut chunks[slot];
+                let take = (chunk.end + 1)
+                    .saturating_sub(position)
+                    .min(chunk_data.len() as u64);
+                chunk.downloaded += take;
+                take
+            };
+
+            if let Err(e) = file.write_all(&chunk_data[..take as usize]).await {
+                lock_plan(plan)[slot].downloaded -= take;
+                return Err(DownloadError::FileError(e.to_string()));
+            }
+
+            self.add_wasted(chunk_data.len() as u64 - take);
+            bytes_written += take;
+            position += take;
+            unflushed += take;
+            self.flush_if_due(file, &mut unflushed, false).await?;
+
+            if self.tracks_available() {
+                file.flush()
+                    .await
+                    .map_err(|e| DownloadError::FileError(e.to_string()))?;
+                self.mark_available(position - take, position);
+            }
+
+            // reached the end, which may have moved since the request
+            if take < chunk_data.len() as u64 {
+                break;
+            }
+        }
+
+        self.flush_if_due(file, &mut unflushed, true).await?;
+
+        Ok(bytes_written)
+    }
+
+    /// Flushes `file` once `unflushed` bytes reach `flush_interval_bytes`
+    ///
+    /// With `last` any remainder is flushed too. Does nothing unless an
+    /// interval is configured.
+    async fn flush_if_due(
+        &self,
+        file: &mut File,
+        unflushed: &mut u64,
+        last: bool,
+    ) -> Result<(), DownloadError> {
+        let Some(interval) = self.config.flush_interval_bytes else {
+            return Ok(());
+        };
+        if *unflushed == 0 || (*unflushed < interval && !last) {
+            return Ok(());
+        }
+
+        file.flush()
+            .await
+            .map_err(|e| DownloadError::FileError(e.to_string()))?;
+        if self.config.sync_on_flush {
+            file.sync_data()
                 .await
                 .map_err(|e| DownloadError::FileError(e.to_string()))?;
-            
-            bytes_written += chunk_data.len() as u64;
         }
 
-        Ok(bytes_written)
+        *unflushed = 0;
+        Ok(())
+    }
+
+    /// Downloads every incomplete chunk into `path` in parallel
+    ///
+    /// When a connection finishes early it takes over the back half of the
+    /// chunk with the most bytes left, so fast connections don't sit idle
+    /// while a slow one drags on.
+    async fn download_chunks(
+        &self,
+        url: &str,
+        path: &Path,
+        chunks: Vec<Chunk>,
+    ) -> Result<u64, DownloadError> {
+        let slots: Vec<usize> = chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !c.is_complete())
+            .map(|(slot, _)| slot)
+            .collect();
+
+        self.reset_available(&chunks);
+
+        // a single file has its full length from the start, so record which
+        // parts are really there; unfinished chunks keep their starting point
+        let recorded: BTreeMap<u8, u64> = chunks.iter().map(|c| (c.index, c.downloaded)).collect();
+        let record_progress = self.config.segment_mode == SegmentMode::SingleFile;
+        if record_progress {
+            save_layout(path, &chunks).await?;
+        }
+
+        let plan: ChunkPlan = Arc::new(Mutex::new(chunks));
+        let mut tasks = JoinSet::new();
+
+        for slot in slots {
+            self.spawn_chunk(&mut tasks, url, path, &plan, slot);
+        }
+
+        // wait for all chunks to complete, dropping the set aborts the rest on error
+        let mut total_bytes = 0u64;
+
+        while let Some(result) = tasks.join_next().await {
+            let bytes = result
+                .map_err(|e| DownloadError::NetworkError(format!("Task failed: {}", e)))??;
+
+            total_bytes += bytes;
+
+            // put the free connection to work on the biggest leftover range
+            if self.config.rebalance_chunks {
+                if let Some(slot) = self.rebalance(&plan) {
+                    self.spawn_chunk(&mut tasks, url, path, &plan, slot);
+                }
+            }
+
+            if record_progress {
+                let mut progress = lock_plan(&plan).clone();
+                for chunk in progress.iter_mut().filter(|c| !c.is_complete()) {
+                    let started_at = recorded.get(&chunk.index).copied().unwrap_or(0);
+                    chunk.downloaded = chunk.downloaded.min(started_at);
+                }
+                save_layout(path, &progress).await?;
+            }
+        }
+
+        if self.config.segment_mode == SegmentMode::SeparateFiles {
+            let chunks = lock_plan(&plan).clone();
+            merge_segments(path, &chunks).await?;
+        } else {
+            let _ = tokio::fs::remove_file(layout_path(path)).await;
+        }
+
+        Ok(total_bytes)
+    }
+
+    /// Spawns a task downloading the chunk at `slot` of the plan
+    fn spawn_chunk(
+        &self,
+        tasks: &mut JoinSet<Result<u64, DownloadError>>,
+        url: &str,
+        path: &Path,
+        plan: &ChunkPlan,
+        slot: usize,
+    ) {
+        let url = url.to_string();
+        let path = path.to_path_buf();
+        let plan = plan.clone();
+        let downloader = self.clone();
+
+        let chunk_task = async move {
+            // hold a file slot until the chunk's file is closed
+            let _permit = downloader.open_files.clone().acquire_owned().await;
+
+            let mut file = downloader.open_chunk_file(&path, &plan, slot).await?;
+
+            downloader.download_chunk_with_retry(&url, &plan, slot, &mut file).await
+        };
+
+        // keep chunk spans under this download's span
+        tasks.spawn(chunk_task.in_current_span());
+    }
+
+    /// Opens the file the chunk at `slot` writes into
+    async fn open_chunk_file(
+        &self,
+        path: &Path,
+        plan: &ChunkPlan,
+        slot: usize,
+    ) -> Result<File, DownloadError> {
+        let file = match self.config.segment_mode {
+            SegmentMode::SingleFile => File::options().write(true).open(path).await,
+            SegmentMode::SeparateFiles => {
+                let chunk = lock_plan(plan)[slot];
+                let file = File::options()
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(segment_path(path, chunk.index))
+                    .await
+                    .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+                // drop anything past the recorded progress, e.g. a stale segment
+                file.set_len(chunk.downloaded).await.map(|_| file)
+            }
+        };
+
+        file.map_err(|e| DownloadError::FileError(e.to_string()))
+    }
+
+    /// Splits the chunk with the most bytes left, returning the new chunk's slot
+    fn rebalance(&self, plan: &ChunkPlan) -> Option<usize> {
+        let mut chunks = lock_plan(plan);
+        // a re-planned layout may have gaps in its indices, so take the next unused one
+        let next_index = chunks.iter().map(|c| c.index as usize + 1).max().unwrap_or(0);
+        let new_index = u8::try_from(next_index).ok()?;
+
+        let slowest = chunks
+            .iter_mut()
+            .filter(|c| !c.is_complete())
+            .max_by_key(|c| c.remaining())?;
+        let stolen = slowest.split_off(new_index, self.config.min_chunk_size)?;
+
+        debug!(
+            from = slowest.index,
+            start = stolen.start,
+            end = stolen.end,
+            "rebalanced chunk"
+        );
+
+        chunks.push(stolen);
+        Some(chunks.len() - 1)
     }
 
     /// Downloads a file using multiple parallel chunks
+    ///
+    /// Returns the bytes downloaded, see `download_outcome` for more detail.
     pub async fn download(
         &self,
         url: &str,
         path: &Path,
     ) -> Result<u64, DownloadError> {
+        Ok(self.download_outcome(url, path).await?.bytes_transferred)
+    }
+
+    /// Downloads a file using multiple parallel chunks, from the first byte
+    ///
+    /// With `temp_dir` set, the file is downloaded there and only moved to
+    /// `path` once complete.
+    pub async fn download_outcome(
+        &self,
+        url: &str,
+        path: &Path,
+    ) -> Result<DownloadOutcome, DownloadError> {
+        self.start_run().run_outcome(url, path).await
+    }
+
+    /// Like `download_outcome`, also reporting how much can be read in order as it arrives
+    ///
+    /// The receiver holds how many bytes from the start of the file have
+    /// been written, which only grows as the leading chunks complete. A
+    /// consumer can process the download as it arrives, e.g. play media, by
+    /// reading up to there. Each buffer is flushed so the reported bytes are
+    /// visible to other readers. Nothing is fetched until the returned
+    /// future is awaited.
+    pub fn download_watched<'a>(
+        &'a self,
+        url: &'a str,
+        path: &'a Path,
+    ) -> (
+        watch::Receiver<u64>,
+        impl std::future::Future<Output = Result<DownloadOutcome, DownloadError>> + 'a,
+    ) {
+        let (readable, available) = watch::channel(0);
+        let run = Self {
+            run: Arc::new(DownloadRun {
+                readable: Some(readable),
+                ..DownloadRun::default()
+            }),
+            ..self.clone()
+        };
+
+        (available, async move { run.run_outcome(url, path).await })
+    }
+
+    /// Downloads `url` from the first byte as the current run, see `download_outcome`
+    #[instrument(name = "download", skip(self, path), fields(path = %path.display()))]
+    async fn run_outcome(&self, url: &str, path: &Path) -> Result<DownloadOutcome, DownloadError> {
+        let work_path = self.prepare_work_path(path).await?;
+        let outcome = self.within_deadline(self.fetch(url, &work_path)).await?;
+        self.finish_work_path(&work_path, path, outcome.last_modified).await?;
+
+        Ok(self.finish_run(outcome))
+    }
+
+    /// Like `download`, but fails unless the file matches `checksum`
+    ///
+    /// The file is verified before it's moved out of `temp_dir`, so only a
+    /// verified file reaches `path`. On a mismatch it's downloaded again up
+    /// to `max_verify_retries` times, all within the one `deadline_ms`.
+    pub async fn download_verified(
+        &self,
+        url: &str,
+        path: &Path,
+        checksum: &Checksum,
+    ) -> Result<u64, DownloadError> {
+        self.start_run().run_verified(url, path, checksum).await
+    }
+
+    /// Downloads and verifies `url` as the current run, see `download_verified`
+    #[instrument(name = "download", skip(self, path, checksum), fields(path = %path.display()))]
+    async fn run_verified(
+        &self,
+        url: &str,
+        path: &Path,
+        checksum: &Checksum,
+    ) -> Result<u64, DownloadError> {
+        let work_path = self.prepare_work_path(path).await?;
+        let outcome = self
+            .within_deadline(self.fetch_verified(url, &work_path, checksum))
+            .await?;
+        self.finish_work_path(&work_path, path, outcome.last_modified).await?;
+
+        Ok(outcome.bytes_transferred)
+    }
+
+    /// Downloads into `path` directly until it matches `checksum`, see `download_verified`
+    async fn fetch_verified(
+        &self,
+        url: &str,
+        path: &Path,
+        checksum: &Checksum,
+    ) -> Result<DownloadOutcome, DownloadError> {
+        let mut attempt = 0;
+
+        let outcome = loop {
+            let outcome = self.fetch(url, path).await?;
+            let bytes = outcome.bytes_transferred;
+
+            match verify_checksum(path, checksum).await {
+                Ok(()) => break outcome,
+                Err(e @ DownloadError::ChecksumMismatch { .. })
+                    if attempt < self.config.max_verify_retries =>
+                {
+                    attempt += 1;
+                    self.add_wasted(bytes);
+                    warn!(attempt, error = %e, "checksum mismatch, downloading again");
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        debug!(algorithm = checksum.algorithm(), "checksum verified");
+
+        Ok(outcome)
+    }
+
+    /// Downloads a file described by a metalink, trying its mirrors in order
+    ///
+    /// The strongest listed checksum is verified as in `download_verified`,
+    /// and a mirror serving a different size than listed is skipped. Any
+    /// server-side failure moves on to the next mirror; local problems such
+    /// as a full disk fail straight away. `deadline_ms` covers all the
+    /// mirrors together.
+    #[instrument(name = "download", skip(self, file, path), fields(path = %path.display()))]
+    pub async fn download_metalink(
+        &self,
+        file: &MetalinkFile,
+        path: &Path,
+    ) -> Result<u64, DownloadError> {
+        let work_path = self.prepare_work_path(path).await?;
+        let outcome = self
+            .within_deadline(self.try_mirrors(file, &work_path))
+            .await?;
+        self.finish_work_path(&work_path, path, outcome.last_modified).await?;
+
+        Ok(outcome.bytes_transferred)
+    }
+
+    /// Tries each of the metalink's mirrors in turn into `path`, see `download_metalink`
+    async fn try_mirrors(
+        &self,
+        file: &MetalinkFile,
+        path: &Path,
+    ) -> Result<DownloadOutcome, DownloadError> {
+        let mut last_error =
+            DownloadError::InvalidMetalink(format!("No mirrors for {}", file.name));
+
+        for mirror in &file.mirrors {
+            // each mirror is a download of its own, e.g. for `failure_limit`
+            let run = self.start_run();
+            let result = match file.checksum() {
+                Some(checksum) => run.fetch_verified(mirror, path, checksum).await,
+                None => run.fetch(mirror, path).await,
+            };
+
+            let error = match (result, file.size) {
+                (Ok(outcome), Some(expected)) if outcome.bytes_transferred != expected => {
+                    let _ = tokio::fs::remove_file(path).await;
+                    DownloadError::LengthMismatch {
+                        expected,
+                        actual: outcome.bytes_transferred,
+                    }
+                }
+                (Ok(outcome), _) => return Ok(outcome),
+                (Err(
+                    e @ (DownloadError::FileError(_)
+                    | DownloadError::InsufficientSpace(_)
+                    | DownloadError::InvalidConfig(_)),
+                ), _) => return Err(e),
+                (Err(e), _) => e,
+            };
+
+            warn!(mirror = %mirror, error = %error, "mirror failed, trying the next one");
+            last_error = error;
+        }
+
+        Err(last_error)
+    }
+
+    /// Downloads into `path` directly, see `download`
+    async fn fetch(&self, url: &str, path: &Path) -> Result<DownloadOutcome, DownloadError> {
+        let result = self.fetch_once(url, path).await;
+        let result = self.restart_if_changed(url, path, result).await;
+        self.reject_if_too_small(path, result).await
+    }
+
+    /// Fails with `EmptyResponse` if the size is below `min_expected_size`
+    fn check_min_size(&self, size: u64) -> Result<(), DownloadError> {
+        match self.config.min_expected_size {
+            Some(min) if size < min => {
+                warn!(size, min, "response smaller than expected");
+                Err(DownloadError::EmptyResponse(size))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes a finished download that turned out too small, see `min_expected_size`
+    async fn reject_if_too_small(
+        &self,
+        path: &Path,
+        result: Result<DownloadOutcome, DownloadError>,
+    ) -> Result<DownloadOutcome, DownloadError> {
+        let outcome = result?;
+
+        if let Err(error) = self.check_min_size(outcome.total_size) {
+            self.discard_partial(path).await;
+            return Err(error);
+        }
+
+        Ok(outcome)
+    }
+
+    /// Deals with the remote file changing during a download
+    ///
+    /// The partial data mixes two versions, so it's discarded. With
+    /// `restart_on_change` the file is then downloaded again from scratch.
+    async fn restart_if_changed(
+        &self,
+        url: &str,
+        path: &Path,
+        result: Result<DownloadOutcome, DownloadError>,
+    ) -> Result<DownloadOutcome, DownloadError> {
+        let Err(error @ DownloadError::ResourceChanged { .. }) = result else {
+            return result;
+        };
+
+        warn!(error = %error, "remote file changed, discarding partial data");
+        self.discard_partial(path).await;
+
+        if !self.config.restart_on_change {
+            return Err(error);
+        }
+
+        info!("restarting download from scratch");
+        let result = self.fetch_once(url, path).await;
+
+        // changed again, don't chase it any further
+        if let Err(DownloadError::ResourceChanged { .. }) = result {
+            self.discard_partial(path).await;
+        }
+
+        result
+    }
+
+    /// Discards a partial file that is longer than the remote file
+    ///
+    /// The remote file was replaced by a smaller one, so the partial holds
+    /// another version. Its first bytes may still match, but with nothing
+    /// stored to check them against (no ETag or digest) they can't be
+    /// trusted, and a preallocated partial would otherwise look complete.
+    async fn discard_if_longer(&self, path: &Path, file_size: u64) {
+        let existing = match tokio::fs::metadata(path).await {
+            Ok(meta) => meta.len(),
+            Err(_) => return,
+        };
+
+        if existing > file_size {
+            warn!(existing, file_size, "remote file shrank, starting over");
+            self.discard_partial(path).await;
+        }
+    }
+
+    /// Removes a partial file and any segment files next to it
+    async fn discard_partial(&self, path: &Path) {
+        let _ = tokio::fs::remove_file(path).await;
+        let _ = tokio::fs::remove_file(layout_path(path)).await;
+        discard_segments_from(path, 0).await;
+    }
+
+    /// Makes one attempt at `fetch`
+    async fn fetch_once(&self, url: &str, path: &Path) -> Result<DownloadOutcome, DownloadError> {
         // get file info
-        let (file_size, supports_ranges) = self.get_file_info(url).await?;
+        let head = self.probe_head(url).await?;
+        let (file_size, supports_ranges) = (head.content_length, head.supports_ranges);
+        if let Some(size) = file_size {
+            self.check_min_size(size)?;
+        }
+
+        // without ranges or a known length we can't split, just stream it
+        let outcome = match file_size {
+            Some(size) if supports_ranges && size > 0 => {
+                self.fetch_chunked(url, path, size).await?
+            }
+            _ => {
+                debug!(?file_size, supports_ranges, "can't split, streaming single");
+                self.single_or_upgrade(url, path).await?
+            }
+        };
+
+        Ok(outcome.modified_at(head.last_modified))
+    }
+
+    /// Streams `url` in one piece, switching to chunks if that fails and
+    /// the server turns out to support ranges after all
+    ///
+    /// A flaky HEAD can leave out `Accept-Ranges` or the length, which
+    /// would otherwise rule out chunking for good. Only retryable failures
+    /// lead to the second look, a ranged GET for the first byte; if that
+    /// doesn't come back as a 206 with a total, the original error stands.
+    async fn single_or_upgrade(
+        &self,
+        url: &str,
+        path: &Path,
+    ) -> Result<DownloadOutcome, DownloadError> {
+        let error = match self.single_fresh(url, path).await {
+            Err(e) if e.is_retryable() => e,
+            result => return result,
+        };
 
-        // if ranges not supported, fall back to single download
-        if !supports_ranges {
-            return self.download_single(url, path).await;
+        match self.probe_range(url).await {
+            Some(size) if size > 0 => {
+                warn!(error = %error, size, "single stream failed, but ranges work, chunking");
+                self.check_min_size(size)?;
+                self.fetch_chunked(url, path, size).await
+            }
+            _ => Err(error),
         }
+    }
+
+    /// Downloads `file_size` bytes of `url` into `path` in parallel chunks
+    async fn fetch_chunked(
+        &self,
+        url: &str,
+        path: &Path,
+        file_size: u64,
+    ) -> Result<DownloadOutcome, DownloadError> {
+        self.check_space(path, file_size)?;
 
         // calculate chunks
         let chunks = self.calculate_chunks(file_size);
+        debug!(file_size, chunks = chunks.len(), "starting chunked download");
 
-        // create output file with correct size (pre-allocate)
-        let file = File::create(path)
-            .await
-            .map_err(|e| DownloadError::FileError(e.to_string()))?;
-        
-        file.set_len(file_size)
-            .await
-            .map_err(|e| DownloadError::FileError(e.to_string()))?;
-
-        // download chunks in parallel with retry logic
-        let mut tasks = Vec::new();
-        
-        for chunk in chunks {
-            let url = url.to_string();
-            let path = path.to_path_buf();
-            let client = self.client.clone();
-            let config = self.config.clone();
-
-            let task = tokio::spawn(async move {
-                let downloader = Self {
-                    client,
-                    config,
-                };
-                
-                let mut file = File::options()
-                    .write(true)
-                    .open(&path)
+        match self.config.segment_mode {
+            SegmentMode::SingleFile => {
+                // create output file with correct size (pre-allocate)
+                let file = File::create(path)
                     .await
                     .map_err(|e| DownloadError::FileError(e.to_string()))?;
 
-                downloader.download_chunk_with_retry(&url, chunk, &mut file).await
-            });
+                preallocate(&file, file_size, self.config.preallocate).await?;
 
-            tasks.push(task);
+                // close the file handle, we'll reopen in each task
+                drop(file);
+            }
+            SegmentMode::SeparateFiles => discard_segments_from(path, chunks.len()).await,
         }
 
-        // wait for all chunks to complete
-        let mut total_bytes = 0u64;
-        
-        for task in tasks {
-            let bytes = task
-                .await
-                .map_err(|e| DownloadError::NetworkError(format!("Task failed: {}", e)))?
-                ?;
-            
-            total_bytes += bytes;
-        }
+        // download chunks in parallel with retry logic
+        let total_bytes = self.download_chunks(url, path, chunks).await?;
+        check_length(path, file_size).await?;
 
-        Ok(total_bytes)
+        info!(bytes = total_bytes, "download complete");
+
+        Ok(DownloadOutcome::new(OutcomeKind::Fresh, total_bytes, file_size))
     }
 
     /// Downloads a file with resume support (detects partial files)
+    ///
+    /// Returns the bytes downloaded by this call, see
+    /// `download_resumable_outcome` for more detail.
     pub async fn download_resumable(
         &self,
         url: &str,
         path: &Path,
     ) -> Result<u64, DownloadError> {
-        // get file info
-        let (file_size, supports_ranges) = self.get_file_info(url).await?;
+        Ok(self.download_resumable_outcome(url, path).await?.bytes_transferred)
+    }
+
+    /// Downloads a file with resume support, reporting whether it resumed
+    ///
+    /// With `temp_dir` set, the partial file is looked for and kept there.
+    #[instrument(name = "download", skip(self, path), fields(path = %path.display()))]
+    pub async fn download_resumable_outcome(
+        &self,
+        url: &str,
+        path: &Path,
+    ) -> Result<DownloadOutcome, DownloadError> {
+        let run = self.start_run();
+        let work_path = run.prepare_work_path(path).await?;
+        let outcome = run
+            .within_deadline(run.fetch_resumable(url, &work_path))
+            .await?;
+        run.finish_work_path(&work_path, path, outcome.last_modified).await?;
+
+        Ok(run.finish_run(outcome))
+    }
 
-        // if ranges not supported, fall back to single download
-        if !supports_ranges {
-            return self.download_single(url, path).await;
+    /// Runs `download`, failing it once `deadline_ms` has passed
+    ///
+    /// Dropping the future aborts its chunk tasks, and whatever they wrote
+    /// stays in the partial file.
+    async fn within_deadline<T>(
+        &self,
+        download: impl std::future::Future<Output = Result<T, DownloadError>>,
+    ) -> Result<T, DownloadError> {
+        let Some(deadline_ms) = self.config.deadline_ms else {
+            return download.await;
+        };
+
+        match tokio::time::timeout(Duration::from_millis(deadline_ms), download).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!(deadline_ms, "download deadline exceeded");
+                Err(DownloadError::DeadlineExceeded(deadline_ms))
+            }
         }
+    }
+
+    /// Downloads into `path` directly, see `download_resumable`
+    async fn fetch_resumable(
+        &self,
+        url: &str,
+        path: &Path,
+    ) -> Result<DownloadOutcome, DownloadError> {
+        let result = self.resume_once(url, path).await;
+        let result = self.restart_if_changed(url, path, result).await;
+        self.reject_if_too_small(path, result).await
+    }
+
+    /// Makes one attempt at `fetch_resumable`
+    async fn resume_once(&self, url: &str, path: &Path) -> Result<DownloadOutcome, DownloadError> {
+        // get file info
+        let head = self.probe_head(url).await?;
+        let (file_size, supports_ranges) = (head.content_length, head.supports_ranges);
+        if let Some(size) = file_size {
+            self.check_min_size(size)?;
+            self.discard_if_longer(path, size).await;
+        }
+
+        // without ranges or a known length we can't split, but may still resume
+        let file_size = match file_size {
+            Some(size) if supports_ranges && size > 0 => size,
+            _ => {
+                debug!(?file_size, supports_ranges, "can't split, resuming single");
+                let outcome = self.resume_single(url, path, file_size).await?;
+                return Ok(outcome.modified_at(head.last_modified));
+            }
+        };
 
         // detect existing partial file and get chunks with resume info
-        let chunks = self.detect_resume(path, file_size).await?;
+        let mut chunks = self.resume_chunks(path, file_size).await?;
+
+        if let Some(overlap) = self.config.resume_check_bytes.filter(|&n| n > 0) {
+            self.check_resume_seams(url, path, &mut chunks, overlap).await?;
+        }
+
+        if self.config.segment_mode == SegmentMode::SeparateFiles {
+            // splits from an earlier run don't match this layout
+            discard_segments_from(path, chunks.len()).await;
+        }
+
+        // finished segments may still be waiting to be merged
+        let pending_merge = self.config.segment_mode == SegmentMode::SeparateFiles
+            && tokio::fs::try_exists(segment_path(path, 0)).await.unwrap_or(false);
 
         // check if download is already complete
         let total_remaining: u64 = chunks.iter().map(|c| c.remaining()).sum();
-        if total_remaining == 0 {
-            return Ok(0); // already complete
+        if total_remaining == 0 && !pending_merge {
+            // a longer local file also looks complete, so check before trusting it
+            check_length(path, file_size).await?;
+            info!("already complete");
+            return Ok(DownloadOutcome::new(OutcomeKind::AlreadyComplete, 0, file_size)
+                .modified_at(head.last_modified));
         }
 
-        // ensure file exists with correct size
-        let file = if tokio::fs::metadata(path).await.is_ok() {
-            // file exists, open for writing
-            File::options()
-                .write(true)
-                .open(path)
-                .await
-                .map_err(|e| DownloadError::FileError(e.to_string()))?
+        debug!(file_size, remaining = total_remaining, "resuming chunked download");
+
+        let kind = if total_remaining < file_size {
+            OutcomeKind::Resumed
         } else {
-            // create new file with correct size
-            let file = File::create(path)
-                .await
-                .map_err(|e| DownloadError::FileError(e.to_string()))?;
-            
-            file.set_len(file_size)
+            OutcomeKind::Fresh
+        };
+        let bytes = self.fetch_planned(url, path, file_size, chunks).await?;
+
+        Ok(DownloadOutcome::new(kind, bytes, file_size).modified_at(head.last_modified))
+    }
+
+    /// Downloads the incomplete chunks of `chunks` into an existing partial file
+    async fn fetch_planned(
+        &self,
+        url: &str,
+        path: &Path,
+        file_size: u64,
+        chunks: Vec<Chunk>,
+    ) -> Result<u64, DownloadError> {
+        let total_remaining: u64 = chunks.iter().map(|c| c.remaining()).sum();
+        self.check_space(path, total_remaining)?;
+
+        // segment files are opened per chunk, the destination is written on merge
+        if self.config.segment_mode == SegmentMode::SingleFile {
+            // ensure file exists with correct size
+            let file = if tokio::fs::metadata(path).await.is_ok() {
+                // file exists, open for writing
+                File::options()
+                    .write(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| DownloadError::FileError(e.to_string()))?
+            } else {
+                // create new file with correct size
+                let file = File::create(path)
+                    .await
+                    .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+                preallocate(&file, file_size, self.config.preallocate).await?;
+
+                file
+            };
+
+            // close the file handle, we'll reopen in each task
+            drop(file);
+        }
+
+        // download chunks in parallel (only incomplete ones)
+        let total_bytes = self.download_chunks(url, path, chunks).await?;
+        check_length(path, file_size).await?;
+
+        info!(bytes = total_bytes, "download complete");
+
+        Ok(total_bytes)
+    }
+
+    /// Continues a paused download with a chunk plan from the caller
+    ///
+    /// Meant for a plan from `replan_chunks`, e.g. to add connections to a
+    /// download that turned out to be on a fast server. The plan has to cover
+    /// the whole remote file, otherwise this fails with
+    /// `DownloadError::ResourceChanged`. A plan with gaps or overlapping
+    /// chunks fails with `DownloadError::InvalidConfig`, as the bytes in a
+    /// gap would never be fetched.
+    pub async fn download_planned(
+        &self,
+        url: &str,
+        path: &Path,
+        chunks: Vec<Chunk>,
+    ) -> Result<u64, DownloadError> {
+        self.start_run().run_planned(url, path, chunks).await
+    }
+
+    /// Continues a download with `chunks` as the current run, see `download_planned`
+    #[instrument(name = "download", skip(self, path, chunks), fields(path = %path.display()))]
+    async fn run_planned(
+        &self,
+        url: &str,
+        path: &Path,
+        chunks: Vec<Chunk>,
+    ) -> Result<u64, DownloadError> {
+        let work_path = self.prepare_work_path(path).await?;
+        let outcome = self
+            .within_deadline(self.fetch_plan(url, &work_path, chunks))
+            .await?;
+        self.finish_work_path(&work_path, path, outcome.last_modified).await?;
+
+        Ok(outcome.bytes_transferred)
+    }
+
+    /// Checks `chunks` against the server and downloads them into `path`, see `download_planned`
+    async fn fetch_plan(
+        &self,
+        url: &str,
+        path: &Path,
+        chunks: Vec<Chunk>,
+    ) -> Result<DownloadOutcome, DownloadError> {
+        let head = self.probe_head(url).await?;
+        let file_size = head.content_length;
+
+        let planned = chunks.iter().map(|c| c.end + 1).max().unwrap_or(0);
+        if !covers_exactly(&chunks, planned) {
+            return Err(DownloadError::InvalidConfig(
+                "Chunk plan has gaps or overlapping chunks".to_string(),
+            ));
+        }
+        if file_size != Some(planned) {
+            return Err(DownloadError::ResourceChanged {
+                expected: planned,
+                actual: file_size.unwrap_or(0),
+            });
+        }
+
+        let result = self
+            .fetch_planned(url, path, planned, chunks)
+            .await
+            .map(|bytes| DownloadOutcome::new(OutcomeKind::Resumed, bytes, planned));
+        let outcome = self.restart_if_changed(url, path, result).await?;
+
+        Ok(outcome.modified_at(head.last_modified))
+    }
+
+    /// Reports what `download_resumable` would do without downloading the file
+    ///
+    /// Sends a single HEAD request and looks at any existing partial file for
+    /// `path` (in `temp_dir` if set); nothing is written.
+    pub async fn inspect(&self, url: &str, path: &Path) -> Result<DownloadPlan, DownloadError> {
+        let head = self.probe_head(url).await?;
+        let total_size = head.content_length;
+        let path = &self.work_path(path)?;
+
+        let existing = match tokio::fs::metadata(path).await {
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        };
+
+        let mut plan = DownloadPlan {
+            final_url: head.final_url,
+            total_size,
+            supports_ranges: head.supports_ranges,
+            chunked: false,
+            chunks: Vec::new(),
+            existing_bytes: 0,
+            bytes_remaining: total_size,
+            disk_usage: total_size,
+            http_version: head.http_version,
+            head_latency: head.latency,
+        };
+
+        match total_size {
+            Some(size) if plan.supports_ranges && size > 0 => {
+                plan.chunked = true;
+                // mirrors discard_if_longer: a longer local file is discarded
+                plan.chunks = if existing > size {
+                    self.calculate_chunks(size)
+                } else {
+                    self.resume_chunks(path, size).await?
+                };
+                plan.existing_bytes = plan.chunks.iter().map(|c| c.downloaded).sum();
+                plan.bytes_remaining = Some(plan.chunks.iter().map(|c| c.remaining()).sum());
+            }
+            // mirrors resume_single: a longer local file is discarded
+            Some(size) if existing > size => {}
+            Some(size) => {
+                plan.existing_bytes = existing;
+                plan.bytes_remaining = Some(size - existing);
+                if size > 0 {
+                    plan.chunks = vec![Chunk {
+                        index: 0,
+                        start: 0,
+                        end: size - 1,
+                        downloaded: existing,
+                    }];
+                }
+            }
+            None => plan.existing_bytes = existing,
+        }
+
+        Ok(plan)
+    }
+
+    /// Probes each of `urls` without downloading anything
+    ///
+    /// Meant for checking a batch before starting it: dead links and hosts
+    /// that can't resume show up front. The probes run together, limited by
+    /// `max_concurrent_probes`. Results are in the same order as `urls`, and
+    /// one failing doesn't stop the others.
+    pub async fn preflight(&self, urls: &[String]) -> Vec<Result<RemoteInfo, DownloadError>> {
+        let probes = urls.iter().map(|url| async move {
+            let head = self.probe_head(url).await?;
+            Ok(RemoteInfo {
+                final_url: head.final_url,
+                size: head.content_length,
+                supports_ranges: head.supports_ranges,
+            })
+        });
+
+        futures_util::future::join_all(probes).await
+    }
+
+    /// Returns the URL to resume from when `resolved_url` may have expired
+    ///
+    /// Signed CDN URLs stop working after a while. If `resolved_url` still
+    /// answers a HEAD request it's returned as is; if it's refused (401, 403
+    /// or 410), `original_url` is resolved again, following redirects, to get
+    /// a freshly signed one. The new URL is only trusted if it reports
+    /// `expected_size`, the size the partial file was started with.
+    pub async fn refresh_resolved_url(
+        &self,
+        original_url: &str,
+        resolved_url: &str,
+        expected_size: Option<u64>,
+    ) -> Result<String, DownloadError> {
+        let status = match self.probe_head(resolved_url).await {
+            Ok(_) => return Ok(resolved_url.to_string()),
+            Err(DownloadError::HttpError(status @ (401 | 403 | 410))) => status,
+            Err(e) => return Err(e),
+        };
+
+        let head = self.probe_head(original_url).await?;
+        let fresh_url = head.final_url;
+        debug!(status, %fresh_url, "resolved URL refused, re-resolved original");
+
+        match (expected_size, head.content_length) {
+            (Some(expected), Some(actual)) if expected != actual => {
+                Err(DownloadError::ResourceChanged { expected, actual })
+            }
+            // nothing to compare, so the partial file can't be trusted
+            (Some(_), None) => Err(DownloadError::HttpError(status)),
+            _ => Ok(fresh_url),
+        }
+    }
+
+    /// Returns where the file for `path` is written while downloading
+    ///
+    /// That's `path` itself, or a file in `temp_dir` named after a hash of
+    /// the whole of `path` and its file name, e.g. `3f2a9c0e1b7d4a65-x.iso`.
+    /// Downloads of the same name into different directories so get
+    /// partials of their own, and a rerun finds its partial again.
+    pub fn work_path(&self, path: &Path) -> Result<PathBuf, DownloadError> {
+        use sha2::Digest;
+
+        let Some(temp_dir) = &self.config.temp_dir else {
+            return Ok(path.to_path_buf());
+        };
+
+        let file_name = path.file_name().ok_or_else(|| {
+            DownloadError::FileError(format!("No file name in {}", path.display()))
+        })?;
+
+        let digest = sha2::Sha256::digest(path.as_os_str().as_encoded_bytes());
+        let prefix: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+
+        let mut name = std::ffi::OsString::from(format!("{}-", prefix));
+        name.push(file_name);
+        Ok(temp_dir.join(name))
+    }
+
+    /// Like `work_path`, creating the temp directory if needed
+    async fn prepare_work_path(&self, path: &Path) -> Result<PathBuf, DownloadError> {
+        if let Some(temp_dir) = &self.config.temp_dir {
+            tokio::fs::create_dir_all(temp_dir)
                 .await
                 .map_err(|e| DownloadError::FileError(e.to_string()))?;
-            
-            file
+        }
+
+        self.work_path(path)
+    }
+
+    /// Moves a finished download from the temp directory to `path`
+    ///
+    /// With `preserve_timestamps`, the file then gets `last_modified` as
+    /// its modification time. Failing to set it only logs a warning, the
+    /// download itself is fine.
+    async fn finish_work_path(
+        &self,
+        work_path: &Path,
+        path: &Path,
+        last_modified: Option<SystemTime>,
+    ) -> Result<(), DownloadError> {
+        if work_path != path {
+            debug!(from = %work_path.display(), "moving into place");
+            move_into_place(work_path, path).await?;
+        }
+
+        if let Some(time) = last_modified.filter(|_| self.config.preserve_timestamps) {
+            if let Err(e) = set_modified(path, time).await {
+                warn!(error = %e, "couldn't set the modification time");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fails early if writing `bytes` more to `work_path` would leave less
+    /// than `min_free_space_after` free
+    fn check_space(&self, work_path: &Path, bytes: u64) -> Result<(), DownloadError> {
+        let dir = match work_path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
         };
+        let needed = bytes.saturating_add(self.config.min_free_space_after.unwrap_or(0));
 
-        // close the file handle, we'll reopen in each task
-        drop(file);
+        match available_space(dir) {
+            Some(free) if free < needed => {
+                warn!(free, needed, dir = %dir.display(), "not enough disk space");
+                Err(DownloadError::InsufficientSpace(needed))
+            }
+            _ => Ok(()),
+        }
+    }
 
-        // download chunks in parallel (only incomplete ones)
-        let mut tasks = Vec::new();
-        
-        for chunk in chunks {
-            // skip complete chunks
-            if chunk.is_complete() {
-                continue;
+    /// Downloads bytes `start..=end` of a file into `writer`
+    ///
+    /// Sends one Range request, for when only part of a file is wanted,
+    /// e.g. the first megabyte of a video for a preview. An `end` past the
+    /// end of the file gets the bytes up to it. Fails with
+    /// `DownloadError::RangeNotSupported` if the server sends anything
+    /// other than the requested slice. Returns the bytes written.
+    pub async fn download_range<W>(
+        &self,
+        url: &str,
+        start: u64,
+        end: u64,
+        writer: &mut W,
+    ) -> Result<u64, DownloadError>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        if end < start {
+            return Err(DownloadError::InvalidConfig(format!(
+                "Range {}-{} ends before it starts",
+                start, end
+            )));
+        }
+
+        let response = self.transport.get_range(url, start, Some(end)).await?;
+
+        debug!(status = response.status().as_u16(), start, end, "range response");
+
+        match response.status() {
+            reqwest::StatusCode::PARTIAL_CONTENT => {}
+            // the whole file, the server ignored the range
+            reqwest::StatusCode::OK => return Err(DownloadError::RangeNotSupported),
+            status => return Err(DownloadError::HttpError(status.as_u16())),
+        }
+
+        let expected = match content_range_span(&response) {
+            Some((first, last)) if first == start && last >= first && last <= end => {
+                last - first + 1
+            }
+            span => {
+                warn!(?span, start, end, "unexpected Content-Range");
+                return Err(DownloadError::RangeNotSupported);
             }
+        };
+
+        let mut body = response.bytes_stream();
+        let mut written = 0u64;
+
+        while let Some(data) =
+            next_with_stall_timeout(&mut body, self.config.stall_timeout_ms).await?
+        {
+            let data = data?;
+
+            // never hand the caller more than it asked for
+            let take = data.len().min((expected - written) as usize);
+            writer
+                .write_all(&data[..take])
+                .await
+                .map_err(|e| DownloadError::FileError(e.to_string()))?;
+            written += take as u64;
+
+            if take < data.len() {
+                break;
+            }
+        }
+
+        writer
+            .flush()
+            .await
+            .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+        if written != expected {
+            return Err(DownloadError::LengthMismatch {
+                expected,
+                actual: written,
+            });
+        }
+
+        Ok(written)
+    }
+
+    /// Works out the file name a download should be saved under
+    ///
+    /// Uses the server's Content-Disposition if present, otherwise the last
+    /// path segment of the URL after redirects, then the original URL, and
+    /// finally `FALLBACK_FILENAME`. The name is passed through
+    /// `sanitize_filename`, and a change is logged.
+    pub async fn resolve_filename(&self, url: &str) -> Result<String, DownloadError> {
+        let response = self.head(url).await?;
+
+        if !response.status().is_success() {
+            return Err(DownloadError::HttpError(response.status().as_u16()));
+        }
+
+        let from_header = response
+            .headers()
+            .get("content-disposition")
+            .and_then(|v| v.to_str().ok())
+            .and_then(filename_from_content_disposition);
+
+        let name = from_header
+            .or_else(|| filename_from_url(response.url().as_str()))
+            .or_else(|| filename_from_url(url))
+            .unwrap_or_else(|| FALLBACK_FILENAME.to_string());
+
+        let sanitized = sanitize_filename(&name);
+        if sanitized != name {
+            info!(original = %name, sanitized = %sanitized, "renamed to suit this OS");
+        }
+
+        Ok(sanitized)
+    }
+
+    /// Downloads into `dir`, naming the file via `resolve_filename`
+    ///
+    /// Returns the final path and the number of bytes downloaded. If the name
+    /// is taken, a number is added, e.g. `notes (1).txt`, rather than
+    /// overwriting the existing file.
+    pub async fn download_to_dir(
+        &self,
+        url: &str,
+        dir: &Path,
+    ) -> Result<(PathBuf, u64), DownloadError> {
+        let filename = self.resolve_filename(url).await?;
 
-            let url = url.to_string();
-            let path = path.to_path_buf();
-            let client = self.client.clone();
-            let config = self.config.clone();
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| DownloadError::FileError(e.to_string()))?;
 
-            let task = tokio::spawn(async move {
-                let downloader = Self {
-                    client,
-                    config,
-                };
-                
-                let mut file = File::options()
-                    .write(true)
-                    .open(&path)
-                    .await
-                    .map_err(|e| DownloadError::FileError(e.to_string()))?;
+        let path = claim_free_name(dir, &filename).await?;
+
+        match self.download(url, &path).await {
+            Ok(bytes) => Ok((path, bytes)),
+            Err(e) => {
+                // give the name back unless a partial download is kept there
+                if tokio::fs::metadata(&path).await.is_ok_and(|m| m.len() == 0) {
+                    let _ = tokio::fs::remove_file(&path).await;
+                }
+                Err(e)
+            }
+        }
+    }
 
-                downloader.download_chunk_with_retry(&url, chunk, &mut file).await
+    /// Downloads many files, running at most `concurrency` at once
+    ///
+    /// Results are returned in the same order as `urls`. A failed download
+    /// doesn't stop the others. Dropping the returned future, e.g. on a
+    /// timeout, aborts the downloads still running.
+    pub async fn download_batch(
+        &self,
+        urls: &[(String, PathBuf)],
+        concurrency: usize,
+    ) -> Vec<Result<u64, DownloadError>> {
+        let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+        let mut tasks = JoinSet::new();
+        let mut slots = HashMap::new();
+
+        for (slot, (url, path)) in urls.iter().enumerate() {
+            let url = url.clone();
+            let path = path.clone();
+            let semaphore = semaphore.clone();
+            let downloader = self.clone();
+
+            let task = tasks.spawn(async move {
+                // hold a slot for the whole download
+                let _permit = semaphore.acquire_owned().await;
+
+                downloader.download(&url, &path).await
             });
 
-            tasks.push(task);
+            slots.insert(task.id(), slot);
         }
 
-        // wait for all chunks to complete
-        let mut total_bytes = 0u64;
-        
-        for task in tasks {
-            let bytes = task
-                .await
-                .map_err(|e| DownloadError::NetworkError(format!("Task failed: {}", e)))?
-                ?;
-            
-            total_bytes += bytes;
+        // put the results back in input order as they come in
+        let mut results: Vec<Option<Result<u64, DownloadError>>> = vec![None; urls.len()];
+
+        while let Some(joined) = tasks.join_next_with_id().await {
+            let (id, result) = match joined {
+                Ok((id, result)) => (id, result),
+                Err(e) => (
+                    e.id(),
+                    Err(DownloadError::NetworkError(format!("Task failed: {}", e))),
+                ),
+            };
+
+            results[slots[&id]] = Some(result);
         }
 
-        Ok(total_bytes)
+        results.into_iter().flatten().collect()
     }
 
     /// Fallback to single-threaded download
     async fn download_single(&self, url: &str, path: &Path) -> Result<u64, DownloadError> {
-        let response = self.client
-            .get(url)
-            .send()
-            .await
-            .map_err(|e| DownloadError::NetworkError(e.to_string()))?;
+        let response = self.transport.get(url).await?;
+
+        debug!(status = response.status().as_u16(), "single stream response");
 
         if !response.status().is_success() {
             return Err(DownloadError::HttpError(response.status().as_u16()));
         }
 
-        let mut file = File::create(path)
+        let file = File::create(path)
             .await
             .map_err(|e| DownloadError::FileError(e.to_string()))?;
 
+        self.write_single(response, file, 0).await
+    }
+
+    /// Single-threaded download that continues an existing partial file
+    ///
+    /// Asks for the bytes after the ones already on disk and appends them.
+    /// If the server ignores the Range and sends the whole body, the file is
+    /// rewritten from scratch. This also covers servers that accept ranges
+    /// but don't report a length: the file can't be split, but it resumes,
+    /// and a 416 whose `Content-Range` total matches the local file means
+    /// it was already complete.
+    async fn resume_single(
+        &self,
+        url: &str,
+        path: &Path,
+        file_size: Option<u64>,
+    ) -> Result<DownloadOutcome, DownloadError> {
+        // a file written in chunks isn't filled in order, its length proves nothing
+        if tokio::fs::try_exists(layout_path(path)).await.unwrap_or(false) {
+            debug!("partial file was written in chunks, restarting single stream");
+            self.discard_partial(path).await;
+            return self.single_fresh(url, path).await;
+        }
+
+        let existing = match tokio::fs::metadata(path).await {
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        };
+
+        if existing == 0 {
+            return self.single_fresh(url, path).await;
+        }
+
+        match file_size {
+            Some(size) if existing == size => {
+                info!("already complete");
+                return Ok(DownloadOutcome::new(OutcomeKind::AlreadyComplete, 0, size));
+            }
+            // local file is bigger than the remote one, it can't be a prefix
+            Some(size) if existing > size => return self.single_fresh(url, path).await,
+            _ => {}
+        }
+
+        let response = self.transport.get_range(url, existing, None).await?;
+
+        debug!(status = response.status().as_u16(), existing, "single resume response");
+
+        let (file, kept) = match response.status() {
+            reqwest::StatusCode::PARTIAL_CONTENT
+                if content_range_span(&response).is_some_and(|(first, _)| first != existing) =>
+            {
+                // not the range we asked for, so it can't be appended
+                debug!(span = ?content_range_span(&response), "unexpected range, restarting");
+                return self.single_fresh(url, path).await;
+            }
+            reqwest::StatusCode::PARTIAL_CONTENT => {
+                let file = File::options()
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| DownloadError::FileError(e.to_string()))?;
+                (file, existing)
+            }
+            reqwest::StatusCode::OK => {
+                // server ignored the range, the body starts at byte 0
+                debug!("range ignored, restarting single stream");
+                let file = File::create(path)
+                    .await
+                    .map_err(|e| DownloadError::FileError(e.to_string()))?;
+                (file, 0)
+            }
+            reqwest::StatusCode::RANGE_NOT_SATISFIABLE
+                if file_size.is_none() && content_range_total(&response) == Some(existing) =>
+            {
+                // nothing after the last byte we have, and the server says that's all
+                info!("already complete");
+                return Ok(DownloadOutcome::new(OutcomeKind::AlreadyComplete, 0, existing));
+            }
+            reqwest::StatusCode::RANGE_NOT_SATISFIABLE => {
+                // we can't tell what changed remotely, so start over
+                return self.single_fresh(url, path).await;
+            }
+            status => return Err(DownloadError::HttpError(status.as_u16())),
+        };
+
+        let bytes = self.write_single(response, file, kept).await?;
+        if kept == 0 {
+            // what we had was sent again
+            self.add_wasted(existing.min(bytes));
+        }
+        let kind = if kept > 0 {
+            OutcomeKind::Resumed
+        } else {
+            OutcomeKind::Fresh
+        };
+
+        Ok(DownloadOutcome::new(kind, bytes, kept + bytes))
+    }
+
+    /// `download_single` as a fresh download's outcome
+    async fn single_fresh(&self, url: &str, path: &Path) -> Result<DownloadOutcome, DownloadError> {
+        let bytes = self.download_single(url, path).await?;
+        Ok(DownloadOutcome::new(OutcomeKind::Fresh, bytes, bytes))
+    }
+
+    /// Streams a response body to the end of `file`, which already holds `kept` bytes
+    async fn write_single(
+        &self,
+        response: TransportResponse,
+        mut file: File,
+        kept: u64,
+    ) -> Result<u64, DownloadError> {
         let mut bytes_downloaded = 0u64;
+        let mut unflushed = 0u64;
         let mut stream = response.bytes_stream();
 
         use futures_util::StreamExt;
 
+        self.reset_available(&[]);
+        self.mark_available(0, kept);
+
         while let Some(chunk) = stream.next().await {
-            let chunk = chunk.map_err(|e| DownloadError::NetworkError(e.to_string()))?;
+            let chunk = chunk?;
             
             file.write_all(&chunk)
                 .await
                 .map_err(|e| DownloadError::FileError(e.to_string()))?;
             
             bytes_downloaded += chunk.len() as u64;
+            unflushed += chunk.len() as u64;
+            self.flush_if_due(&mut file, &mut unflushed, false).await?;
+
+            if self.tracks_available() {
+                file.flush()
+                    .await
+                    .map_err(|e| DownloadError::FileError(e.to_string()))?;
+                self.mark_available(kept, kept + bytes_downloaded);
+            }
         }
 
+        self.flush_if_due(&mut file, &mut unflushed, true).await?;
         file.flush()
             .await
             .map_err(|e| DownloadError::FileError(e.to_string()))?;
 
+        info!(bytes = bytes_downloaded, "single stream complete");
+
         Ok(bytes_downloaded)
     }
 }
@@ -539,6 +2980,125 @@ impl Default for ChunkedDownloader {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::transport::{BodyStream, ResponseFuture};
+    use futures_util::StreamExt;
+    use reqwest::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE};
+    use reqwest::StatusCode;
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+    /// Serves a file from memory, like the test servers in `tests/common`
+    /// but without a socket
+    struct MockTransport {
+        body: Vec<u8>,
+        ranges: bool,             // advertise ranges, otherwise refuse them with 416
+        stall_first: AtomicBool,  // the next range stops halfway and goes silent
+        head_delay: Duration,     // how long each HEAD takes to answer
+        body_delay: Duration,     // how long a range body waits before its first byte
+        hide_ranges: AtomicBool,  // the next HEAD leaves out Accept-Ranges
+        fail_plain_get: bool,     // a GET without a Range gets a 503
+        heads_in_flight: AtomicUsize,
+        max_heads_in_flight: AtomicUsize,
+        requests: Mutex<Vec<String>>,
+    }
+
+    impl MockTransport {
+        fn new(body: Vec<u8>, ranges: bool) -> Self {
+            Self {
+                body,
+                ranges,
+                stall_first: AtomicBool::new(false),
+                head_delay: Duration::ZERO,
+                body_delay: Duration::ZERO,
+                hide_ranges: AtomicBool::new(false),
+                fail_plain_get: false,
+                heads_in_flight: AtomicUsize::new(0),
+                max_heads_in_flight: AtomicUsize::new(0),
+                requests: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn respond(&self, request: String, status: StatusCode) -> TransportResponse {
+            self.requests.lock().unwrap().push(request);
+            let url = reqwest::Url::parse("http://mock.invalid/file.bin").unwrap();
+            TransportResponse::new(status, url)
+        }
+
+        fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl HttpTransport for MockTransport {
+        fn head<'a>(&'a self, _url: &'a str) -> ResponseFuture<'a> {
+            Box::pin(async move {
+                let in_flight = self.heads_in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_heads_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                sleep(self.head_delay).await;
+                self.heads_in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                let mut response = self
+                    .respond("HEAD".to_string(), StatusCode::OK)
+                    .with_header(CONTENT_LENGTH, self.body.len().to_string());
+                if self.ranges && !self.hide_ranges.swap(false, Ordering::SeqCst) {
+                    response = response.with_header(ACCEPT_RANGES, "bytes");
+                }
+                Ok(response)
+            })
+        }
+
+        fn get<'a>(&'a self, _url: &'a str) -> ResponseFuture<'a> {
+            if self.fail_plain_get {
+                let response = self.respond("GET".to_string(), StatusCode::SERVICE_UNAVAILABLE);
+                return Box::pin(async move { Ok(response) });
+            }
+
+            let response = self
+                .respond("GET".to_string(), StatusCode::OK)
+                .with_body(self.body.clone());
+            Box::pin(async move { Ok(response) })
+        }
+
+        fn get_range<'a>(
+            &'a self,
+            _url: &'a str,
+            start: u64,
+            end: Option<u64>,
+        ) -> ResponseFuture<'a> {
+            let request = match end {
+                Some(end) => format!("{}-{}", start, end),
+                None => format!("{}-", start),
+            };
+
+            if !self.ranges {
+                let response = self.respond(request, StatusCode::RANGE_NOT_SATISFIABLE);
+                return Box::pin(async move { Ok(response) });
+            }
+
+            let total = self.body.len() as u64;
+            let end = end.unwrap_or(total - 1).min(total - 1);
+            let slice = bytes::Bytes::copy_from_slice(&self.body[start as usize..=end as usize]);
+
+            let body: BodyStream = if self.stall_first.swap(false, Ordering::SeqCst) {
+                let half = slice.slice(..slice.len() / 2);
+                futures_util::stream::once(async move { Ok(half) })
+                    .chain(futures_util::stream::pending())
+                    .boxed()
+            } else {
+                let delay = self.body_delay;
+                futures_util::stream::once(async move {
+                    sleep(delay).await;
+                    Ok(slice)
+                })
+                .boxed()
+            };
+
+            let response = self
+                .respond(request, StatusCode::PARTIAL_CONTENT)
+                .with_header(CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, total))
+                .with_body_stream(body);
+            Box::pin(async move { Ok(response) })
+        }
+    }
 
     #[test]
     fn test_chunk_calculation() {
@@ -548,6 +3108,7 @@ mod tests {
             max_retries: 3,
             retry_delay_ms: 1000,
             exponential_backoff: true,
+            ..ChunkConfig::default()
         };
         let downloader = ChunkedDownloader::with_config(config);
 
@@ -566,6 +3127,43 @@ mod tests {
         assert_eq!(chunks[3].end, 999);
     }
 
+    #[test]
+    fn test_aligned_chunks() {
+        let config = ChunkConfig {
+            chunk_count: 4,
+            min_chunk_size: 100,
+            chunk_alignment: Some(1000),
+            ..ChunkConfig::default()
+        };
+        let downloader = ChunkedDownloader::with_config(config);
+
+        // 10_500 / 4 = 2625, rounded to 3000, 5000 and 8000
+        let chunks = downloader.calculate_chunks(10_500);
+        let bounds: Vec<_> = chunks.iter().map(|c| (c.index, c.start, c.end)).collect();
+        assert_eq!(
+            bounds,
+            vec![(0, 0, 2999), (1, 3000, 4999), (2, 5000, 7999), (3, 8000, 10_499)]
+        );
+
+        // the remainder is in the last chunk, which may be smaller than the others
+        let chunks = downloader.calculate_chunks(4_100);
+        let bounds: Vec<_> = chunks.iter().map(|c| (c.index, c.start, c.end)).collect();
+        assert_eq!(bounds, vec![(0, 0, 999), (1, 1000, 1999), (2, 2000, 2999), (3, 3000, 4099)]);
+
+        // alignment coarser than the chunks: fewer chunks, no gaps or overlaps
+        for file_size in [1_500, 2_999, 3_001] {
+            let chunks = downloader.calculate_chunks(file_size);
+            assert!(chunks.len() < 4, "{}: {:?}", file_size, chunks);
+            assert_eq!(chunks[0].start, 0);
+            assert_eq!(chunks.last().unwrap().end, file_size - 1);
+            for (i, pair) in chunks.windows(2).enumerate() {
+                assert_eq!(pair[0].index as usize, i);
+                assert_eq!(pair[0].end + 1, pair[1].start);
+                assert_eq!(pair[1].start % 1000, 0);
+            }
+        }
+    }
+
     #[test]
     fn test_small_file_single_chunk() {
         let config = ChunkConfig {
@@ -574,6 +3172,7 @@ mod tests {
             max_retries: 3,
             retry_delay_ms: 1000,
             exponential_backoff: true,
+            ..ChunkConfig::default()
         };
         let downloader = ChunkedDownloader::with_config(config);
 
@@ -595,6 +3194,40 @@ mod tests {
         assert_eq!(chunk.size(), 100);
     }
 
+    #[test]
+    fn test_is_fully_downloaded() {
+        let chunk = |index, start, end, downloaded| Chunk { index, start, end, downloaded };
+
+        // in any order, as long as every byte is there once
+        let done = [chunk(1, 500, 999, 500), chunk(0, 0, 499, 500)];
+        assert!(is_fully_downloaded(&done, 1000));
+        assert!(is_fully_downloaded(&[], 0));
+
+        // an unfinished chunk, a gap, an overlap, or the wrong size
+        assert!(!is_fully_downloaded(&[chunk(0, 0, 499, 500), chunk(1, 500, 999, 499)], 1000));
+        assert!(!is_fully_downloaded(&[chunk(0, 0, 399, 400), chunk(1, 500, 999, 500)], 1000));
+        assert!(!is_fully_downloaded(&[chunk(0, 0, 599, 600), chunk(1, 500, 999, 500)], 1000));
+        assert!(!is_fully_downloaded(&[chunk(0, 100, 999, 900)], 1000));
+        assert!(!is_fully_downloaded(&done, 1200));
+    }
+
+    #[tokio::test]
+    async fn test_planned_download_rejects_gaps() {
+        let transport = Arc::new(MockTransport::new(vec![1u8; 1000], true));
+        let downloader = ChunkedDownloader::with_transport(ChunkConfig::default(), transport);
+        let path = std::env::temp_dir().join("test_planned_gaps.bin");
+
+        // bytes 400..500 belong to no chunk and would never be fetched
+        let chunks = vec![
+            Chunk { index: 0, start: 0, end: 399, downloaded: 0 },
+            Chunk { index: 1, start: 500, end: 999, downloaded: 0 },
+        ];
+        let result = downloader
+            .download_planned("http://mock.invalid/file.bin", &path, chunks)
+            .await;
+        assert!(matches!(result, Err(DownloadError::InvalidConfig(_))), "{:?}", result);
+    }
+
     #[test]
     fn test_chunk_resume_tracking() {
         let mut chunk = Chunk {
@@ -615,6 +3248,164 @@ mod tests {
         assert!(chunk.is_complete());
     }
 
+    #[test]
+    fn test_split_off_even() {
+        let mut chunk = Chunk {
+            index: 0,
+            start: 0,
+            end: 999,
+            downloaded: 200,
+        };
+
+        // 800 bytes left, the back 400 move to the new chunk
+        let stolen = chunk.split_off(4, 100).unwrap();
+
+        assert_eq!(chunk.end, 599);
+        assert_eq!(stolen.index, 4);
+        assert_eq!(stolen.start, 600);
+        assert_eq!(stolen.end, 999);
+        assert_eq!(stolen.downloaded, 0);
+
+        // no gap or overlap between the halves
+        assert_eq!(chunk.end + 1, stolen.start);
+        assert_eq!(chunk.remaining() + stolen.remaining(), 800);
+    }
+
+    #[test]
+    fn test_split_off_odd() {
+        let mut chunk = Chunk {
+            index: 1,
+            start: 1000,
+            end: 1010,
+            downloaded: 0,
+        };
+
+        // 11 bytes: the front keeps the odd byte
+        let stolen = chunk.split_off(2, 1).unwrap();
+
+        assert_eq!(chunk.remaining(), 6);
+        assert_eq!(stolen.start, 1006);
+        assert_eq!(stolen.size(), 5);
+    }
+
+    #[test]
+    fn test_split_off_too_small() {
+        let mut chunk = Chunk {
+            index: 0,
+            start: 0,
+            end: 999,
+            downloaded: 900,
+        };
+
+        // only 100 left, halves would be under the minimum
+        assert_eq!(chunk.split_off(1, 64), None);
+        assert_eq!(chunk.end, 999);
+
+        // complete chunks are never split
+        chunk.downloaded = 1000;
+        assert_eq!(chunk.split_off(1, 0), None);
+    }
+
+    #[test]
+    fn test_availability_out_of_order() {
+        let mut available = Availability::default();
+
+        // later chunks finishing first don't count until the gap fills
+        available.insert(2000, 3000);
+        available.insert(1000, 1500);
+        assert_eq!(available.contiguous(), 0);
+
+        available.insert(0, 1000);
+        assert_eq!(available.contiguous(), 1500);
+
+        available.insert(1500, 2000);
+        assert_eq!(available.contiguous(), 3000);
+
+        // overlapping and repeated ranges change nothing
+        available.insert(500, 2500);
+        assert_eq!(available.contiguous(), 3000);
+        assert_eq!(available.written.len(), 1);
+
+        // resumed progress seeds the ranges
+        let chunks = [
+            Chunk { index: 0, start: 0, end: 999, downloaded: 1000 },
+            Chunk { index: 1, start: 1000, end: 1999, downloaded: 400 },
+            Chunk { index: 2, start: 2000, end: 2999, downloaded: 1000 },
+        ];
+        available.reset(&chunks);
+        assert_eq!(available.contiguous(), 1400);
+        available.insert(1400, 2000);
+        assert_eq!(available.contiguous(), 3000);
+    }
+
+    #[test]
+    fn test_replan_scattered_progress() {
+        let config = ChunkConfig {
+            min_chunk_size: 100,
+            ..ChunkConfig::default()
+        };
+        let downloader = ChunkedDownloader::with_config(config);
+
+        // four chunks of 1000 after some parallel progress
+        let chunk = |index: u8, downloaded| Chunk {
+            index,
+            start: index as u64 * 1000,
+            end: index as u64 * 1000 + 999,
+            downloaded,
+        };
+        let chunks = [chunk(0, 1000), chunk(1, 200), chunk(2, 0), chunk(3, 900)];
+
+        let replanned = downloader.replan_chunks(&chunks, 8);
+
+        // every byte covered exactly once, and no progress lost
+        assert_eq!(replanned[0].start, 0);
+        for pair in replanned.windows(2) {
+            assert_eq!(pair[0].end + 1, pair[1].start);
+        }
+        assert_eq!(replanned.last().unwrap().end, 3999);
+        let downloaded: u64 = replanned.iter().map(|c| c.downloaded).sum();
+        assert_eq!(downloaded, 2100);
+
+        // finished parts keep their index, so segment files still match
+        let kept: Vec<_> = replanned.iter().filter(|c| c.is_complete()).collect();
+        assert_eq!(kept.len(), 3);
+        assert_eq!((kept[1].index, kept[1].start, kept[1].end), (1, 1000, 1199));
+
+        // 1200-2999 is one missing range shared by 7 chunks, the 100 bytes
+        // at 3900 are too few to split
+        let new: Vec<_> = replanned.iter().filter(|c| !c.is_complete()).collect();
+        assert_eq!(new.len(), 8);
+        assert!(new.iter().all(|c| c.index >= 4 && c.size() >= 100));
+        assert_eq!(new.iter().filter(|c| c.start < 3000).count(), 7);
+
+        // fewer chunks than ranges still covers every range
+        let replanned = downloader.replan_chunks(&chunks, 1);
+        assert_eq!(replanned.iter().filter(|c| !c.is_complete()).count(), 2);
+    }
+
+    #[test]
+    fn test_rebalance_picks_largest_remaining() {
+        let config = ChunkConfig {
+            min_chunk_size: 10,
+            ..ChunkConfig::default()
+        };
+        let downloader = ChunkedDownloader::with_config(config);
+
+        let plan: ChunkPlan = Arc::new(Mutex::new(vec![
+            Chunk { index: 0, start: 0, end: 99, downloaded: 100 },
+            Chunk { index: 1, start: 100, end: 199, downloaded: 80 },
+            Chunk { index: 2, start: 200, end: 299, downloaded: 10 },
+        ]));
+
+        let slot = downloader.rebalance(&plan).unwrap();
+        let chunks = lock_plan(&plan).clone();
+
+        // chunk 2 had the most left (90 bytes) and gave up its back 45
+        assert_eq!(slot, 3);
+        assert_eq!(chunks[2].end, 254);
+        assert_eq!(chunks[3], Chunk { index: 3, start: 255, end: 299, downloaded: 0 });
+    }
+
     #[tokio::test]
     async fn test_resume_detection_no_file() {
         let downloader = ChunkedDownloader::new();
@@ -705,12 +3496,331 @@ mod tests {
         let _ = tokio::fs::remove_file(&file_path).await;
     }
 
+    #[tokio::test]
+    async fn test_batch_failures_are_independent() {
+        let downloader = ChunkedDownloader::new();
+        let temp_dir = std::env::temp_dir();
+
+        // nothing listens on port 1, so every download fails fast
+        let urls = vec![
+            ("http://127.0.0.1:1/a.bin".to_string(), temp_dir.join("batch_a.bin")),
+            ("not a url".to_string(), temp_dir.join("batch_b.bin")),
+            ("http://127.0.0.1:1/c.bin".to_string(), temp_dir.join("batch_c.bin")),
+        ];
+
+        let results = downloader.download_batch(&urls, 2).await;
+
+        // one result per input, each failure reported on its own
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|r| r.is_err()));
+    }
+
+    #[tokio::test]
+    async fn test_batch_dropped_aborts_downloads() {
+        let transport = Arc::new(MockTransport {
+            body_delay: Duration::from_millis(200),
+            ..MockTransport::new(vec![5u8; 4096], true)
+        });
+        let config = ChunkConfig {
+            chunk_count: 1,
+            ..ChunkConfig::default()
+        };
+        let downloader = ChunkedDownloader::with_transport(config, transport.clone());
+        let urls: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|name| {
+                let path = std::env::temp_dir().join(format!("test_batch_dropped_{}.bin", name));
+                ("http://mock.invalid/file.bin".to_string(), path)
+            })
+            .collect();
+
+        // given up on while the first download waits for its body
+        let batch = downloader.download_batch(&urls, 1);
+        assert!(tokio::time::timeout(Duration::from_millis(50), batch).await.is_err());
+
+        // the rest of the batch never starts
+        sleep(Duration::from_millis(600)).await;
+        assert_eq!(transport.requests(), ["HEAD", "0-4095"]);
+
+        for (_, path) in &urls {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_empty() {
+        let downloader = ChunkedDownloader::new();
+        let results = downloader.download_batch(&[], 4).await;
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn test_min_free_space_after() {
+        let path = std::env::temp_dir().join("test_min_free_space.bin");
+
+        let downloader = ChunkedDownloader::with_config(ChunkConfig {
+            min_free_space_after: None,
+            ..ChunkConfig::default()
+        });
+        assert!(downloader.check_space(&path, 1024).is_ok());
+
+        // fits on its own, but not with the reserve on top
+        let downloader = ChunkedDownloader::with_config(ChunkConfig {
+            min_free_space_after: Some(u64::MAX - 512),
+            ..ChunkConfig::default()
+        });
+        assert!(matches!(
+            downloader.check_space(&path, 1024),
+            Err(DownloadError::InsufficientSpace(u64::MAX))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_probe_concurrency_limit() {
+        let transport = Arc::new(MockTransport {
+            head_delay: Duration::from_millis(20),
+            ..MockTransport::new(vec![7u8; 100], false)
+        });
+        let config = ChunkConfig {
+            max_concurrent_probes: Some(4),
+            ..ChunkConfig::default()
+        };
+        let downloader = ChunkedDownloader::with_transport(config, transport.clone());
+
+        let dir = std::env::temp_dir().join("test_probe_concurrency_limit");
+        let urls: Vec<(String, PathBuf)> = (0..50)
+            .map(|i| (format!("http://mock.invalid/{}.bin", i), dir.join(format!("{}.bin", i))))
+            .collect();
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        // 50 downloads start together, but their HEADs go four at a time
+        let results = downloader.download_batch(&urls, 50).await;
+        assert!(results.iter().all(|r| matches!(r, Ok(100))), "{:?}", results);
+        assert_eq!(transport.max_heads_in_flight.load(Ordering::SeqCst), 4);
+
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_buffer_memory_budget() {
+        let body: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let transport = Arc::new(MockTransport::new(body.clone(), true));
+        let config = ChunkConfig {
+            chunk_count: 4,
+            min_chunk_size: 1024,
+            write_buffer_chunks: 4,
+            max_buffer_memory: Some(2048),
+            ..ChunkConfig::default()
+        };
+        let downloader = ChunkedDownloader::with_transport(config, transport);
+
+        let dir = std::env::temp_dir().join("test_buffer_memory_budget");
+        let urls: Vec<(String, PathBuf)> = (0..20)
+            .map(|i| (format!("http://mock.invalid/{}.bin", i), dir.join(format!("{}.bin", i))))
+            .collect();
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        // 80 chunks of 1KB arrive at once, but only two wait for the disk
+        let results = downloader.download_batch(&urls, 20).await;
+        assert!(results.iter().all(|r| matches!(r, Ok(4096))), "{:?}", results);
+        let peak = downloader.peak_buffered_bytes();
+        assert!(peak > 0 && peak <= 2048, "{}", peak);
+
+        for (_, path) in &urls {
+            assert_eq!(tokio::fs::read(path).await.unwrap(), body);
+        }
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_request_timings() {
+        let transport = Arc::new(MockTransport {
+            head_delay: Duration::from_millis(20),
+            body_delay: Duration::from_millis(40),
+            ..MockTransport::new(vec![3u8; 4096], true)
+        });
+        // no rebalancing, so each download makes exactly one request per chunk
+        let config = ChunkConfig {
+            chunk_count: 2,
+            min_chunk_size: 1024,
+            rebalance_chunks: false,
+            ..ChunkConfig::default()
+        };
+        let downloader = ChunkedDownloader::with_transport(config, transport);
+        let url = "http://mock.invalid/file.bin";
+        let path = std::env::temp_dir().join("test_request_timings.bin");
+        let _ = tokio::fs::remove_file(&path).await;
+
+        let plan = downloader.inspect(url, &path).await.unwrap();
+        assert!(plan.head_latency >= Duration::from_millis(20));
+
+        // concurrent downloads keep their timings apart
+        let other = std::env::temp_dir().join("test_request_timings_other.bin");
+        let (outcome, other_outcome) = tokio::join!(
+            downloader.download_outcome(url, &path),
+            downloader.download_outcome(url, &other)
+        );
+        let outcome = outcome.unwrap();
+        assert_eq!(other_outcome.unwrap().timings.len(), 2);
+
+        // the headers come straight away, the body only after the delay
+        let mut timings = outcome.timings.clone();
+        timings.sort_by_key(|t| t.chunk);
+        assert_eq!(timings.iter().map(|t| t.chunk).collect::<Vec<_>>(), [0, 1]);
+        for timing in &timings {
+            let first_byte = timing.first_byte.unwrap();
+            assert!(first_byte >= Duration::from_millis(40), "{:?}", timing);
+            assert!(timing.headers < first_byte, "{:?}", timing);
+        }
+
+        let slowest = outcome.slowest_start().unwrap();
+        assert!(timings.contains(&slowest));
+        assert!(timings.iter().all(|t| t.first_byte <= slowest.first_byte));
+
+        let _ = tokio::fs::remove_file(&path).await;
+        let _ = tokio::fs::remove_file(&other).await;
+    }
+
+    #[tokio::test]
+    async fn test_mock_transport_chunked_download() {
+        let body: Vec<u8> = (0..8192u32).map(|i| (i % 251) as u8).collect();
+        let transport = Arc::new(MockTransport::new(body.clone(), true));
+        let config = ChunkConfig {
+            chunk_count: 4,
+            min_chunk_size: 1024,
+            rebalance_chunks: false,
+            ..ChunkConfig::default()
+        };
+        let downloader = ChunkedDownloader::with_transport(config, transport.clone());
+
+        let path = std::env::temp_dir().join("test_mock_chunked.bin");
+        let _ = tokio::fs::remove_file(&path).await;
+
+        let bytes = downloader.download("http://mock.invalid/file.bin", &path).await.unwrap();
+        assert_eq!(bytes, 8192);
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), body);
+
+        let mut requests = transport.requests();
+        requests.sort();
+        assert_eq!(requests, ["0-2047", "2048-4095", "4096-6143", "6144-8191", "HEAD"]);
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_mock_transport_stall_retried() {
+        let body: Vec<u8> = (0..4096u32).map(|i| (i % 233) as u8).collect();
+        let transport = Arc::new(MockTransport::new(body.clone(), true));
+        transport.stall_first.store(true, Ordering::SeqCst);
+
+        let config = ChunkConfig {
+            chunk_count: 1,
+            stall_timeout_ms: Some(50),
+            retry_delay_ms: 10,
+            ..ChunkConfig::default()
+        };
+        let downloader = ChunkedDownloader::with_transport(config, transport.clone());
+
+        let path = std::env::temp_dir().join("test_mock_stall.bin");
+        let _ = tokio::fs::remove_file(&path).await;
+
+        let outcome = downloader
+            .download_outcome("http://mock.invalid/file.bin", &path)
+            .await
+            .unwrap();
+        assert_eq!(outcome.bytes_transferred, 4096);
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), body);
+        assert_eq!(outcome.retries, 1);
+
+        // the retry carried on after the half that arrived
+        assert_eq!(transport.requests(), ["HEAD", "0-4095", "2048-4095"]);
+
+        // the next download on the same downloader counts its own retries
+        transport.stall_first.store(true, Ordering::SeqCst);
+        let outcome = downloader
+            .download_outcome("http://mock.invalid/file.bin", &path)
+            .await
+            .unwrap();
+        assert_eq!(outcome.retries, 1);
+
+        let url = "http://mock.invalid/file.bin".to_string();
+        let mut download = Download::new(crate::DownloadId::new(27), url);
+        download.record_outcome(&outcome);
+        download.record_outcome(&outcome);
+        assert_eq!(download.retry_count(), 2);
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_mock_transport_416_restarts_single() {
+        let body: Vec<u8> = (0..4096u32).map(|i| (i % 227) as u8).collect();
+        let transport = Arc::new(MockTransport::new(body.clone(), false));
+        let downloader =
+            ChunkedDownloader::with_transport(ChunkConfig::default(), transport.clone());
+
+        let path = std::env::temp_dir().join("test_mock_416.bin");
+        tokio::fs::write(&path, &body[..1000]).await.unwrap();
+
+        let outcome = downloader
+            .download_resumable_outcome("http://mock.invalid/file.bin", &path)
+            .await
+            .unwrap();
+        assert_eq!(outcome.kind, OutcomeKind::Fresh);
+        assert_eq!(outcome.bytes_transferred, 4096);
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), body);
+        assert_eq!(transport.requests(), ["HEAD", "1000-", "GET"]);
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_single_stream_upgrades_to_chunks() {
+        let body: Vec<u8> = (0..4096u32).map(|i| (i % 233) as u8).collect();
+        let transport = Arc::new(MockTransport {
+            hide_ranges: AtomicBool::new(true),
+            fail_plain_get: true,
+            ..MockTransport::new(body.clone(), true)
+        });
+        let config = ChunkConfig {
+            chunk_count: 2,
+            min_chunk_size: 1024,
+            ..ChunkConfig::default()
+        };
+        let downloader = ChunkedDownloader::with_transport(config, transport.clone());
+        let path = std::env::temp_dir().join("test_mock_upgrade.bin");
+
+        // the HEAD wrongly says no ranges, the single stream fails, and a
+        // ranged GET for the first byte shows chunking works after all
+        let outcome = downloader
+            .download_outcome("http://mock.invalid/file.bin", &path)
+            .await
+            .unwrap();
+        assert_eq!(outcome.bytes_transferred, 4096);
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), body);
+        let requests = transport.requests();
+        assert_eq!(requests[..3], ["HEAD", "GET", "0-0"]);
+        assert_eq!(requests.len(), 5, "{:?}", requests);
+
+        // without ranges the single stream's error is reported
+        let transport = Arc::new(MockTransport {
+            fail_plain_get: true,
+            ..MockTransport::new(body, false)
+        });
+        let downloader = ChunkedDownloader::with_transport(ChunkConfig::default(), transport);
+        let result = downloader.download("http://mock.invalid/file.bin", &path).await;
+        assert!(matches!(result, Err(DownloadError::HttpError(503))), "{:?}", result);
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
     #[test]
     fn test_retry_config() {
         let config = ChunkConfig::default();
         assert_eq!(config.max_retries, 3);
         assert_eq!(config.retry_delay_ms, 1000);
         assert!(config.exponential_backoff);
+        assert!(config.preallocate);
     }
 
     #[test]
@@ -721,6 +3831,7 @@ mod tests {
             max_retries: 5,
             retry_delay_ms: 500,
             exponential_backoff: false,
+            ..ChunkConfig::default()
         };
 
         let downloader = ChunkedDownloader::with_config(config.clone());
@@ -729,6 +3840,33 @@ mod tests {
         assert!(!downloader.config.exponential_backoff);
     }
 
+    #[test]
+    fn test_per_download_retry_override() {
+        use crate::DownloadId;
+
+        let global = ChunkConfig::default();
+        let url = "https://example.com/a.iso".to_string();
+        let mut download = Download::new(DownloadId::new(1), url);
+
+        // no override keeps the global policy
+        let config = global.for_download(&download);
+        assert_eq!(config.max_retries, 3);
+        assert_eq!(config.retry_delay_ms, 1000);
+
+        // flaky mirror gets more patience
+        download.set_max_retries(10);
+        download.set_retry_delay_ms(250);
+
+        let config = global.for_download(&download);
+        assert_eq!(config.max_retries, 10);
+        assert_eq!(config.retry_delay_ms, 250);
+        assert_eq!(config.chunk_count, global.chunk_count);
+        assert_eq!(config.deadline_ms, None);
+
+        download.set_deadline_ms(600_000);
+        assert_eq!(global.for_download(&download).deadline_ms, Some(600_000));
+    }
+
     #[test]
     fn test_exponential_backoff_delays() {
         // simulate exponential backoff calculation
@@ -0,0 +1,396 @@
+//! HTTP client configuration shared by the downloaders
+
+use crate::{Download, DownloadError};
+use hyper::client::connect::dns::Name;
+use reqwest::cookie::Jar;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::{Client, Url};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// User-Agent sent when none is configured
+pub const DEFAULT_USER_AGENT: &str = "FluxDM/0.1.0";
+
+/// Which IP versions connections may use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IpPreference {
+    /// Use every address a host resolves to
+    ///
+    /// IPv6 is tried first, with IPv4 started alongside if it hasn't
+    /// connected after 300ms ("happy eyeballs").
+    #[default]
+    Auto,
+    /// Only connect over IPv4, e.g. to work around a broken IPv6 route
+    V4Only,
+    /// Only connect over IPv6
+    V6Only,
+}
+
+impl IpPreference {
+    /// Whether connecting to `ip` is allowed
+    pub fn allows(self, ip: IpAddr) -> bool {
+        match self {
+            IpPreference::Auto => true,
+            IpPreference::V4Only => ip.is_ipv4(),
+            IpPreference::V6Only => ip.is_ipv6(),
+        }
+    }
+}
+
+/// Which HTTP versions connections may use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HttpVersionPref {
+    /// HTTP/2 where TLS negotiates it (ALPN), HTTP/1.1 otherwise
+    #[default]
+    Auto,
+    /// Only HTTP/1.1, one connection per concurrent request
+    Http1Only,
+    /// HTTP/2 without negotiation, failing against servers that lack it
+    ///
+    /// Also the only way to get HTTP/2 over plain `http://` (h2c).
+    Http2Prior,
+}
+
+/// Settings for the HTTP client used by a downloader
+///
+/// The settings apply for the downloader's whole lifetime, including every
+/// per-chunk connection.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Host name overrides as `(host, address)` pairs
+    ///
+    /// Requests to `host` connect to `address` instead of resolving it, while
+    /// still sending the original Host header and TLS server name. The
+    /// address is an IP, optionally with a port; the port is ignored and the
+    /// one from the URL is used, as DNS has no notion of ports.
+    pub resolve: Vec<(String, String)>,
+    /// User-Agent header to send instead of `DEFAULT_USER_AGENT`
+    ///
+    /// Some hosts block unknown agents or only offer ranges to browsers.
+    pub user_agent: Option<String>,
+    /// Whether to keep cookies set by responses and send them on later requests
+    ///
+    /// The jar belongs to the client, so every chunk connection of every
+    /// download made with it shares one session.
+    pub cookie_store: bool,
+    /// Cookies to start the jar with, as `(url, cookie)` pairs
+    ///
+    /// `cookie` uses the Set-Cookie format, e.g. `"session=abc; Path=/"`, and
+    /// is sent to requests matching `url`. Requires `cookie_store`.
+    pub cookies: Vec<(String, String)>,
+    /// Idle connections kept open per host for reuse
+    ///
+    /// Reusing a connection skips the TCP and TLS handshakes, which dominate
+    /// when fetching many small files from one host. This only caps idle
+    /// connections, not how many may be open at once. The chunked downloader
+    /// raises it to at least `chunk_count`, so a retried chunk finds a warm
+    /// connection instead of connecting again.
+    pub pool_max_idle_per_host: usize,
+    /// How long an idle connection is kept, `None` keeps it until the server closes it
+    pub pool_idle_timeout_ms: Option<u64>,
+    /// Which IP versions to connect over
+    ///
+    /// Filters the addresses host names resolve to; a host with none left
+    /// fails with a network error. URLs with a literal IP and `resolve`
+    /// overrides are used as given. The happy eyeballs delay under `Auto`
+    /// isn't configurable.
+    pub ip_version: IpPreference,
+    /// Which HTTP versions to speak
+    ///
+    /// Over HTTP/2 a chunked download's connections become streams
+    /// multiplexed over one connection per host, which some servers serve
+    /// faster than parallel HTTP/1.1 connections and others slower.
+    /// `DownloadPlan::http_version` shows what a server ended up with.
+    pub http_version: HttpVersionPref,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            resolve: Vec::new(),                 // resolve hosts normally
+            user_agent: None,                    // DEFAULT_USER_AGENT
+            cookie_store: true,                  // carry login sessions across requests
+            cookies: Vec::new(),                 // start with an empty jar
+            pool_max_idle_per_host: 16,          // a chunked download's connections
+            pool_idle_timeout_ms: Some(90_000),  // close after 90s unused
+            ip_version: IpPreference::Auto,      // whichever connects first
+            http_version: HttpVersionPref::Auto, // whatever TLS negotiates
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Connects requests for `host` to `addr` instead of resolving it
+    ///
+    /// A malformed address is reported when the downloader is created.
+    pub fn resolve(mut self, host: impl Into<String>, addr: impl Into<String>) -> Self {
+        self.resolve.push((host.into(), addr.into()));
+        self
+    }
+
+    /// Sends `user_agent` instead of the FluxDM one
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Only connects over the given IP versions
+    pub fn ip_version(mut self, ip_version: IpPreference) -> Self {
+        self.ip_version = ip_version;
+        self
+    }
+
+    /// Only speaks the given HTTP versions
+    pub fn http_version(mut self, http_version: HttpVersionPref) -> Self {
+        self.http_version = http_version;
+        self
+    }
+
+    /// Adds a cookie to the initial jar, sent to requests matching `url`
+    ///
+    /// A malformed URL is reported when the downloader is created.
+    pub fn cookie(mut self, url: impl Into<String>, cookie: impl Into<String>) -> Self {
+        self.cookies.push((url.into(), cookie.into()));
+        self
+    }
+
+    /// Returns a copy of this config with the download's overrides applied
+    pub fn for_download(&self, download: &Download) -> Self {
+        let mut config = self.clone();
+
+        if let Some(user_agent) = download.user_agent() {
+            config.user_agent = Some(user_agent.to_string());
+        }
+
+        config
+    }
+
+    /// Returns a copy of this config keeping at least `connections` idle per host
+    pub(crate) fn with_pool_for(&self, connections: usize) -> Self {
+        let mut config = self.clone();
+        config.pool_max_idle_per_host = config.pool_max_idle_per_host.max(connections);
+        config
+    }
+
+    /// Builds a reqwest client with these settings
+    pub(crate) fn build(&self) -> Result<Client, DownloadError> {
+        let user_agent = self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+        let mut builder = Client::builder()
+            .user_agent(user_agent)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout_ms.map(Duration::from_millis));
+
+        builder = match self.http_version {
+            HttpVersionPref::Auto => builder,
+            HttpVersionPref::Http1Only => builder.http1_only(),
+            HttpVersionPref::Http2Prior => builder.http2_prior_knowledge(),
+        };
+
+        if self.ip_version != IpPreference::Auto {
+            builder = builder.dns_resolver(Arc::new(FilteredResolver(self.ip_version)));
+        }
+
+        for (host, addr) in &self.resolve {
+            if host.is_empty() {
+                return Err(DownloadError::InvalidConfig(
+                    "Empty host in resolve override".to_string(),
+                ));
+            }
+
+            builder = builder.resolve(host, parse_addr(host, addr)?);
+        }
+
+        if self.cookie_store {
+            builder = builder.cookie_provider(Arc::new(self.cookie_jar()?));
+        } else if !self.cookies.is_empty() {
+            return Err(DownloadError::InvalidConfig(
+                "Cookies given but the cookie store is disabled".to_string(),
+            ));
+        }
+
+        builder
+            .build()
+            .map_err(|e| DownloadError::InvalidConfig(e.to_string()))
+    }
+
+    /// Creates the cookie jar seeded with `cookies`
+    fn cookie_jar(&self) -> Result<Jar, DownloadError> {
+        let jar = Jar::default();
+
+        for (url, cookie) in &self.cookies {
+            let url = Url::parse(url).map_err(|e| {
+                DownloadError::InvalidConfig(format!("Invalid cookie URL {:?}: {}", url, e))
+            })?;
+            jar.add_cookie_str(cookie, &url);
+        }
+
+        Ok(jar)
+    }
+}
+
+/// System resolver dropping the addresses an `IpPreference` doesn't allow
+struct FilteredResolver(IpPreference);
+
+impl Resolve for FilteredResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let preference = self.0;
+
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| preference.allows(addr.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(format!("No {:?} address for {}", preference, name.as_str()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Parses an override address, accepting a bare IP or IP:port
+fn parse_addr(host: &str, addr: &str) -> Result<SocketAddr, DownloadError> {
+    let addr = addr.trim();
+
+    addr.parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, 0))
+        .or_else(|_| addr.parse::<SocketAddr>())
+        .map_err(|_| {
+            DownloadError::InvalidConfig(format!("Invalid address {:?} for host {}", addr, host))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DownloadId;
+
+    #[test]
+    fn test_default_builds() {
+        assert!(ClientConfig::default().build().is_ok());
+    }
+
+    #[test]
+    fn test_user_agent_override() {
+        let config = ClientConfig::default().user_agent("Mozilla/5.0");
+        assert_eq!(config.user_agent.as_deref(), Some("Mozilla/5.0"));
+        assert!(config.build().is_ok());
+
+        // a per-download agent wins over the shared one
+        let mut download = Download::new(DownloadId::new(1), "https://example.com/a".to_string());
+        download.set_user_agent("curl/8.0".to_string());
+        let config = config.for_download(&download);
+        assert_eq!(config.user_agent.as_deref(), Some("curl/8.0"));
+
+        // and without one the shared agent is kept
+        let download = Download::new(DownloadId::new(2), "https://example.com/b".to_string());
+        let config = ClientConfig::default().for_download(&download);
+        assert_eq!(config.user_agent, None);
+    }
+
+    #[test]
+    fn test_http_version_builds() {
+        for version in [
+            HttpVersionPref::Auto,
+            HttpVersionPref::Http1Only,
+            HttpVersionPref::Http2Prior,
+        ] {
+            let config = ClientConfig::default().http_version(version);
+            assert_eq!(config.http_version, version);
+            assert!(config.build().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_user_agent_rejects_invalid_header() {
+        let config = ClientConfig::default().user_agent("bad\nagent");
+
+        assert!(matches!(
+            config.build(),
+            Err(DownloadError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_cookie_config() {
+        let config = ClientConfig::default().cookie("https://example.com/", "session=abc");
+        assert!(config.build().is_ok());
+
+        let bad_url = ClientConfig::default().cookie("not a url", "session=abc");
+        assert!(matches!(
+            bad_url.build(),
+            Err(DownloadError::InvalidConfig(_))
+        ));
+
+        let no_store = ClientConfig {
+            cookie_store: false,
+            ..ClientConfig::default().cookie("https://example.com/", "session=abc")
+        };
+        assert!(matches!(
+            no_store.build(),
+            Err(DownloadError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_pool_for_connections() {
+        let config = ClientConfig {
+            pool_max_idle_per_host: 4,
+            pool_idle_timeout_ms: None,
+            ..ClientConfig::default()
+        };
+        assert!(config.build().is_ok());
+
+        // raised to fit every connection, never lowered
+        assert_eq!(config.with_pool_for(8).pool_max_idle_per_host, 8);
+        assert_eq!(config.with_pool_for(2).pool_max_idle_per_host, 4);
+    }
+
+    #[test]
+    fn test_ip_preference() {
+        let v4: IpAddr = "192.0.2.1".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+
+        assert!(IpPreference::Auto.allows(v4) && IpPreference::Auto.allows(v6));
+        assert!(IpPreference::V4Only.allows(v4) && !IpPreference::V4Only.allows(v6));
+        assert!(!IpPreference::V6Only.allows(v4) && IpPreference::V6Only.allows(v6));
+
+        let config = ClientConfig::default().ip_version(IpPreference::V4Only);
+        assert!(config.build().is_ok());
+    }
+
+    #[test]
+    fn test_resolve_accepts_ip_with_or_without_port() {
+        let config = ClientConfig::default()
+            .resolve("cdn.example.com", "203.0.113.7")
+            .resolve("mirror.example.com", "[2001:db8::1]:443");
+
+        assert_eq!(config.resolve.len(), 2);
+        assert!(config.build().is_ok());
+    }
+
+    #[test]
+    fn test_resolve_rejects_malformed_address() {
+        let config = ClientConfig::default().resolve("cdn.example.com", "not-an-ip");
+
+        match config.build() {
+            Err(DownloadError::InvalidConfig(msg)) => {
+                assert!(msg.contains("not-an-ip") && msg.contains("cdn.example.com"))
+            }
+            other => panic!("expected InvalidConfig, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_resolve_rejects_empty_host() {
+        let config = ClientConfig::default().resolve("", "127.0.0.1");
+
+        assert!(matches!(
+            config.build(),
+            Err(DownloadError::InvalidConfig(_))
+        ));
+    }
+}
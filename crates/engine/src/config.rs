@@ -0,0 +1,257 @@
+//! Settings shared by every download, gathered in one place
+
+use crate::{
+    sanitize_filename, ChunkConfig, ChunkedDownloader, ClientConfig, Download, DownloadError,
+    PathTemplate,
+};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Settings for a whole download session, built with `FluxConfig::builder`
+///
+/// Collects what applies to every download: where files go, how many run
+/// at once, and the chunk and client defaults. Each download's
+/// `ChunkConfig` starts from `chunk_config_for`. Settings that depend on
+/// each other are checked once, when the config is built.
+#[derive(Debug, Clone)]
+pub struct FluxConfig {
+    download_dir: PathBuf,
+    max_concurrent_downloads: usize,
+    path_template: Option<PathTemplate>,
+    chunk: ChunkConfig,
+    client: ClientConfig,
+}
+
+impl FluxConfig {
+    /// Starts a config that saves downloads into `download_dir`
+    pub fn builder(download_dir: impl Into<PathBuf>) -> FluxConfigBuilder {
+        FluxConfigBuilder {
+            download_dir: download_dir.into(),
+            max_concurrent_downloads: 3,
+            path_template: None,
+            chunk: ChunkConfig::default(),
+            client: ClientConfig::default(),
+        }
+    }
+
+    /// Returns the directory downloads are saved into
+    pub fn download_dir(&self) -> &Path {
+        &self.download_dir
+    }
+
+    /// Returns the directory partial files are kept in, if not next to the destination
+    pub fn temp_dir(&self) -> Option<&Path> {
+        self.chunk.temp_dir.as_deref()
+    }
+
+    /// Returns how many downloads may run at once
+    pub fn max_concurrent_downloads(&self) -> usize {
+        self.max_concurrent_downloads
+    }
+
+    /// Returns the template downloads are placed by, if any
+    pub fn path_template(&self) -> Option<&PathTemplate> {
+        self.path_template.as_ref()
+    }
+
+    /// Returns the default chunk settings
+    pub fn chunk_config(&self) -> &ChunkConfig {
+        &self.chunk
+    }
+
+    /// Returns the client settings
+    pub fn client_config(&self) -> &ClientConfig {
+        &self.client
+    }
+
+    /// Returns the chunk settings for `download`, with its overrides applied
+    pub fn chunk_config_for(&self, download: &Download) -> ChunkConfig {
+        self.chunk.for_download(download)
+    }
+
+    /// Creates a downloader for `download` with these settings
+    pub fn downloader_for(&self, download: &Download) -> Result<ChunkedDownloader, DownloadError> {
+        ChunkedDownloader::with_client_config(
+            self.chunk_config_for(download),
+            &self.client.for_download(download),
+        )
+    }
+
+    /// Returns where `download`, saved as `filename`, goes
+    ///
+    /// Uses the path template if one is set, and otherwise puts the file
+    /// straight into the download directory. `now` fills in `{date}`.
+    pub fn path_for(&self, download: &Download, filename: &str, now: SystemTime) -> PathBuf {
+        match &self.path_template {
+            Some(template) => template.render(&self.download_dir, download, filename, now),
+            None => self.download_dir.join(sanitize_filename(filename)),
+        }
+    }
+}
+
+/// Builds a `FluxConfig`, see `FluxConfig::builder`
+#[derive(Debug, Clone)]
+pub struct FluxConfigBuilder {
+    download_dir: PathBuf,
+    max_concurrent_downloads: usize,
+    path_template: Option<String>,
+    chunk: ChunkConfig,
+    client: ClientConfig,
+}
+
+impl FluxConfigBuilder {
+    /// Keeps partial files in `dir` instead of next to their destination
+    pub fn temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.chunk.temp_dir = Some(dir.into());
+        self
+    }
+
+    /// Lets `max` downloads run at once (default 3)
+    pub fn max_concurrent_downloads(mut self, max: usize) -> Self {
+        self.max_concurrent_downloads = max;
+        self
+    }
+
+    /// Places downloads by `template`, e.g. `{category}/{filename}`
+    ///
+    /// A malformed template is reported by `build`.
+    pub fn path_template(mut self, template: impl Into<String>) -> Self {
+        self.path_template = Some(template.into());
+        self
+    }
+
+    /// Uses `chunk` as the default chunk settings
+    ///
+    /// A temp dir set on the builder before or after this wins over the
+    /// one in `chunk`.
+    pub fn chunk_config(mut self, chunk: ChunkConfig) -> Self {
+        let temp_dir = self.chunk.temp_dir.take();
+        self.chunk = chunk;
+        if temp_dir.is_some() {
+            self.chunk.temp_dir = temp_dir;
+        }
+        self
+    }
+
+    /// Uses `client` for the HTTP client settings
+    pub fn client_config(mut self, client: ClientConfig) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Checks the settings and builds the config
+    ///
+    /// Fails with `DownloadError::InvalidConfig` if the download directory
+    /// is empty, the temp dir isn't an existing directory, no downloads or
+    /// chunks may run, the path template or client settings are malformed,
+    /// or `chunk_alignment` is zero.
+    pub fn build(self) -> Result<FluxConfig, DownloadError> {
+        let invalid = |reason: String| Err(DownloadError::InvalidConfig(reason));
+
+        if self.download_dir.as_os_str().is_empty() {
+            return invalid("Download directory is empty".to_string());
+        }
+        if let Some(dir) = &self.chunk.temp_dir {
+            if !dir.is_dir() {
+                return invalid(format!("Temp dir {} isn't a directory", dir.display()));
+            }
+        }
+        if self.max_concurrent_downloads == 0 {
+            return invalid("At least one download must be allowed to run".to_string());
+        }
+        if self.chunk.chunk_count == 0 {
+            return invalid("Chunk count must be at least 1".to_string());
+        }
+        if self.chunk.chunk_alignment == Some(0) {
+            return invalid("Chunk alignment must be at least 1 byte".to_string());
+        }
+
+        let path_template = self
+            .path_template
+            .as_deref()
+            .map(PathTemplate::parse)
+            .transpose()?;
+
+        // building a client is cheap, and reports bad overrides and cookies now
+        self.client.build()?;
+
+        Ok(FluxConfig {
+            download_dir: self.download_dir,
+            max_concurrent_downloads: self.max_concurrent_downloads,
+            path_template,
+            chunk: self.chunk,
+            client: self.client,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DownloadId;
+
+    #[test]
+    fn test_build_and_derive() {
+        let temp_dir = std::env::temp_dir();
+        let config = FluxConfig::builder("/downloads")
+            .max_concurrent_downloads(5)
+            .chunk_config(ChunkConfig {
+                chunk_count: 8,
+                ..ChunkConfig::default()
+            })
+            .temp_dir(&temp_dir)
+            .path_template("{category}/{filename}")
+            .build()
+            .unwrap();
+
+        assert_eq!(config.download_dir(), Path::new("/downloads"));
+        assert_eq!(config.temp_dir(), Some(temp_dir.as_path()));
+        assert_eq!(config.max_concurrent_downloads(), 5);
+
+        // per-download settings start from the shared ones
+        let mut download = Download::new(DownloadId::new(1), "https://a.example/x.iso".to_string());
+        download.set_max_retries(9);
+        let chunk = config.chunk_config_for(&download);
+        assert_eq!(chunk.chunk_count, 8);
+        assert_eq!(chunk.max_retries, 9);
+        assert_eq!(chunk.temp_dir.as_deref(), Some(temp_dir.as_path()));
+        assert!(config.downloader_for(&download).is_ok());
+
+        let now = SystemTime::now();
+        assert_eq!(
+            config.path_for(&download, "x.iso", now),
+            Path::new("/downloads/Uncategorized/x.iso")
+        );
+        let plain = FluxConfig::builder("/downloads").build().unwrap();
+        assert_eq!(
+            plain.path_for(&download, "a/b.iso", now),
+            Path::new("/downloads/a_b.iso")
+        );
+    }
+
+    #[test]
+    fn test_build_rejects_bad_settings() {
+        let missing = std::env::temp_dir().join("fluxdm_no_such_temp_dir");
+        let builders = [
+            FluxConfig::builder(""),
+            FluxConfig::builder("/downloads").temp_dir(missing),
+            FluxConfig::builder("/downloads").max_concurrent_downloads(0),
+            FluxConfig::builder("/downloads").path_template("{nope}"),
+            FluxConfig::builder("/downloads")
+                .client_config(ClientConfig::default().resolve("host.example", "not an ip")),
+            FluxConfig::builder("/downloads").chunk_config(ChunkConfig {
+                chunk_alignment: Some(0),
+                ..ChunkConfig::default()
+            }),
+        ];
+
+        for builder in builders {
+            let debug = format!("{:?}", builder);
+            assert!(
+                matches!(builder.build(), Err(DownloadError::InvalidConfig(_))),
+                "{}",
+                debug
+            );
+        }
+    }
+}
@@ -0,0 +1,838 @@
+//! Disk space and file placement helpers
+
+use crate::checksum::verify_checksum;
+use crate::filename::numbered_filename;
+use crate::{Checksum, Chunk, DownloadError};
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// Sizes a file for download, reserving its blocks when `reserve` is set
+///
+/// `set_len` alone creates a sparse file, so running out of space only shows
+/// up halfway through. Reserving up front turns that into an early
+/// `DownloadError::InsufficientSpace`. Falls back to `set_len` where
+/// reservation isn't supported.
+pub(crate) async fn preallocate(
+    file: &File,
+    size: u64,
+    reserve: bool,
+) -> Result<(), DownloadError> {
+    if reserve && size > 0 && reserve_blocks(file, size).await? {
+        return Ok(());
+    }
+
+    file.set_len(size)
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))
+}
+
+/// Reserves blocks with posix_fallocate, returns false if unsupported
+#[cfg(target_os = "linux")]
+async fn reserve_blocks(file: &File, size: u64) -> Result<bool, DownloadError> {
+    use std::os::unix::io::AsRawFd;
+
+    let std_file = file
+        .try_clone()
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))?
+        .into_std()
+        .await;
+
+    // fallocate can take a while on big files, keep it off the runtime
+    let code = tokio::task::spawn_blocking(move || unsafe {
+        libc::posix_fallocate(std_file.as_raw_fd(), 0, size as libc::off_t)
+    })
+    .await
+    .map_err(|e| DownloadError::FileError(format!("Task failed: {}", e)))?;
+
+    // posix_fallocate returns the error number instead of setting errno
+    match code {
+        0 => Ok(true),
+        libc::ENOSPC | libc::EFBIG => Err(DownloadError::InsufficientSpace(size)),
+        libc::EOPNOTSUPP | libc::EINVAL => Ok(false), // filesystem can't do it
+        other => Err(DownloadError::FileError(
+            std::io::Error::from_raw_os_error(other).to_string(),
+        )),
+    }
+}
+
+/// Reservation isn't available on this platform
+#[cfg(not(target_os = "linux"))]
+async fn reserve_blocks(_file: &File, _size: u64) -> Result<bool, DownloadError> {
+    Ok(false)
+}
+
+/// Checks that a finished download's file is exactly `expected` bytes long
+///
+/// Catches chunks that were wrongly treated as already downloaded, which
+/// would otherwise leave a short or oversized file behind a successful result.
+pub(crate) async fn check_length(path: &Path, expected: u64) -> Result<(), DownloadError> {
+    let actual = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))?
+        .len();
+
+    if actual != expected {
+        return Err(DownloadError::LengthMismatch { expected, actual });
+    }
+
+    Ok(())
+}
+
+/// Returns the path of the segment file for chunk `index` of `path`
+///
+/// Segments sit next to the destination as `<name>.part<index>`.
+pub(crate) fn segment_path(path: &Path, index: u8) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".part{}", index));
+    PathBuf::from(name)
+}
+
+/// Returns the path of the file recording the chunk layout of `path`
+///
+/// It sits next to the destination as `<name>.chunks`, apart from the
+/// `<name>.progress` a `ProgressFile` may write there.
+pub(crate) fn layout_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".chunks");
+    PathBuf::from(name)
+}
+
+/// Records the chunk layout of a download into a single file
+///
+/// One `index start end downloaded` line per chunk. Only progress known to
+/// be on disk should be recorded, as a resume trusts it. The file is
+/// replaced through a temporary file and a rename, so a crash mid-write
+/// leaves the previous layout rather than a torn one.
+pub(crate) async fn save_layout(path: &Path, chunks: &[Chunk]) -> Result<(), DownloadError> {
+    let lines: String = chunks
+        .iter()
+        .map(|c| format!("{} {} {} {}\n", c.index, c.start, c.end, c.downloaded))
+        .collect();
+
+    let layout = layout_path(path);
+    let mut temp = layout.as_os_str().to_owned();
+    temp.push(".tmp");
+
+    tokio::fs::write(&temp, lines)
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))?;
+    tokio::fs::rename(&temp, &layout)
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))
+}
+
+/// Reads the chunk layout recorded by `save_layout`
+///
+/// Returns None if nothing was recorded. Lines that can't be parsed are
+/// left out, so the layout no longer covers the file and the download
+/// starts over.
+pub(crate) async fn load_layout(path: &Path) -> Option<Vec<Chunk>> {
+    let recorded = tokio::fs::read_to_string(layout_path(path)).await.ok()?;
+
+    let chunks = recorded
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let chunk = Chunk {
+                index: fields.next()?.parse().ok()?,
+                start: fields.next()?.parse().ok()?,
+                end: fields.next()?.parse().ok()?,
+                downloaded: fields.next()?.parse().ok()?,
+            };
+            let valid = chunk.end >= chunk.start && chunk.downloaded <= chunk.size();
+            (valid && fields.next().is_none()).then_some(chunk)
+        })
+        .collect();
+
+    Some(chunks)
+}
+
+/// Concatenates the chunks' segment files into `path`, then deletes them
+///
+/// Segments are written in byte order; only the first `size()` bytes of each
+/// are used.
+pub(crate) async fn merge_segments(path: &Path, chunks: &[Chunk]) -> Result<(), DownloadError> {
+    let mut ordered = chunks.to_vec();
+    ordered.sort_by_key(|c| c.start);
+
+    let mut output = File::create(path)
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+    for chunk in &ordered {
+        let segment = File::open(segment_path(path, chunk.index))
+            .await
+            .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+        tokio::io::copy(&mut segment.take(chunk.size()), &mut output)
+            .await
+            .map_err(|e| DownloadError::FileError(e.to_string()))?;
+    }
+
+    output
+        .flush()
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+    for chunk in &ordered {
+        tokio::fs::remove_file(segment_path(path, chunk.index))
+            .await
+            .map_err(|e| DownloadError::FileError(e.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Deletes leftover segments numbered `first` and up
+///
+/// Splits from an earlier run leave segments past the initial chunk count;
+/// their bytes aren't reused, and stale data must not leak into a merge.
+pub(crate) async fn discard_segments_from(path: &Path, first: usize) {
+    for index in first..=u8::MAX as usize {
+        let segment = segment_path(path, index as u8);
+
+        // split indices are handed out in order, so the first gap ends them
+        if tokio::fs::remove_file(&segment).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Most numbered names tried by `claim_free_name` before giving up
+const MAX_NUMBERED_NAMES: u32 = 1000;
+
+/// Creates an empty file called `name` in `dir`, numbering the name if it's taken
+///
+/// Returns the new file's path. Creating the file claims the name, so two
+/// downloads of the same file at once don't both pick it.
+pub(crate) async fn claim_free_name(dir: &Path, name: &str) -> Result<PathBuf, DownloadError> {
+    for n in 0..=MAX_NUMBERED_NAMES {
+        let path = match n {
+            0 => dir.join(name),
+            n => dir.join(numbered_filename(name, n)),
+        };
+
+        match File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+        {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(DownloadError::FileError(e.to_string())),
+        }
+    }
+
+    Err(DownloadError::FileError(format!(
+        "No free name for {} in {}",
+        name,
+        dir.display()
+    )))
+}
+
+/// Moves a finished file into `to_dir`, returning its new path
+///
+/// Links the file in under its new name when possible, which fails rather
+/// than replace a file that's already there. Across filesystems, or where
+/// hard links aren't supported, it checks for an existing file and then
+/// copies to a temporary name inside `to_dir` and renames that into place,
+/// so the destination never shows a half-copied file; a file created at the
+/// destination between the check and the rename is overwritten.
+pub async fn move_file(from: &Path, to_dir: &Path) -> Result<PathBuf, DownloadError> {
+    let file_name = from
+        .file_name()
+        .ok_or_else(|| DownloadError::FileError(format!("No file name in {}", from.display())))?;
+    let dest = to_dir.join(file_name);
+    let exists =
+        || DownloadError::FileError(format!("Destination already exists: {}", dest.display()));
+
+    tokio::fs::create_dir_all(to_dir)
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+    match tokio::fs::hard_link(from, &dest).await {
+        Ok(()) => {
+            tokio::fs::remove_file(from)
+                .await
+                .map_err(|e| DownloadError::FileError(e.to_string()))?;
+            return Ok(dest);
+        }
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(exists()),
+        Err(_) => {} // another filesystem, or no hard links there
+    }
+
+    if tokio::fs::try_exists(&dest).await.unwrap_or(false) {
+        return Err(exists());
+    }
+
+    move_into_place(from, &dest).await?;
+
+    Ok(dest)
+}
+
+/// Sets the modification time of the file at `path` to `time`
+pub(crate) async fn set_modified(path: &Path, time: SystemTime) -> Result<(), DownloadError> {
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+    file.into_std()
+        .await
+        .set_modified(time)
+        .map_err(|e| DownloadError::FileError(e.to_string()))
+}
+
+/// Moves `from` to `to`, replacing any existing file
+///
+/// Renames when possible, otherwise copies to a hidden temporary name next
+/// to `to` and renames that, so `to` never shows a half-copied file.
+pub(crate) async fn move_into_place(from: &Path, to: &Path) -> Result<(), DownloadError> {
+    match tokio::fs::rename(from, to).await {
+        Ok(()) => return Ok(()),
+        Err(e) if crosses_devices(&e) => {} // copy instead
+        Err(e) => return Err(DownloadError::FileError(e.to_string())),
+    }
+
+    let file_name = to
+        .file_name()
+        .ok_or_else(|| DownloadError::FileError(format!("No file name in {}", to.display())))?;
+    let temp = to.with_file_name(format!(".{}.moving", file_name.to_string_lossy()));
+
+    tokio::fs::copy(from, &temp)
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+    tokio::fs::rename(&temp, to)
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+    tokio::fs::remove_file(from)
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))
+}
+
+/// Whether a rename failed because its paths are on different filesystems
+///
+/// Checks the OS error code, as `ErrorKind::CrossesDevices` needs Rust 1.85.
+fn crosses_devices(error: &std::io::Error) -> bool {
+    if cfg!(windows) {
+        error.raw_os_error() == Some(17) // ERROR_NOT_SAME_DEVICE
+    } else {
+        error.raw_os_error() == Some(18) // EXDEV
+    }
+}
+
+/// A partial copy of a file, e.g. from another machine or tool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialFile {
+    /// File holding each present byte at its offset in the full file
+    pub path: PathBuf,
+    /// Byte ranges of the full file that `path` holds
+    pub ranges: Vec<Range<u64>>,
+}
+
+impl PartialFile {
+    /// Creates a partial file that holds no ranges yet
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            ranges: Vec::new(),
+        }
+    }
+
+    /// Adds a range of the full file that the partial file holds
+    pub fn with_range(mut self, range: Range<u64>) -> Self {
+        self.ranges.push(range);
+        self
+    }
+
+    /// Creates a partial file from a chunk layout, e.g. `DownloadPlan::chunks`
+    ///
+    /// Each chunk's downloaded prefix is a present range.
+    pub fn from_chunks(path: impl Into<PathBuf>, chunks: &[Chunk]) -> Self {
+        let ranges = chunks
+            .iter()
+            .filter(|c| c.downloaded > 0)
+            .map(|c| c.start..c.resume_position().min(c.end + 1))
+            .collect();
+        Self {
+            path: path.into(),
+            ranges,
+        }
+    }
+}
+
+/// Combines the bytes of several partial copies of a file into `target`
+///
+/// Returns the merged file's chunk layout, which can be passed to
+/// `ChunkedDownloader::download_planned` to fetch only what's still
+/// missing. Where sources overlap, the earlier one in `sources` is used.
+/// If the merged file is complete and `checksum` is given, it is verified,
+/// and on a mismatch each other source is tried as the preferred one
+/// before giving up with `DownloadError::ChecksumMismatch`.
+pub async fn merge_partials(
+    target: &Path,
+    sources: &[PartialFile],
+    file_size: u64,
+    checksum: Option<&Checksum>,
+) -> Result<Vec<Chunk>, DownloadError> {
+    let mut order: Vec<usize> = (0..sources.len()).collect();
+    let mut attempt = 0;
+
+    loop {
+        let pieces = assign_ranges(sources, &order, file_size);
+        write_pieces(target, sources, &pieces, file_size).await?;
+
+        let chunks = present_chunks(&pieces, file_size)?;
+        let complete = chunks.iter().all(|c| c.is_complete());
+
+        let Some(checksum) = checksum.filter(|_| complete) else {
+            return Ok(chunks);
+        };
+        match verify_checksum(target, checksum).await {
+            Ok(()) => return Ok(chunks),
+            Err(e) if attempt + 1 >= sources.len() => return Err(e),
+            Err(_) => {
+                // prefer the next source and merge again
+                attempt += 1;
+                order.rotate_left(1);
+            }
+        }
+    }
+}
+
+/// Picks a source for each byte of the file, earlier in `order` first
+///
+/// Returns non-overlapping pieces keyed by their start, each with its end
+/// (exclusive) and source index.
+fn assign_ranges(
+    sources: &[PartialFile],
+    order: &[usize],
+    file_size: u64,
+) -> BTreeMap<u64, (u64, usize)> {
+    let mut pieces: BTreeMap<u64, (u64, usize)> = BTreeMap::new();
+
+    for &source in order {
+        for range in &sources[source].ranges {
+            let mut start = range.start;
+            let end = range.end.min(file_size);
+
+            // fill the gaps between pieces already assigned
+            while start < end {
+                let covering = pieces
+                    .range(..=start)
+                    .next_back()
+                    .filter(|(_, &(piece_end, _))| piece_end > start)
+                    .map(|(_, &(piece_end, _))| piece_end);
+                if let Some(piece_end) = covering {
+                    start = piece_end;
+                    continue;
+                }
+
+                let gap_end = pieces
+                    .range(start..)
+                    .next()
+                    .map_or(end, |(&next, _)| next.min(end));
+                pieces.insert(start, (gap_end, source));
+                start = gap_end;
+            }
+        }
+    }
+
+    pieces
+}
+
+/// Writes `file_size` bytes to `target`, copying each piece from its source
+async fn write_pieces(
+    target: &Path,
+    sources: &[PartialFile],
+    pieces: &BTreeMap<u64, (u64, usize)>,
+    file_size: u64,
+) -> Result<(), DownloadError> {
+    let mut output = File::create(target)
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))?;
+    output
+        .set_len(file_size)
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+    for (&start, &(end, source)) in pieces {
+        let path = &sources[source].path;
+        let mut input = File::open(path)
+            .await
+            .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+        input
+            .seek(SeekFrom::Start(start))
+            .await
+            .map_err(|e| DownloadError::FileError(e.to_string()))?;
+        output
+            .seek(SeekFrom::Start(start))
+            .await
+            .map_err(|e| DownloadError::FileError(e.to_string()))?;
+
+        let copied = tokio::io::copy(&mut input.take(end - start), &mut output)
+            .await
+            .map_err(|e| DownloadError::FileError(e.to_string()))?;
+        if copied != end - start {
+            return Err(DownloadError::FileError(format!(
+                "{} ends before byte {}",
+                path.display(),
+                end
+            )));
+        }
+    }
+
+    output
+        .flush()
+        .await
+        .map_err(|e| DownloadError::FileError(e.to_string()))
+}
+
+/// Describes which bytes `pieces` cover as a chunk layout
+///
+/// Each present run starts a chunk that reaches up to the next run, so
+/// the chunk's downloaded prefix is the run and the rest is missing.
+fn present_chunks(
+    pieces: &BTreeMap<u64, (u64, usize)>,
+    file_size: u64,
+) -> Result<Vec<Chunk>, DownloadError> {
+    let mut runs: Vec<Range<u64>> = Vec::new();
+    for (&start, &(end, _)) in pieces {
+        match runs.last_mut() {
+            Some(run) if run.end == start => run.end = end,
+            _ => runs.push(start..end),
+        }
+    }
+
+    let mut starts: Vec<(u64, u64)> = runs.iter().map(|r| (r.start, r.end - r.start)).collect();
+    // nothing is present before the first run
+    let gap_first = starts
+        .first()
+        .map_or(file_size > 0, |&(start, _)| start > 0);
+    if gap_first {
+        starts.insert(0, (0, 0));
+    }
+    if starts.len() > u8::MAX as usize + 1 {
+        return Err(DownloadError::FileError(format!(
+            "Merged file has {} separate ranges, too many to resume",
+            runs.len()
+        )));
+    }
+
+    let chunks = starts
+        .iter()
+        .enumerate()
+        .map(|(i, &(start, downloaded))| Chunk {
+            index: i as u8,
+            start,
+            end: starts.get(i + 1).map_or(file_size, |&(next, _)| next) - 1,
+            downloaded,
+        })
+        .collect();
+
+    Ok(chunks)
+}
+
+/// Returns the free space in bytes available to this user on `dir`'s filesystem
+///
+/// `None` if it can't be determined.
+#[cfg(target_os = "linux")]
+pub(crate) fn available_space(dir: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+/// Free space isn't queried on this platform
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn available_space(_dir: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_preallocate_sets_length() {
+        let path = std::env::temp_dir().join("test_preallocate.bin");
+        let _ = tokio::fs::remove_file(&path).await;
+
+        let file = File::create(&path).await.unwrap();
+        preallocate(&file, 65_536, true).await.unwrap();
+        drop(file);
+
+        let metadata = tokio::fs::metadata(&path).await.unwrap();
+        assert_eq!(metadata.len(), 65_536);
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_check_length() {
+        let path = std::env::temp_dir().join("test_check_length.bin");
+        tokio::fs::write(&path, vec![0u8; 100]).await.unwrap();
+
+        assert!(check_length(&path, 100).await.is_ok());
+        assert_eq!(
+            check_length(&path, 120).await,
+            Err(DownloadError::LengthMismatch {
+                expected: 120,
+                actual: 100
+            })
+        );
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_move_into_place_replaces() {
+        let dir = std::env::temp_dir();
+        let from = dir.join("test_move_into_place_src.bin");
+        let to = dir.join("test_move_into_place_dst.bin");
+        tokio::fs::write(&from, b"new").await.unwrap();
+        tokio::fs::write(&to, b"old contents").await.unwrap();
+
+        move_into_place(&from, &to).await.unwrap();
+
+        assert_eq!(tokio::fs::read(&to).await.unwrap(), b"new");
+        assert!(!from.exists());
+
+        let _ = tokio::fs::remove_file(&to).await;
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_available_space() {
+        assert!(available_space(&std::env::temp_dir()).is_some_and(|free| free > 0));
+        assert_eq!(available_space(Path::new("/no/such/dir")), None);
+    }
+
+    #[test]
+    fn test_segment_path() {
+        let path = Path::new("/downloads/file.iso");
+        assert_eq!(
+            segment_path(path, 3),
+            PathBuf::from("/downloads/file.iso.part3")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge_segments_in_byte_order() {
+        let path = std::env::temp_dir().join("test_merge_segments.bin");
+        let chunks = [
+            Chunk {
+                index: 0,
+                start: 0,
+                end: 2,
+                downloaded: 3,
+            },
+            Chunk {
+                index: 2,
+                start: 6,
+                end: 7,
+                downloaded: 2,
+            },
+            Chunk {
+                index: 1,
+                start: 3,
+                end: 5,
+                downloaded: 3,
+            },
+        ];
+
+        tokio::fs::write(segment_path(&path, 0), b"abc")
+            .await
+            .unwrap();
+        tokio::fs::write(segment_path(&path, 1), b"def")
+            .await
+            .unwrap();
+        // longer than its chunk, e.g. its tail was split off, the extra is ignored
+        tokio::fs::write(segment_path(&path, 2), b"ghXX")
+            .await
+            .unwrap();
+
+        merge_segments(&path, &chunks).await.unwrap();
+
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), b"abcdefgh");
+        for chunk in &chunks {
+            assert!(!segment_path(&path, chunk.index).exists());
+        }
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_preallocate_sparse() {
+        let path = std::env::temp_dir().join("test_preallocate_sparse.bin");
+        let _ = tokio::fs::remove_file(&path).await;
+
+        let file = File::create(&path).await.unwrap();
+        preallocate(&file, 65_536, false).await.unwrap();
+        drop(file);
+
+        let metadata = tokio::fs::metadata(&path).await.unwrap();
+        assert_eq!(metadata.len(), 65_536);
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_move_file() {
+        let temp_dir = std::env::temp_dir();
+        let source = temp_dir.join("test_move_source.bin");
+        let library = temp_dir.join("test_move_library");
+        let _ = tokio::fs::remove_dir_all(&library).await;
+
+        tokio::fs::write(&source, b"finished").await.unwrap();
+
+        let dest = move_file(&source, &library).await.unwrap();
+
+        assert_eq!(dest, library.join("test_move_source.bin"));
+        assert!(!source.exists());
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), b"finished");
+
+        let _ = tokio::fs::remove_dir_all(&library).await;
+    }
+
+    #[tokio::test]
+    async fn test_move_file_conflict() {
+        let temp_dir = std::env::temp_dir();
+        let source = temp_dir.join("test_move_conflict.bin");
+        let library = temp_dir.join("test_move_conflict_library");
+        let _ = tokio::fs::remove_dir_all(&library).await;
+
+        tokio::fs::create_dir_all(&library).await.unwrap();
+        tokio::fs::write(library.join("test_move_conflict.bin"), b"old")
+            .await
+            .unwrap();
+        tokio::fs::write(&source, b"new").await.unwrap();
+
+        // existing file must not be clobbered
+        let result = move_file(&source, &library).await;
+        assert!(matches!(result, Err(DownloadError::FileError(_))));
+        assert!(source.exists());
+        assert_eq!(
+            tokio::fs::read(library.join("test_move_conflict.bin"))
+                .await
+                .unwrap(),
+            b"old"
+        );
+
+        let _ = tokio::fs::remove_file(&source).await;
+        let _ = tokio::fs::remove_dir_all(&library).await;
+    }
+
+    #[tokio::test]
+    async fn test_merge_partials_layout() {
+        let temp_dir = std::env::temp_dir();
+        let body: Vec<u8> = (0..10_000u32).map(|i| (i % 239) as u8).collect();
+        let (first, second) = (
+            temp_dir.join("test_merge_partials_a.bin"),
+            temp_dir.join("test_merge_partials_b.bin"),
+        );
+        let target = temp_dir.join("test_merge_partials.bin");
+
+        // each copy is full length, but only its ranges hold real bytes
+        let mut copy = vec![0u8; 10_000];
+        copy[..4000].copy_from_slice(&body[..4000]);
+        copy[8000..9000].copy_from_slice(&body[8000..9000]);
+        tokio::fs::write(&first, &copy).await.unwrap();
+        tokio::fs::write(&second, &body[..7000]).await.unwrap();
+
+        let sources = [
+            PartialFile::new(&first)
+                .with_range(0..4000)
+                .with_range(8000..9000),
+            PartialFile::new(&second).with_range(3000..7000),
+        ];
+        let chunks = merge_partials(&target, &sources, 10_000, None)
+            .await
+            .unwrap();
+
+        let layout: Vec<_> = chunks
+            .iter()
+            .map(|c| (c.index, c.start, c.end, c.downloaded))
+            .collect();
+        assert_eq!(layout, vec![(0, 0, 7999, 7000), (1, 8000, 9999, 1000)]);
+
+        let merged = tokio::fs::read(&target).await.unwrap();
+        assert_eq!(merged.len(), 10_000);
+        assert_eq!(&merged[..7000], &body[..7000]);
+        assert_eq!(&merged[8000..9000], &body[8000..9000]);
+
+        // the layout reads back as the same ranges
+        let again = PartialFile::from_chunks(&target, &chunks);
+        assert_eq!(again.ranges, vec![0..7000, 8000..9000]);
+
+        // a range the source doesn't actually hold is an error
+        let short = [PartialFile::new(&second).with_range(6000..8000)];
+        let result = merge_partials(&target, &short, 10_000, None).await;
+        assert!(matches!(result, Err(DownloadError::FileError(_))));
+
+        for path in [&first, &second, &target] {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge_partials_prefers_verified_source() {
+        use sha2::Digest;
+
+        let temp_dir = std::env::temp_dir();
+        let body: Vec<u8> = (0..5000u32).map(|i| (i % 227) as u8).collect();
+        let checksum = Checksum::Sha256(sha2::Sha256::digest(&body).to_vec());
+        let (corrupt, good) = (
+            temp_dir.join("test_merge_verified_a.bin"),
+            temp_dir.join("test_merge_verified_b.bin"),
+        );
+        let target = temp_dir.join("test_merge_verified.bin");
+
+        // both claim the middle, but the first copy's version of it is wrong
+        let mut damaged = body.clone();
+        damaged[2000..2500].fill(0xff);
+        tokio::fs::write(&corrupt, &damaged).await.unwrap();
+        tokio::fs::write(&good, &body).await.unwrap();
+        let sources = [
+            PartialFile::new(&corrupt).with_range(0..3000),
+            PartialFile::new(&good).with_range(1500..5000),
+        ];
+
+        let chunks = merge_partials(&target, &sources, 5000, Some(&checksum))
+            .await
+            .unwrap();
+        assert!(chunks.iter().all(|c| c.is_complete()));
+        assert_eq!(tokio::fs::read(&target).await.unwrap(), body);
+
+        // with no copy that verifies, the mismatch is reported
+        let sources = [PartialFile::new(&corrupt).with_range(0..5000)];
+        let result = merge_partials(&target, &sources, 5000, Some(&checksum)).await;
+        assert!(matches!(
+            result,
+            Err(DownloadError::ChecksumMismatch { .. })
+        ));
+
+        for path in [&corrupt, &good, &target] {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+}
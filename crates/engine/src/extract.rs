@@ -0,0 +1,296 @@
+//! Extracting downloaded archives
+
+use crate::DownloadError;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+/// Archive formats that can be extracted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    /// `.zip`
+    Zip,
+    /// Uncompressed `.tar`
+    Tar,
+    /// Gzip compressed tar, `.tar.gz` or `.tgz`
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// Detects the archive type of the file at `path`
+    ///
+    /// Zip and tar are recognised by their magic bytes. Gzip only says the
+    /// file is compressed, so a gzip file also needs a `.tar.gz` or `.tgz`
+    /// name. Returns `None` for anything else, or if the file can't be read.
+    pub fn detect(path: &Path) -> Option<Self> {
+        let mut header = [0u8; 262];
+        let mut file = File::open(path).ok()?;
+        let len = read_up_to(&mut file, &mut header).ok()?;
+        let header = &header[..len];
+
+        let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+
+        if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(ArchiveKind::Zip)
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            (name.ends_with(".tar.gz") || name.ends_with(".tgz")).then_some(ArchiveKind::TarGz)
+        } else if header.get(257..262) == Some(b"ustar") {
+            Some(ArchiveKind::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Extracts the archive at `path` into `dest`, see `extract_archive_with_progress`
+pub async fn extract_archive(path: &Path, dest: &Path) -> Result<u64, DownloadError> {
+    extract_archive_with_progress(path, dest, |_| {}).await
+}
+
+/// Extracts the archive at `path` into `dest`, returning the number of entries
+///
+/// `on_entry` is called with each entry's path (relative to `dest`) once it
+/// has been written. Entries that would land outside `dest`, like
+/// `../../etc/passwd` or absolute paths, fail the whole extraction with
+/// `DownloadError::ExtractionFailed` before anything is written for them.
+pub async fn extract_archive_with_progress<F>(
+    path: &Path,
+    dest: &Path,
+    on_entry: F,
+) -> Result<u64, DownloadError>
+where
+    F: FnMut(&Path) + Send + 'static,
+{
+    let path = path.to_path_buf();
+    let dest = dest.to_path_buf();
+
+    // decompressing is CPU and disk bound, keep it off the runtime
+    tokio::task::spawn_blocking(move || extract_blocking(&path, &dest, on_entry))
+        .await
+        .map_err(|e| DownloadError::ExtractionFailed(format!("Task failed: {}", e)))?
+}
+
+fn extract_blocking<F>(path: &Path, dest: &Path, on_entry: F) -> Result<u64, DownloadError>
+where
+    F: FnMut(&Path),
+{
+    let kind = ArchiveKind::detect(path).ok_or_else(|| {
+        DownloadError::ExtractionFailed(format!("{} is not a supported archive", path.display()))
+    })?;
+
+    std::fs::create_dir_all(dest).map_err(failed)?;
+    let file = File::open(path).map_err(failed)?;
+
+    match kind {
+        ArchiveKind::Zip => extract_zip(file, dest, on_entry),
+        ArchiveKind::Tar => extract_tar(tar::Archive::new(file), dest, on_entry),
+        ArchiveKind::TarGz => extract_tar(
+            tar::Archive::new(flate2::read::GzDecoder::new(file)),
+            dest,
+            on_entry,
+        ),
+    }
+}
+
+fn extract_zip<F>(file: File, dest: &Path, mut on_entry: F) -> Result<u64, DownloadError>
+where
+    F: FnMut(&Path),
+{
+    let mut archive = zip::ZipArchive::new(file).map_err(failed)?;
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(failed)?;
+        let name = PathBuf::from(entry.name());
+        let target = safe_join(dest, &name)?;
+
+        if entry.is_dir() {
+            std::fs::create_dir_all(&target).map_err(failed)?;
+        } else {
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent).map_err(failed)?;
+            }
+            let mut out = File::create(&target).map_err(failed)?;
+            std::io::copy(&mut entry, &mut out).map_err(failed)?;
+        }
+
+        on_entry(&name);
+    }
+
+    Ok(archive.len() as u64)
+}
+
+fn extract_tar<R, F>(
+    mut archive: tar::Archive<R>,
+    dest: &Path,
+    mut on_entry: F,
+) -> Result<u64, DownloadError>
+where
+    R: Read,
+    F: FnMut(&Path),
+{
+    let mut count = 0;
+
+    for entry in archive.entries().map_err(failed)? {
+        let mut entry = entry.map_err(failed)?;
+        let name = entry.path().map_err(failed)?.into_owned();
+        safe_join(dest, &name)?;
+
+        // unpack_in also refuses to follow links out of `dest`
+        if !entry.unpack_in(dest).map_err(failed)? {
+            return Err(unsafe_entry(&name));
+        }
+
+        on_entry(&name);
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Joins an entry name onto `dest`, refusing names that escape it
+fn safe_join(dest: &Path, name: &Path) -> Result<PathBuf, DownloadError> {
+    let mut target = dest.to_path_buf();
+
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => target.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(unsafe_entry(name));
+            }
+        }
+    }
+
+    Ok(target)
+}
+
+fn unsafe_entry(name: &Path) -> DownloadError {
+    DownloadError::ExtractionFailed(format!(
+        "Entry {} would be written outside the target directory",
+        name.display()
+    ))
+}
+
+fn failed(e: impl std::fmt::Display) -> DownloadError {
+    DownloadError::ExtractionFailed(e.to_string())
+}
+
+/// Fills as much of `buf` as the reader has, for short files
+fn read_up_to(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&path);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, data) in entries {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_extract_zip() {
+        let archive = temp_path("test_extract.zip");
+        let dest = temp_path("test_extract_zip_out");
+        write_zip(&archive, &[("a.txt", b"alpha"), ("dir/b.txt", b"beta")]);
+
+        assert_eq!(ArchiveKind::detect(&archive), Some(ArchiveKind::Zip));
+
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let record = seen.clone();
+        let count = extract_archive_with_progress(&archive, &dest, move |name| {
+            record.lock().unwrap().push(name.to_path_buf())
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(std::fs::read(dest.join("a.txt")).unwrap(), b"alpha");
+        assert_eq!(std::fs::read(dest.join("dir/b.txt")).unwrap(), b"beta");
+        assert_eq!(seen.lock().unwrap().len(), 2);
+
+        let _ = std::fs::remove_file(&archive);
+        let _ = std::fs::remove_dir_all(&dest);
+    }
+
+    #[tokio::test]
+    async fn test_extract_tar_gz() {
+        let archive = temp_path("test_extract.tar.gz");
+        let dest = temp_path("test_extract_tgz_out");
+
+        let gz = flate2::write::GzEncoder::new(
+            File::create(&archive).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut tar = tar::Builder::new(gz);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "docs/readme.txt", &b"hello"[..])
+            .unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        assert_eq!(ArchiveKind::detect(&archive), Some(ArchiveKind::TarGz));
+
+        assert_eq!(extract_archive(&archive, &dest).await.unwrap(), 1);
+        assert_eq!(
+            std::fs::read(dest.join("docs/readme.txt")).unwrap(),
+            b"hello"
+        );
+
+        let _ = std::fs::remove_file(&archive);
+        let _ = std::fs::remove_dir_all(&dest);
+    }
+
+    #[tokio::test]
+    async fn test_zip_slip_rejected() {
+        let archive = temp_path("test_extract_slip.zip");
+        let dest = temp_path("test_extract_slip_out");
+        write_zip(&archive, &[("ok.txt", b"ok"), ("../escaped.txt", b"evil")]);
+
+        let err = extract_archive(&archive, &dest).await.unwrap_err();
+        assert!(matches!(err, DownloadError::ExtractionFailed(_)));
+        assert!(!std::env::temp_dir().join("escaped.txt").exists());
+
+        let _ = std::fs::remove_file(&archive);
+        let _ = std::fs::remove_dir_all(&dest);
+    }
+
+    #[tokio::test]
+    async fn test_not_an_archive() {
+        let gz_file = temp_path("test_extract_plain.gz");
+        std::fs::write(&gz_file, [0x1f, 0x8b, 0, 0]).unwrap();
+        assert_eq!(ArchiveKind::detect(&gz_file), None);
+
+        let err = extract_archive(&gz_file, &temp_path("test_extract_plain_out"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DownloadError::ExtractionFailed(_)));
+
+        let _ = std::fs::remove_file(&gz_file);
+    }
+}
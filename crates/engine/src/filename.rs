@@ -0,0 +1,254 @@
+//! Output filename resolution
+
+use percent_encoding::percent_decode_str;
+
+/// Name used when neither the headers nor the URL provide one
+pub const FALLBACK_FILENAME: &str = "download";
+
+/// Longest file name in bytes that common filesystems accept
+pub const MAX_FILENAME_BYTES: usize = 255;
+
+/// Longest tail after the last dot that's kept as an extension when truncating
+const MAX_EXTENSION_BYTES: usize = 16;
+
+/// Device names Windows reserves, with or without an extension
+const WINDOWS_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Extracts the filename from a Content-Disposition header value
+///
+/// Prefers the RFC 5987 `filename*=` form over plain `filename=`.
+pub fn filename_from_content_disposition(value: &str) -> Option<String> {
+    let mut plain = None;
+    let mut extended = None;
+
+    for param in value.split(';').map(str::trim) {
+        let Some((key, raw)) = param.split_once('=') else {
+            continue;
+        };
+
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename*" => {
+                // charset'language'percent-encoded-name
+                let encoded = raw.trim().splitn(3, '\'').nth(2)?;
+                extended = Some(percent_decode_str(encoded).decode_utf8_lossy().into_owned());
+            }
+            "filename" => {
+                plain = Some(raw.trim().trim_matches('"').to_string());
+            }
+            _ => {}
+        }
+    }
+
+    extended.or(plain).and_then(|name| clean(&name))
+}
+
+/// Extracts the filename from the last segment of a URL path
+pub fn filename_from_url(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let segment = url.path_segments()?.next_back()?;
+
+    clean(&percent_decode_str(segment).decode_utf8_lossy())
+}
+
+/// Makes a file name legal on the current OS
+///
+/// Characters the OS forbids and control characters become `_`. On
+/// Windows that covers `<>:"/\|?*`, trailing dots and spaces are trimmed,
+/// and device names like `CON` or `nul.txt` get a `_` prefix. Names longer
+/// than `MAX_FILENAME_BYTES` are shortened, keeping the extension. A name
+/// with nothing left becomes `FALLBACK_FILENAME`.
+pub fn sanitize_filename(name: &str) -> String {
+    sanitize_for(name, cfg!(windows))
+}
+
+/// `sanitize_filename` with Windows or Unix rules
+fn sanitize_for(name: &str, windows: bool) -> String {
+    let forbidden = |c: char| {
+        c.is_control()
+            || c == '/'
+            || (windows && matches!(c, '<' | '>' | ':' | '"' | '\\' | '|' | '?' | '*'))
+    };
+    let mut name: String = name
+        .chars()
+        .map(|c| if forbidden(c) { '_' } else { c })
+        .collect();
+
+    if windows {
+        name.truncate(name.trim_end_matches(['.', ' ']).len());
+
+        let stem = name.split('.').next().unwrap_or("").trim_end();
+        if WINDOWS_RESERVED
+            .iter()
+            .any(|r| r.eq_ignore_ascii_case(stem))
+        {
+            name.insert(0, '_');
+        }
+    }
+
+    let name = truncate_keeping_extension(&name, MAX_FILENAME_BYTES);
+
+    match name.as_str() {
+        "" | "." | ".." => FALLBACK_FILENAME.to_string(),
+        _ => name,
+    }
+}
+
+/// Shortens `name` to at most `max` bytes, cutting the stem rather than the extension
+fn truncate_keeping_extension(name: &str, max: usize) -> String {
+    if name.len() <= max {
+        return name.to_string();
+    }
+
+    let extension = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= MAX_EXTENSION_BYTES => &name[dot..],
+        _ => "",
+    };
+
+    let mut cut = max - extension.len();
+    while !name.is_char_boundary(cut) {
+        cut -= 1;
+    }
+
+    format!("{}{}", &name[..cut], extension)
+}
+
+/// Returns `name` with ` (n)` before its extension, e.g. `notes (1).txt`
+///
+/// The part before the extension is shortened if needed to stay within
+/// `MAX_FILENAME_BYTES`.
+pub(crate) fn numbered_filename(name: &str, n: u32) -> String {
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let number = format!(" ({})", n);
+
+    let mut cut = stem
+        .len()
+        .min(MAX_FILENAME_BYTES.saturating_sub(number.len() + extension.len()));
+    while !stem.is_char_boundary(cut) {
+        cut -= 1;
+    }
+
+    format!("{}{}{}", &stem[..cut], number, extension)
+}
+
+/// Strips any directory parts so a server can't choose where we write
+pub(crate) fn clean(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next()?.trim();
+
+    match name {
+        "" | "." | ".." => None,
+        name => Some(name.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_disposition_plain() {
+        assert_eq!(
+            filename_from_content_disposition("attachment; filename=\"report.pdf\""),
+            Some("report.pdf".to_string())
+        );
+        assert_eq!(
+            filename_from_content_disposition("attachment; filename=data.csv"),
+            Some("data.csv".to_string())
+        );
+        assert_eq!(filename_from_content_disposition("inline"), None);
+    }
+
+    #[test]
+    fn test_content_disposition_extended() {
+        // filename* wins over the ASCII fallback
+        let value = "attachment; filename=\"fallback.txt\"; filename*=UTF-8''na%C3%AFve%20file.txt";
+        assert_eq!(
+            filename_from_content_disposition(value),
+            Some("naïve file.txt".to_string())
+        );
+    }
+
+    #[test]
+    fn test_content_disposition_path_traversal() {
+        assert_eq!(
+            filename_from_content_disposition("attachment; filename=\"../../etc/passwd\""),
+            Some("passwd".to_string())
+        );
+        assert_eq!(
+            filename_from_content_disposition("attachment; filename=\"..\""),
+            None
+        );
+    }
+
+    #[test]
+    fn test_filename_from_url() {
+        assert_eq!(
+            filename_from_url("https://example.com/files/ubuntu%2024.iso?token=abc"),
+            Some("ubuntu 24.iso".to_string())
+        );
+        assert_eq!(filename_from_url("https://example.com/"), None);
+        assert_eq!(filename_from_url("not a url"), None);
+    }
+
+    #[test]
+    fn test_sanitize_windows_rules() {
+        assert_eq!(
+            sanitize_for("a<b>c:d\"e|f?g*.txt", true),
+            "a_b_c_d_e_f_g_.txt"
+        );
+        assert_eq!(sanitize_for("report. . ", true), "report");
+        assert_eq!(sanitize_for("CON", true), "_CON");
+        assert_eq!(sanitize_for("nul.tar.gz", true), "_nul.tar.gz");
+        assert_eq!(sanitize_for("console.log", true), "console.log");
+        assert_eq!(sanitize_for("...", true), FALLBACK_FILENAME);
+    }
+
+    #[test]
+    fn test_sanitize_unix_rules() {
+        assert_eq!(sanitize_for("a<b>c:d|e?.txt", false), "a<b>c:d|e?.txt");
+        assert_eq!(sanitize_for("CON", false), "CON");
+        assert_eq!(sanitize_for("tab\there", false), "tab_here");
+        assert_eq!(sanitize_for("trailing. ", false), "trailing. ");
+    }
+
+    #[test]
+    fn test_sanitize_truncates_keeping_extension() {
+        let long = format!("{}.iso", "é".repeat(200));
+        let name = sanitize_filename(&long);
+
+        assert!(name.len() <= MAX_FILENAME_BYTES);
+        assert!(name.ends_with("é.iso"));
+
+        // no short extension to keep, the name is just cut
+        let name = sanitize_filename(&"x".repeat(300));
+        assert_eq!(name.len(), MAX_FILENAME_BYTES);
+    }
+
+    #[test]
+    fn test_numbered_filename() {
+        assert_eq!(numbered_filename("notes.txt", 1), "notes (1).txt");
+        assert_eq!(numbered_filename("archive.tar.gz", 2), "archive.tar (2).gz");
+        assert_eq!(numbered_filename(".bashrc", 1), ".bashrc (1)");
+        assert_eq!(numbered_filename("download", 10), "download (10)");
+
+        // still short enough once numbered
+        let name = numbered_filename(&format!("{}.iso", "é".repeat(126)), 3);
+        assert!(name.len() <= MAX_FILENAME_BYTES);
+        assert!(name.ends_with("é (3).iso"));
+    }
+
+    #[test]
+    fn test_sanitize_current_os() {
+        #[cfg(windows)]
+        assert_eq!(sanitize_filename("what?.txt"), "what_.txt");
+        #[cfg(not(windows))]
+        assert_eq!(sanitize_filename("what?.txt"), "what?.txt");
+
+        assert_eq!(sanitize_filename("ubuntu 24.iso"), "ubuntu 24.iso");
+    }
+}
@@ -1,24 +1,52 @@
 //! HTTP download functionality
 
-use reqwest::Client;
+use crate::transport::{HttpTransport, ReqwestTransport, TransportResponse};
+use crate::ClientConfig;
 use std::path::Path;
+use std::sync::Arc;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
+use tracing::{debug, info, instrument};
+
+/// Default limit for downloads held in memory (16MB)
+const DEFAULT_MAX_IN_MEMORY_SIZE: u64 = 16 * 1024 * 1024;
 
 /// HTTP downloader for single-threaded downloads
 pub struct HttpDownloader {
-    client: Client,
+    transport: Arc<dyn HttpTransport>,
+    max_in_memory_size: u64,
 }
 
 impl HttpDownloader {
     /// Creates a new HTTP downloader
     pub fn new() -> Self {
-        let client = Client::builder()
-            .user_agent("FluxDM/0.1.0")
-            .build()
-            .expect("failed to create HTTP client"); // temporary, will improve error handling
+        Self::with_client_config(&ClientConfig::default())
+            .expect("failed to create HTTP client") // temporary, will improve error handling
+    }
+
+    /// Creates a new HTTP downloader with custom client settings
+    ///
+    /// Fails with `DownloadError::InvalidConfig` if the settings are invalid.
+    pub fn with_client_config(client_config: &ClientConfig) -> Result<Self, DownloadError> {
+        let transport = ReqwestTransport::new(client_config.build()?, false);
+
+        Ok(Self::with_transport(Arc::new(transport)))
+    }
+
+    /// Creates an HTTP downloader sending its requests through `transport`
+    ///
+    /// See `ChunkedDownloader::with_transport`.
+    pub fn with_transport(transport: Arc<dyn HttpTransport>) -> Self {
+        Self {
+            transport,
+            max_in_memory_size: DEFAULT_MAX_IN_MEMORY_SIZE,
+        }
+    }
 
-        Self { client }
+    /// Sets the maximum size accepted by `download_bytes`
+    pub fn with_max_in_memory_size(mut self, max_bytes: u64) -> Self {
+        self.max_in_memory_size = max_bytes;
+        self
     }
 
     /// Downloads a file from URL to the specified path
@@ -31,23 +59,18 @@ impl HttpDownloader {
     /// # Returns
     ///
     /// Returns the total number of bytes downloaded
+    #[instrument(name = "download", skip(self, path), fields(path = %path.display()))]
     pub async fn download(&self, url: &str, path: &Path) -> Result<u64, DownloadError> {
         // make the HTTP request
-        let response = self
-            .client
-            .get(url)
-            .send()
-            .await
-            .map_err(|e| DownloadError::NetworkError(e.to_string()))?;
+        let response = self.transport.get(url).await?;
+
+        debug!(status = response.status().as_u16(), "response");
 
         // check if request was successful
         if !response.status().is_success() {
             return Err(DownloadError::HttpError(response.status().as_u16()));
         }
 
-        // get content length if available (for future progress tracking)
-        let _total_size = response.content_length();
-
         // create the output file
         let mut file = File::create(path)
             .await
@@ -60,7 +83,7 @@ impl HttpDownloader {
         use futures_util::StreamExt; // for .next()
 
         while let Some(chunk) = stream.next().await {
-            let chunk = chunk.map_err(|e| DownloadError::NetworkError(e.to_string()))?;
+            let chunk = chunk?;
 
             file.write_all(&chunk)
                 .await
@@ -74,26 +97,71 @@ impl HttpDownloader {
             .await
             .map_err(|e| DownloadError::FileError(e.to_string()))?;
 
+        info!(bytes = bytes_downloaded, "download complete");
+
         Ok(bytes_downloaded)
     }
 
+    /// Downloads a small file directly into memory
+    ///
+    /// Fails with `DownloadError::TooLarge` if the body exceeds the
+    /// configured in-memory limit, so a wrong URL can't exhaust memory.
+    pub async fn download_bytes(&self, url: &str) -> Result<Vec<u8>, DownloadError> {
+        let response = self.transport.get(url).await?;
+
+        if !response.status().is_success() {
+            return Err(DownloadError::HttpError(response.status().as_u16()));
+        }
+
+        // reject early if the server tells us it's too big
+        if let Some(len) = content_length(&response) {
+            if len > self.max_in_memory_size {
+                return Err(DownloadError::TooLarge(self.max_in_memory_size));
+            }
+        }
+
+        let mut buffer = Vec::new();
+        let mut stream = response.bytes_stream();
+
+        use futures_util::StreamExt;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+
+            // content length may be missing or wrong, so check as we go
+            if (buffer.len() + chunk.len()) as u64 > self.max_in_memory_size {
+                return Err(DownloadError::TooLarge(self.max_in_memory_size));
+            }
+
+            buffer.extend_from_slice(&chunk);
+        }
+
+        Ok(buffer)
+    }
+
     /// Gets the content length of a URL without downloading
     pub async fn get_content_length(&self, url: &str) -> Result<Option<u64>, DownloadError> {
-        let response = self
-            .client
-            .head(url)
-            .send()
-            .await
-            .map_err(|e| DownloadError::NetworkError(e.to_string()))?;
+        let response = self.transport.head(url).await?;
 
         if !response.status().is_success() {
             return Err(DownloadError::HttpError(response.status().as_u16()));
         }
 
-        Ok(response.content_length())
+        Ok(content_length(&response))
     }
 }
 
+/// Reads the `Content-Length` header of a response
+fn content_length(response: &TransportResponse) -> Option<u64> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
 impl Default for HttpDownloader {
     fn default() -> Self {
         Self::new()
@@ -111,6 +179,59 @@ pub enum DownloadError {
     FileError(String),
     /// Invalid URL
     InvalidUrl(String),
+    /// Response larger than the allowed limit in bytes
+    TooLarge(u64),
+    /// Not enough disk space for the required number of bytes
+    InsufficientSpace(u64),
+    /// Request or transfer took longer than allowed
+    Timeout(String),
+    /// Invalid downloader or client settings
+    InvalidConfig(String),
+    /// Finished file doesn't have the expected length
+    LengthMismatch { expected: u64, actual: u64 },
+    /// File contents don't match the expected digest (hex encoded)
+    ChecksumMismatch { expected: String, actual: String },
+    /// Metalink document is malformed or lists nothing usable
+    InvalidMetalink(String),
+    /// Remote file changed size while it was being downloaded
+    ResourceChanged { expected: u64, actual: u64 },
+    /// Downloaded archive couldn't be extracted
+    ExtractionFailed(String),
+    /// Server didn't answer a Range request with the requested bytes
+    RangeNotSupported,
+    /// Response body of this many bytes is too small to be the real file
+    EmptyResponse(u64),
+    /// Download didn't finish within its deadline of this many milliseconds
+    DeadlineExceeded(u64),
+    /// This many chunk attempts failed within a window of this many milliseconds
+    TooManyFailures { failures: u32, window_ms: u64 },
+}
+
+impl DownloadError {
+    /// Returns true if trying the same request again may succeed
+    ///
+    /// Connection problems, timeouts, rate limiting and server-side (5xx)
+    /// failures are worth retrying; client errors and local I/O problems are not.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DownloadError::NetworkError(_) | DownloadError::Timeout(_) => true,
+            DownloadError::HttpError(code) => *code == 408 || *code == 429 || *code >= 500,
+            DownloadError::FileError(_)
+            | DownloadError::InvalidUrl(_)
+            | DownloadError::TooLarge(_)
+            | DownloadError::InsufficientSpace(_)
+            | DownloadError::InvalidConfig(_)
+            | DownloadError::LengthMismatch { .. }
+            | DownloadError::ChecksumMismatch { .. }
+            | DownloadError::InvalidMetalink(_)
+            | DownloadError::ResourceChanged { .. }
+            | DownloadError::ExtractionFailed(_)
+            | DownloadError::RangeNotSupported
+            | DownloadError::EmptyResponse(_)
+            | DownloadError::DeadlineExceeded(_)
+            | DownloadError::TooManyFailures { .. } => false,
+        }
+    }
 }
 
 impl std::fmt::Display for DownloadError {
@@ -120,15 +241,115 @@ impl std::fmt::Display for DownloadError {
             DownloadError::HttpError(code) => write!(f, "HTTP error: {}", code),
             DownloadError::FileError(msg) => write!(f, "File error: {}", msg),
             DownloadError::InvalidUrl(msg) => write!(f, "Invalid URL: {}", msg),
+            DownloadError::TooLarge(limit) => {
+                write!(f, "Response exceeds limit of {} bytes", limit)
+            }
+            DownloadError::InsufficientSpace(needed) => {
+                write!(f, "Insufficient disk space: {} bytes required", needed)
+            }
+            DownloadError::Timeout(msg) => write!(f, "Timed out: {}", msg),
+            DownloadError::InvalidConfig(msg) => write!(f, "Invalid configuration: {}", msg),
+            DownloadError::LengthMismatch { expected, actual } => {
+                write!(f, "File is {} bytes, expected {}", actual, expected)
+            }
+            DownloadError::ChecksumMismatch { expected, actual } => {
+                write!(f, "Checksum mismatch: expected {}, got {}", expected, actual)
+            }
+            DownloadError::InvalidMetalink(msg) => write!(f, "Invalid metalink: {}", msg),
+            DownloadError::ResourceChanged { expected, actual } => {
+                write!(f, "Remote file changed from {} to {} bytes", expected, actual)
+            }
+            DownloadError::ExtractionFailed(msg) => write!(f, "Extraction failed: {}", msg),
+            DownloadError::RangeNotSupported => write!(f, "Server doesn't support byte ranges"),
+            DownloadError::EmptyResponse(size) => {
+                write!(f, "Response of {} bytes is smaller than expected", size)
+            }
+            DownloadError::DeadlineExceeded(ms) => {
+                write!(f, "Download didn't finish within {} ms", ms)
+            }
+            DownloadError::TooManyFailures { failures, window_ms } => {
+                write!(f, "{} chunk failures within {} ms", failures, window_ms)
+            }
         }
     }
 }
 
 impl std::error::Error for DownloadError {}
 
+/// Status codes to retry, or not, whatever `DownloadError::is_retryable` says
+///
+/// An explicit entry overrides the default classification; a code in both
+/// lists is never retried. Errors other than HTTP statuses keep the default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetryStatusPolicy {
+    /// Codes to retry anyway, e.g. a 404 from a CDN edge that hasn't caught up
+    pub retry: Vec<u16>,
+    /// Codes never to retry, e.g. a 503 that means "down for maintenance"
+    pub never_retry: Vec<u16>,
+}
+
+impl RetryStatusPolicy {
+    /// Returns true if `error` is worth another attempt under this policy
+    pub fn should_retry(&self, error: &DownloadError) -> bool {
+        if let DownloadError::HttpError(code) = error {
+            if self.never_retry.contains(code) {
+                return false;
+            }
+            if self.retry.contains(code) {
+                return true;
+            }
+        }
+
+        error.is_retryable()
+    }
+}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(e: reqwest::Error) -> Self {
+        // keep timeouts apart so callers can extend the limit instead of giving up
+        if e.is_timeout() {
+            DownloadError::Timeout(e.to_string())
+        } else {
+            DownloadError::NetworkError(e.to_string())
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ResponseFuture;
+    use reqwest::header::CONTENT_LENGTH;
+    use reqwest::{StatusCode, Url};
+
+    /// Answers every request with `body`, without a socket
+    struct CannedTransport {
+        body: Vec<u8>,
+    }
+
+    impl CannedTransport {
+        fn respond(&self) -> TransportResponse {
+            let url = Url::parse("http://mock.invalid/file.bin").unwrap();
+            TransportResponse::new(StatusCode::OK, url)
+                .with_header(CONTENT_LENGTH, self.body.len().to_string())
+        }
+    }
+
+    impl HttpTransport for CannedTransport {
+        fn head<'a>(&'a self, _url: &'a str) -> ResponseFuture<'a> {
+            let response = self.respond();
+            Box::pin(async move { Ok(response) })
+        }
+
+        fn get<'a>(&'a self, _url: &'a str) -> ResponseFuture<'a> {
+            let response = self.respond().with_body(self.body.clone());
+            Box::pin(async move { Ok(response) })
+        }
+
+        fn get_range<'a>(&'a self, url: &'a str, _: u64, _: Option<u64>) -> ResponseFuture<'a> {
+            self.get(url)
+        }
+    }
 
     #[tokio::test]
     async fn test_downloader_creation() {
@@ -136,6 +357,57 @@ mod tests {
         // just verify it doesn't panic
     }
 
+    #[tokio::test]
+    async fn test_mock_transport_download() {
+        let body = b"single stream".repeat(100);
+        let transport = Arc::new(CannedTransport { body: body.clone() });
+        let downloader = HttpDownloader::with_transport(transport).with_max_in_memory_size(1000);
+        let url = "http://mock.invalid/file.bin";
+        let path = std::env::temp_dir().join("test_http_mock_transport.bin");
+
+        assert_eq!(downloader.download(url, &path).await.unwrap(), 1300);
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), body);
+        assert_eq!(downloader.get_content_length(url).await.unwrap(), Some(1300));
+        assert_eq!(
+            downloader.download_bytes(url).await,
+            Err(DownloadError::TooLarge(1000))
+        );
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[test]
+    fn test_retryable_errors() {
+        assert!(DownloadError::Timeout("slow".to_string()).is_retryable());
+        assert!(DownloadError::NetworkError("reset".to_string()).is_retryable());
+        assert!(DownloadError::HttpError(503).is_retryable());
+        assert!(DownloadError::HttpError(429).is_retryable());
+        assert!(!DownloadError::HttpError(404).is_retryable());
+        assert!(!DownloadError::FileError("denied".to_string()).is_retryable());
+        assert!(!DownloadError::InsufficientSpace(1).is_retryable());
+        assert!(!DownloadError::DeadlineExceeded(600_000).is_retryable());
+        assert!(!DownloadError::TooManyFailures { failures: 4, window_ms: 1000 }.is_retryable());
+    }
+
+    #[test]
+    fn test_retry_status_policy() {
+        let policy = RetryStatusPolicy {
+            retry: vec![404, 409],
+            never_retry: vec![503, 409],
+        };
+
+        assert!(policy.should_retry(&DownloadError::HttpError(404)));
+        assert!(!policy.should_retry(&DownloadError::HttpError(503)));
+        // the deny list wins
+        assert!(!policy.should_retry(&DownloadError::HttpError(409)));
+
+        // everything else falls back to is_retryable
+        assert!(policy.should_retry(&DownloadError::HttpError(502)));
+        assert!(!policy.should_retry(&DownloadError::HttpError(403)));
+        assert!(policy.should_retry(&DownloadError::Timeout("slow".to_string())));
+        assert!(RetryStatusPolicy::default().should_retry(&DownloadError::HttpError(500)));
+    }
+
     // note: actual download tests require network access
     // we'll add integration tests later with mock servers
 }
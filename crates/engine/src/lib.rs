@@ -1,11 +1,48 @@
+use std::collections::VecDeque;
 use std::path::PathBuf;
 use std::time::SystemTime;
 
 mod http;
+mod builder;
+mod checksum;
 mod chunked;
-
-pub use http::{DownloadError, HttpDownloader};
-pub use chunked::{Chunk, ChunkConfig, ChunkedDownloader};
+mod client;
+mod config;
+mod disk;
+mod extract;
+mod filename;
+mod metalink;
+mod progress;
+mod rewrite;
+mod template;
+mod transport;
+
+pub use http::{DownloadError, HttpDownloader, RetryStatusPolicy};
+pub use builder::DownloadBuilder;
+pub use checksum::{verify_checksum, verify_file, verify_file_with_size, Checksum};
+pub use chunked::{
+    is_fully_downloaded, Chunk, ChunkConfig, ChunkedDownloader, DownloadOutcome, DownloadPlan,
+    OutcomeKind, RemoteInfo, RequestTiming, SegmentMode,
+};
+pub use client::{ClientConfig, HttpVersionPref, IpPreference, DEFAULT_USER_AGENT};
+pub use config::{FluxConfig, FluxConfigBuilder};
+pub use disk::{merge_partials, move_file, PartialFile};
+pub use extract::{extract_archive, extract_archive_with_progress, ArchiveKind};
+pub use filename::{
+    filename_from_content_disposition, filename_from_url, sanitize_filename, FALLBACK_FILENAME,
+    MAX_FILENAME_BYTES,
+};
+pub use metalink::{parse_metalink, MetalinkFile};
+pub use progress::ProgressFile;
+pub use rewrite::{
+    normalize_url, resolve_location, AddQueryParam, DropboxDirect, GitHubRaw, UrlRewriter,
+    UrlRewriters,
+};
+pub use template::{PathTemplate, UNCATEGORIZED};
+pub use transport::{BodyStream, HttpTransport, ResponseFuture, TransportResponse};
+
+/// Number of points kept by `Download::speed_history`
+pub const SPEED_HISTORY_LEN: usize = 60;
 
 /// Unique identifier for a download
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -42,6 +79,8 @@ pub enum DownloadStatus {
 pub struct Download {
     id: DownloadId,
     url: String,
+    rewritten_url: Option<String>,
+    resolved_url: Option<String>,
     file_path: Option<PathBuf>,
     status: DownloadStatus,
     bytes_downloaded: u64,
@@ -49,7 +88,22 @@ pub struct Download {
     created_at: SystemTime,
     started_at: Option<SystemTime>,
     completed_at: Option<SystemTime>,
+    scheduled_at: Option<SystemTime>,
+    move_to: Option<PathBuf>,
+    category: Option<String>,
+    max_retries: Option<u32>,
+    retry_delay_ms: Option<u64>,
+    deadline_ms: Option<u64>,
+    chunk_count: Option<u8>,
+    checksum: Option<Checksum>,
+    retry_count: u32,
+    wasted_bytes: u64,
+    user_agent: Option<String>,
+    extract_to: Option<PathBuf>,
+    extraction_error: Option<String>,
     error_message: Option<String>,
+    pause_reason: Option<String>,
+    speed_samples: VecDeque<(SystemTime, u64)>,
 }
 
 impl Download {
@@ -58,6 +112,8 @@ impl Download {
         Self {
             id,
             url,
+            rewritten_url: None,
+            resolved_url: None,
             file_path: None,
             status: DownloadStatus::Pending,
             bytes_downloaded: 0,
@@ -65,10 +121,30 @@ impl Download {
             created_at: SystemTime::now(),
             started_at: None,
             completed_at: None,
+            scheduled_at: None,
+            move_to: None,
+            category: None,
+            max_retries: None,
+            retry_delay_ms: None,
+            deadline_ms: None,
+            chunk_count: None,
+            checksum: None,
+            retry_count: 0,
+            wasted_bytes: 0,
+            user_agent: None,
+            extract_to: None,
+            extraction_error: None,
             error_message: None,
+            pause_reason: None,
+            speed_samples: VecDeque::with_capacity(SPEED_HISTORY_LEN + 1),
         }
     }
 
+    /// Starts a download with all its options set in one go, see `DownloadBuilder`
+    pub fn builder(id: DownloadId) -> DownloadBuilder {
+        DownloadBuilder::new(id)
+    }
+
     /// Returns the download's unique ID
     pub fn id(&self) -> DownloadId {
         self.id
@@ -79,6 +155,32 @@ impl Download {
         &self.url
     }
 
+    /// Returns the URL to fetch: the rewritten one if any, otherwise `url`
+    pub fn download_url(&self) -> &str {
+        self.rewritten_url.as_deref().unwrap_or(&self.url)
+    }
+
+    /// Runs the URL through `rewriters`, keeping the original for display
+    ///
+    /// Call this before probing the URL, as rewriting may change the host.
+    pub fn rewrite_url(&mut self, rewriters: &UrlRewriters) {
+        let rewritten = rewriters.rewrite(&self.url);
+        self.rewritten_url = (rewritten != self.url).then_some(rewritten);
+    }
+
+    /// Returns the URL the download was last fetched from, after redirects
+    ///
+    /// For signed CDN links this expires; `download_url` stays the one to
+    /// re-resolve from, see `ChunkedDownloader::refresh_resolved_url`.
+    pub fn resolved_url(&self) -> Option<&str> {
+        self.resolved_url.as_deref()
+    }
+
+    /// Records where `download_url` redirected to
+    pub fn set_resolved_url(&mut self, url: String) {
+        self.resolved_url = (url != self.download_url()).then_some(url);
+    }
+
     /// Returns the file path where download will be saved
     pub fn file_path(&self) -> Option<&PathBuf> {
         self.file_path.as_ref()
@@ -89,6 +191,47 @@ impl Download {
         self.file_path = Some(path);
     }
 
+    /// Returns the directory the finished file should be moved to, if any
+    pub fn move_to(&self) -> Option<&PathBuf> {
+        self.move_to.as_ref()
+    }
+
+    /// Sets a directory to move the file into once the download completes
+    pub fn set_move_to(&mut self, dir: PathBuf) {
+        self.move_to = Some(dir);
+    }
+
+    /// Returns the download's category, if any
+    pub fn category(&self) -> Option<&str> {
+        self.category.as_deref()
+    }
+
+    /// Sets the download's category (e.g. "Videos")
+    pub fn set_category(&mut self, category: String) {
+        self.category = Some(category);
+    }
+
+    /// Removes the download's category
+    pub fn clear_category(&mut self) {
+        self.category = None;
+    }
+
+    /// Returns true if the URL or file name contains `query`, ignoring case
+    ///
+    /// An empty query matches every download.
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+
+        let file_name = self
+            .file_path
+            .as_ref()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().to_lowercase());
+
+        self.url.to_lowercase().contains(&query)
+            || file_name.is_some_and(|n| n.contains(&query))
+    }
+
     /// Returns download's current status
     pub fn status(&self) -> DownloadStatus {
         self.status
@@ -100,10 +243,18 @@ impl Download {
     }
 
     /// Returns the total file size in bytes, if known
+    ///
+    /// Stays `None` for single-stream downloads of unknown length, which
+    /// should show indeterminate progress.
     pub fn total_bytes(&self) -> Option<u64> {
         self.total_bytes
     }
 
+    /// Records the file size once discovered, e.g. from `ChunkedDownloader::inspect`
+    pub fn set_total_bytes(&mut self, total_bytes: u64) {
+        self.total_bytes = Some(total_bytes);
+    }
+
     /// Returns the download progress as a percentage (0.0 to 100.0)
     pub fn progress_percent(&self) -> f64 {
         match self.total_bytes {
@@ -112,6 +263,59 @@ impl Download {
         }
     }
 
+    /// Returns the average speed in bytes per second since the download started
+    ///
+    /// Uses the completion time for finished downloads and the current time
+    /// otherwise. Returns `None` if not started or no time has elapsed yet.
+    pub fn average_speed(&self) -> Option<f64> {
+        let started = self.started_at?;
+        let end = self.completed_at.unwrap_or_else(SystemTime::now);
+
+        // clock may have gone backwards, clamp to zero
+        let elapsed = end.duration_since(started).unwrap_or_default();
+
+        if elapsed.is_zero() {
+            return None;
+        }
+
+        Some(self.bytes_downloaded as f64 / elapsed.as_secs_f64())
+    }
+
+    /// Records the bytes downloaded so far, as one point of `speed_history`
+    ///
+    /// Meant to be called on a fixed timer, e.g. once a second, rather than
+    /// on every write, so the points come out evenly spaced. A sample that
+    /// isn't later than the previous one is ignored.
+    pub fn sample_speed(&mut self, now: SystemTime) {
+        if let Some(&(last, _)) = self.speed_samples.back() {
+            if now <= last {
+                return;
+            }
+        }
+
+        // one more sample than points, each point spans two samples
+        if self.speed_samples.len() > SPEED_HISTORY_LEN {
+            self.speed_samples.pop_front();
+        }
+        self.speed_samples.push_back((now, self.bytes_downloaded));
+    }
+
+    /// Returns the speed in bytes per second between consecutive samples
+    ///
+    /// Each point is stamped with the later sample's time, oldest first, and
+    /// at most `SPEED_HISTORY_LEN` are kept. Progress that went backwards,
+    /// e.g. after a restart, counts as zero.
+    pub fn speed_history(&self) -> Vec<(SystemTime, f64)> {
+        self.speed_samples
+            .iter()
+            .zip(self.speed_samples.iter().skip(1))
+            .map(|(&(from, before), &(to, after))| {
+                let elapsed = to.duration_since(from).unwrap_or_default().as_secs_f64();
+                (to, after.saturating_sub(before) as f64 / elapsed)
+            })
+            .collect()
+    }
+
     /// Returns when the download was created
     pub fn created_at(&self) -> SystemTime {
         self.created_at
@@ -127,11 +331,145 @@ impl Download {
         self.completed_at
     }
 
+    /// Returns when the download is scheduled to start, if scheduled
+    pub fn scheduled_at(&self) -> Option<SystemTime> {
+        self.scheduled_at
+    }
+
+    /// Schedules the download to start at the given time
+    pub fn schedule(&mut self, at: SystemTime) {
+        self.scheduled_at = Some(at);
+    }
+
+    /// Removes any scheduled start time
+    pub fn clear_schedule(&mut self) {
+        self.scheduled_at = None;
+    }
+
+    /// Returns true if the download may start at `now`
+    ///
+    /// Unscheduled downloads are always due.
+    pub fn is_due(&self, now: SystemTime) -> bool {
+        match self.scheduled_at {
+            Some(at) => now >= at,
+            None => true,
+        }
+    }
+
+    /// Returns the per-download retry limit, if overridden
+    pub fn max_retries(&self) -> Option<u32> {
+        self.max_retries
+    }
+
+    /// Overrides the global retry limit for this download
+    pub fn set_max_retries(&mut self, max_retries: u32) {
+        self.max_retries = Some(max_retries);
+    }
+
+    /// Returns the per-download initial retry delay, if overridden
+    pub fn retry_delay_ms(&self) -> Option<u64> {
+        self.retry_delay_ms
+    }
+
+    /// Overrides the global initial retry delay for this download
+    pub fn set_retry_delay_ms(&mut self, delay_ms: u64) {
+        self.retry_delay_ms = Some(delay_ms);
+    }
+
+    /// Returns the per-download deadline in milliseconds, if set
+    pub fn deadline_ms(&self) -> Option<u64> {
+        self.deadline_ms
+    }
+
+    /// Gives up on this download if it isn't done within `deadline_ms`
+    pub fn set_deadline_ms(&mut self, deadline_ms: u64) {
+        self.deadline_ms = Some(deadline_ms);
+    }
+
+    /// Returns the per-download chunk count, if overridden
+    pub fn chunk_count(&self) -> Option<u8> {
+        self.chunk_count
+    }
+
+    /// Overrides the global number of parallel chunks for this download
+    pub fn set_chunk_count(&mut self, chunk_count: u8) {
+        self.chunk_count = Some(chunk_count);
+    }
+
+    /// Returns the checksum the finished file should match, if known
+    pub fn checksum(&self) -> Option<&Checksum> {
+        self.checksum.as_ref()
+    }
+
+    /// Verifies the finished file against `checksum`, see `ChunkedDownloader::download_verified`
+    pub fn set_checksum(&mut self, checksum: Checksum) {
+        self.checksum = Some(checksum);
+    }
+
+    /// Returns how many times the download's chunks have been retried
+    pub fn retry_count(&self) -> u32 {
+        self.retry_count
+    }
+
+    /// Adds retry attempts, e.g. from `DownloadOutcome::retries`
+    pub fn record_retries(&mut self, retries: u32) {
+        self.retry_count = self.retry_count.saturating_add(retries);
+    }
+
+    /// Returns how many received bytes the download has thrown away
+    pub fn wasted_bytes(&self) -> u64 {
+        self.wasted_bytes
+    }
+
+    /// Adds thrown away bytes, e.g. from `DownloadOutcome::wasted_bytes`
+    pub fn record_wasted(&mut self, bytes: u64) {
+        self.wasted_bytes = self.wasted_bytes.saturating_add(bytes);
+    }
+
+    /// Returns the User-Agent override for this download, if any
+    pub fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+
+    /// Sends a different User-Agent for this download, e.g. a browser's
+    pub fn set_user_agent(&mut self, user_agent: String) {
+        self.user_agent = Some(user_agent);
+    }
+
+    /// Returns the directory the finished archive should be extracted into, if any
+    pub fn extract_to(&self) -> Option<&PathBuf> {
+        self.extract_to.as_ref()
+    }
+
+    /// Extracts the file into `dir` once it's downloaded and verified
+    ///
+    /// See `extract_archive`; downloads without this are never extracted.
+    pub fn set_extract_to(&mut self, dir: PathBuf) {
+        self.extract_to = Some(dir);
+    }
+
+    /// Returns why extracting the finished archive failed, if it did
+    pub fn extraction_error(&self) -> Option<&str> {
+        self.extraction_error.as_deref()
+    }
+
+    /// Records a failed extraction
+    ///
+    /// The download itself succeeded, so the status is left as it is.
+    pub fn set_extraction_failed(&mut self, error: String) {
+        self.extraction_error = Some(error);
+    }
+
     /// Returns the error message if download failed
     pub fn error_message(&self) -> Option<&str> {
         self.error_message.as_deref()
     }
 
+    /// Returns why the download was paused, if it wasn't by request
+    pub fn pause_reason(&self) -> Option<&str> {
+        self.pause_reason.as_deref()
+    }
+
     /// Marks the download as started
     pub fn start(&mut self) {
         self.status = DownloadStatus::Downloading;
@@ -143,9 +481,16 @@ impl Download {
         self.status = DownloadStatus::Paused;
     }
 
+    /// Pauses the download, recording why
+    pub fn pause_with_reason(&mut self, reason: String) {
+        self.status = DownloadStatus::Paused;
+        self.pause_reason = Some(reason);
+    }
+
     /// Resumes a paused download
     pub fn resume(&mut self) {
         self.status = DownloadStatus::Downloading;
+        self.pause_reason = None;
     }
 
     /// Marks the download as completed
@@ -161,6 +506,23 @@ impl Download {
         self.completed_at = Some(SystemTime::now());
     }
 
+    /// Records the error a download attempt ended with
+    ///
+    /// `TooManyFailures` pauses the download with the error as the reason,
+    /// so it can be resumed once conditions improve; any other error fails it.
+    pub fn fail_with(&mut self, error: &DownloadError) {
+        match error {
+            DownloadError::TooManyFailures { .. } => self.pause_with_reason(error.to_string()),
+            _ => self.fail(error.to_string()),
+        }
+    }
+
+    /// Records what a finished download attempt reported
+    pub fn record_outcome(&mut self, outcome: &DownloadOutcome) {
+        self.record_retries(outcome.retries);
+        self.record_wasted(outcome.wasted_bytes);
+    }
+
     /// Updates the download progress
     pub fn update_progress(&mut self, bytes_downloaded: u64, total_bytes: Option<u64>) {
         self.bytes_downloaded = bytes_downloaded;
@@ -170,6 +532,59 @@ impl Download {
     }
 }
 
+/// Returns the unfinished download for the same link as `url`, if any
+///
+/// Links are compared after `normalize_url`, so re-adding a link that is
+/// already queued or running can reuse that download instead of starting
+/// a second one. Completed and failed downloads never match, as adding
+/// their link again is a deliberate re-download. An unparseable `url`
+/// matches nothing.
+pub fn find_by_url<'a>(
+    downloads: impl IntoIterator<Item = &'a Download>,
+    url: &str,
+    sort_query: bool,
+) -> Option<DownloadId> {
+    let wanted = normalize_url(url, sort_query).ok()?;
+
+    downloads
+        .into_iter()
+        .filter(|d| !matches!(d.status, DownloadStatus::Completed | DownloadStatus::Failed))
+        .find(|d| normalize_url(&d.url, sort_query).is_ok_and(|u| u == wanted))
+        .map(|d| d.id)
+}
+
+/// Returns the downloads in `category`, in the order of `downloads`
+///
+/// Categories are compared exactly, and downloads without one are never
+/// listed.
+pub fn list_by_category<'a>(
+    downloads: impl IntoIterator<Item = &'a Download>,
+    category: &str,
+) -> Vec<DownloadId> {
+    downloads
+        .into_iter()
+        .filter(|d| d.category() == Some(category))
+        .map(|d| d.id)
+        .collect()
+}
+
+/// Returns the downloads matching `query`, see `Download::matches`
+///
+/// With `status`, only downloads in that state are returned. IDs come back
+/// in the order of `downloads`, so a filtered list keeps its order.
+pub fn search<'a>(
+    downloads: impl IntoIterator<Item = &'a Download>,
+    query: &str,
+    status: Option<DownloadStatus>,
+) -> Vec<DownloadId> {
+    downloads
+        .into_iter()
+        .filter(|d| status.map_or(true, |s| d.status == s))
+        .filter(|d| d.matches(query))
+        .map(|d| d.id)
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -292,6 +707,26 @@ mod tests {
         assert!(download.completed_at().is_some());
     }
 
+    #[test]
+    fn test_download_fail_with() {
+        let id = DownloadId::new(26);
+        let mut download = Download::new(id, "https://example.com/file.zip".to_string());
+
+        // a flaky download is paused for later, with the reason kept
+        download.start();
+        download.fail_with(&DownloadError::TooManyFailures { failures: 6, window_ms: 60_000 });
+        assert_eq!(download.status(), DownloadStatus::Paused);
+        assert_eq!(download.pause_reason(), Some("6 chunk failures within 60000 ms"));
+        assert!(download.error_message().is_none());
+
+        download.resume();
+        assert!(download.pause_reason().is_none());
+
+        download.fail_with(&DownloadError::HttpError(404));
+        assert_eq!(download.status(), DownloadStatus::Failed);
+        assert_eq!(download.error_message(), Some("HTTP error: 404"));
+    }
+
     #[test]
     fn test_download_progress_update() {
         // Test updating progress
@@ -304,4 +739,249 @@ mod tests {
         assert_eq!(download.total_bytes(), Some(1000));
         assert_eq!(download.progress_percent(), 50.0);
     }
+
+    #[test]
+    fn test_download_average_speed() {
+        // Test average speed over a finished download
+        let id = DownloadId::new(12);
+        let mut download = Download::new(id, "https://example.com/file.zip".to_string());
+
+        // no speed before the download starts
+        assert_eq!(download.average_speed(), None);
+
+        let start = SystemTime::now();
+        download.start();
+        download.started_at = Some(start);
+        download.update_progress(4_000, Some(4_000));
+        download.complete();
+        download.completed_at = Some(start + std::time::Duration::from_secs(2));
+
+        assert_eq!(download.average_speed(), Some(2_000.0));
+    }
+
+    #[test]
+    fn test_download_average_speed_clock_backwards() {
+        // A completion time before the start must not panic or go negative
+        let id = DownloadId::new(13);
+        let mut download = Download::new(id, "https://example.com/file.zip".to_string());
+
+        let start = SystemTime::now();
+        download.start();
+        download.started_at = Some(start);
+        download.update_progress(1_000, None);
+        download.complete();
+        download.completed_at = Some(start - std::time::Duration::from_secs(5));
+
+        assert_eq!(download.average_speed(), None);
+    }
+
+    #[test]
+    fn test_download_category() {
+        // Test filtering downloads by category
+        let mut video = Download::new(DownloadId::new(14), "https://a.com/a.mp4".to_string());
+        let mut backup = Download::new(DownloadId::new(15), "https://a.com/b.tar".to_string());
+        let plain = Download::new(DownloadId::new(16), "https://a.com/c.txt".to_string());
+        let mut clip = Download::new(DownloadId::new(13), "https://a.com/d.mp4".to_string());
+
+        video.set_category("Videos".to_string());
+        backup.set_category("Backups".to_string());
+        clip.set_category("Videos".to_string());
+
+        let downloads = [video, backup, plain, clip];
+        let videos = list_by_category(&downloads, "Videos");
+
+        assert_eq!(videos, vec![DownloadId::new(14), DownloadId::new(13)]);
+        assert_eq!(list_by_category(&downloads, "Backups"), vec![DownloadId::new(15)]);
+        assert!(list_by_category(&downloads, "videos").is_empty());
+        assert_eq!(downloads[2].category(), None);
+    }
+
+    #[test]
+    fn test_download_matches() {
+        // Test case-insensitive search over URL and file name
+        let id = DownloadId::new(17);
+        let mut download = Download::new(id, "https://Mirror.example.com/get?id=42".to_string());
+
+        assert!(download.matches("mirror"));
+        assert!(download.matches(""));
+        assert!(!download.matches("ubuntu"));
+
+        // file name is searched once known
+        download.set_file_path(PathBuf::from("/downloads/Ubuntu-24.04.iso"));
+        assert!(download.matches("ubuntu"));
+        assert!(!download.matches("downloads")); // directory isn't part of the name
+    }
+
+    #[test]
+    fn test_download_schedule() {
+        // Test scheduling and clearing a start time
+        let id = DownloadId::new(11);
+        let mut download = Download::new(id, "https://example.com/file.zip".to_string());
+        let now = SystemTime::now();
+
+        // unscheduled downloads can start right away
+        assert_eq!(download.scheduled_at(), None);
+        assert!(download.is_due(now));
+
+        let later = now + std::time::Duration::from_secs(3600);
+        download.schedule(later);
+
+        assert_eq!(download.scheduled_at(), Some(later));
+        assert!(!download.is_due(now));
+        assert!(download.is_due(later));
+
+        download.clear_schedule();
+        assert!(download.is_due(now));
+    }
+
+    #[test]
+    fn test_download_retry_count() {
+        // Test accumulating retries across chunks and runs
+        let id = DownloadId::new(18);
+        let mut download = Download::new(id, "https://example.com/file.zip".to_string());
+
+        assert_eq!(download.retry_count(), 0);
+
+        download.record_retries(3);
+        download.record_retries(4);
+        assert_eq!(download.retry_count(), 7);
+
+        download.record_wasted(4096);
+        assert_eq!(download.wasted_bytes(), 4096);
+    }
+
+    #[test]
+    fn test_download_set_total_bytes() {
+        // Test progress once the size is learned after starting
+        let id = DownloadId::new(19);
+        let mut download = Download::new(id, "https://example.com/file.zip".to_string());
+
+        download.start();
+        download.update_progress(250, None);
+        assert_eq!(download.progress_percent(), 0.0); // size still unknown
+
+        download.set_total_bytes(1000);
+        assert_eq!(download.total_bytes(), Some(1000));
+        assert_eq!(download.progress_percent(), 25.0);
+    }
+
+    #[test]
+    fn test_download_rewrite_url() {
+        // Test the original URL is kept for display after rewriting
+        let id = DownloadId::new(20);
+        let url = "https://github.com/fluxaus/fluxdm/blob/main/README.md";
+        let mut download = Download::new(id, url.to_string());
+        assert_eq!(download.download_url(), url);
+
+        download.rewrite_url(&UrlRewriters::with_builtin());
+        assert_eq!(download.url(), url);
+        assert_eq!(
+            download.download_url(),
+            "https://raw.githubusercontent.com/fluxaus/fluxdm/main/README.md"
+        );
+
+        // rewriting again starts from the original URL
+        download.rewrite_url(&UrlRewriters::new());
+        assert_eq!(download.download_url(), url);
+    }
+
+    #[test]
+    fn test_find_by_url() {
+        // Test the same link added twice is found, however it's written
+        let first = Download::new(DownloadId::new(24), "https://example.com/a.iso?x=1&y=2".into());
+        let mut done = Download::new(DownloadId::new(25), "https://example.com/b.iso".into());
+        done.complete();
+        let queue = vec![first, done];
+
+        let again = "HTTPS://EXAMPLE.com:443/a.iso?x=1&y=2#mirror";
+        assert_eq!(find_by_url(&queue, again, false), Some(DownloadId::new(24)));
+
+        let reordered = "https://example.com/a.iso?y=2&x=1";
+        assert_eq!(find_by_url(&queue, reordered, false), None);
+        assert_eq!(find_by_url(&queue, reordered, true), Some(DownloadId::new(24)));
+
+        // finished downloads may be fetched again
+        assert_eq!(find_by_url(&queue, "https://example.com/b.iso", false), None);
+        assert_eq!(find_by_url(&queue, "not a url", false), None);
+    }
+
+    #[test]
+    fn test_search() {
+        // Test searching the list keeps its order and filters by status
+        let iso = Download::new(DownloadId::new(27), "https://example.com/Ubuntu.iso".into());
+        let mut done = Download::new(DownloadId::new(26), "https://ubuntu.com/notes".into());
+        done.complete();
+        let other = Download::new(DownloadId::new(28), "https://example.com/a.zip".into());
+        let queue = vec![iso, done, other];
+
+        let all = search(&queue, "UBUNTU", None);
+        assert_eq!(all, vec![DownloadId::new(27), DownloadId::new(26)]);
+
+        let completed = search(&queue, "ubuntu", Some(DownloadStatus::Completed));
+        assert_eq!(completed, vec![DownloadId::new(26)]);
+
+        assert_eq!(search(&queue, "", Some(DownloadStatus::Pending)).len(), 2);
+        assert!(search(&queue, "debian", None).is_empty());
+    }
+
+    #[test]
+    fn test_download_resolved_url() {
+        // Test only a redirect target differing from the URL is kept
+        let id = DownloadId::new(23);
+        let mut download = Download::new(id, "https://example.com/get/42".to_string());
+        assert_eq!(download.resolved_url(), None);
+
+        download.set_resolved_url("https://example.com/get/42".to_string());
+        assert_eq!(download.resolved_url(), None);
+
+        download.set_resolved_url("https://cdn.example.net/42?sig=abc".to_string());
+        assert_eq!(download.resolved_url(), Some("https://cdn.example.net/42?sig=abc"));
+        assert_eq!(download.download_url(), "https://example.com/get/42");
+    }
+
+    #[test]
+    fn test_download_extraction_failure() {
+        // Test a failed extraction leaves the download completed
+        let id = DownloadId::new(21);
+        let mut download = Download::new(id, "https://example.com/file.zip".to_string());
+        assert_eq!(download.extract_to(), None);
+
+        download.set_extract_to(PathBuf::from("/tmp/file"));
+        download.start();
+        download.complete();
+        download.set_extraction_failed("not a supported archive".to_string());
+
+        assert_eq!(download.status(), DownloadStatus::Completed);
+        assert_eq!(download.extraction_error(), Some("not a supported archive"));
+        assert_eq!(download.error_message(), None);
+    }
+
+    #[test]
+    fn test_download_speed_history() {
+        // Test speed points between evenly spaced samples
+        let id = DownloadId::new(22);
+        let mut download = Download::new(id, "https://example.com/file.zip".to_string());
+        let start = SystemTime::now();
+        let at = |secs| start + std::time::Duration::from_secs(secs);
+
+        download.sample_speed(at(0));
+        assert!(download.speed_history().is_empty());
+
+        download.update_progress(1_000, None);
+        download.sample_speed(at(1));
+        download.update_progress(3_000, None);
+        download.sample_speed(at(2));
+        download.sample_speed(at(2)); // same instant, ignored
+
+        assert_eq!(download.speed_history(), [(at(1), 1_000.0), (at(2), 2_000.0)]);
+
+        // only the latest points are kept
+        for secs in 3..100 {
+            download.update_progress(secs * 1_000, None);
+            download.sample_speed(at(secs));
+        }
+        let history = download.speed_history();
+        assert_eq!(history.len(), SPEED_HISTORY_LEN);
+        assert_eq!(history[SPEED_HISTORY_LEN - 1], (at(99), 1_000.0));
+    }
 }
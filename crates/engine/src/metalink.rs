@@ -0,0 +1,256 @@
+//! Metalink (RFC 5854 `.meta4` and v3 `.metalink`) parsing
+
+use crate::filename::clean;
+use crate::{Checksum, DownloadError};
+use roxmltree::{Document, Node};
+
+/// A file described by a metalink: where to get it and how to check it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetalinkFile {
+    /// File name, with any directory parts stripped
+    pub name: String,
+    /// Expected size in bytes, if listed
+    pub size: Option<u64>,
+    /// HTTP(S) mirror URLs, most preferred first
+    pub mirrors: Vec<String>,
+    /// Whole-file digests, in the order listed
+    pub checksums: Vec<Checksum>,
+}
+
+impl MetalinkFile {
+    /// Returns the strongest listed checksum, e.g. SHA-256 over MD5
+    pub fn checksum(&self) -> Option<&Checksum> {
+        // longer digests come from stronger algorithms
+        self.checksums.iter().max_by_key(|c| c.expected().len())
+    }
+}
+
+/// Parses a metalink document into the files it describes
+///
+/// The document can come from a local file or from
+/// `HttpDownloader::download_bytes`. Only HTTP(S) mirrors and whole-file
+/// MD5/SHA digests are used; piece hashes, torrents and other URL types
+/// are ignored. Fails with `DownloadError::InvalidMetalink` on malformed
+/// input or a file with no usable mirror.
+pub fn parse_metalink(xml: &str) -> Result<Vec<MetalinkFile>, DownloadError> {
+    let doc = Document::parse(xml).map_err(|e| DownloadError::InvalidMetalink(e.to_string()))?;
+    let root = doc.root_element();
+
+    if !is(root, "metalink") {
+        return Err(DownloadError::InvalidMetalink(format!(
+            "Expected a metalink document, found <{}>",
+            root.tag_name().name()
+        )));
+    }
+
+    let files = root
+        .descendants()
+        .filter(|n| is(*n, "file"))
+        .map(parse_file)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if files.is_empty() {
+        return Err(DownloadError::InvalidMetalink(
+            "No files listed".to_string(),
+        ));
+    }
+
+    Ok(files)
+}
+
+/// Parses one `<file>` element
+fn parse_file(file: Node) -> Result<MetalinkFile, DownloadError> {
+    let name = file
+        .attribute("name")
+        .and_then(clean)
+        .ok_or_else(|| DownloadError::InvalidMetalink("File without a name".to_string()))?;
+
+    let size = match file.children().find(|n| is(*n, "size")) {
+        Some(node) => Some(text(node).parse::<u64>().map_err(|_| {
+            DownloadError::InvalidMetalink(format!("Invalid size for {}: {:?}", name, text(node)))
+        })?),
+        None => None,
+    };
+
+    // v4 lists hashes under <file>, v3 under <verification>; hashes under
+    // <pieces> cover parts of the file and aren't used
+    let whole_file_hashes = file.descendants().filter(|n| {
+        is(*n, "hash")
+            && n.parent_element()
+                .is_some_and(|p| is(p, "file") || is(p, "verification"))
+    });
+
+    let mut checksums = Vec::new();
+    for hash in whole_file_hashes {
+        let Some(algorithm) = hash.attribute("type").and_then(algorithm_name) else {
+            continue;
+        };
+
+        let checksum = Checksum::from_hex(text(hash))
+            .ok()
+            .filter(|c| c.algorithm() == algorithm)
+            .ok_or_else(|| {
+                DownloadError::InvalidMetalink(format!("Invalid {} hash for {}", algorithm, name))
+            })?;
+        checksums.push(checksum);
+    }
+
+    // v4 ranks mirrors by `priority` (lowest first), v3 by `preference`
+    // (highest first); unranked mirrors go last, in document order
+    let mut mirrors: Vec<(u64, String)> = file
+        .descendants()
+        .filter(|n| is(*n, "url"))
+        .filter_map(|url| {
+            let address = text(url);
+            let scheme = reqwest::Url::parse(address).ok()?.scheme().to_string();
+            if scheme != "http" && scheme != "https" {
+                return None;
+            }
+
+            let rank = match (rank_attr(url, "priority"), rank_attr(url, "preference")) {
+                (Some(priority), _) => priority,
+                (None, Some(preference)) => 100u64.saturating_sub(preference),
+                (None, None) => u64::MAX,
+            };
+            Some((rank, address.to_string()))
+        })
+        .collect();
+    mirrors.sort_by_key(|(rank, _)| *rank);
+
+    if mirrors.is_empty() {
+        return Err(DownloadError::InvalidMetalink(format!(
+            "No HTTP mirrors for {}",
+            name
+        )));
+    }
+
+    Ok(MetalinkFile {
+        name,
+        size,
+        mirrors: mirrors.into_iter().map(|(_, url)| url).collect(),
+        checksums,
+    })
+}
+
+/// Whether `node` is an element called `name`, in any namespace
+fn is(node: Node, name: &str) -> bool {
+    node.is_element() && node.tag_name().name() == name
+}
+
+/// Trimmed text content of an element
+fn text<'a>(node: Node<'a, '_>) -> &'a str {
+    node.text().unwrap_or("").trim()
+}
+
+/// Parses a numeric ranking attribute
+fn rank_attr(node: Node, name: &str) -> Option<u64> {
+    node.attribute(name)?.trim().parse().ok()
+}
+
+/// Maps a metalink hash type to the `Checksum::algorithm` name
+///
+/// v4 uses the IANA names ("sha-256"), v3 drops the dash ("sha256").
+fn algorithm_name(hash_type: &str) -> Option<&'static str> {
+    match hash_type.to_ascii_lowercase().as_str() {
+        "md5" => Some("MD5"),
+        "sha-1" | "sha1" => Some("SHA-1"),
+        "sha-256" | "sha256" => Some("SHA-256"),
+        "sha-512" | "sha512" => Some("SHA-512"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256_ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const MD5_ABC: &str = "900150983cd24fb0d6963f7d28e17f72";
+
+    #[test]
+    fn test_parse_meta4() {
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <metalink xmlns="urn:ietf:params:xml:ns:metalink">
+              <file name="ubuntu.iso">
+                <size>3</size>
+                <hash type="md5">{MD5_ABC}</hash>
+                <hash type="sha-256">{SHA256_ABC}</hash>
+                <pieces length="1" type="sha-1">
+                  <hash>a9993e364706816aba3e25717850c26c9cd0d89d</hash>
+                </pieces>
+                <url priority="2">https://slow.example.com/ubuntu.iso</url>
+                <url priority="1">https://fast.example.com/ubuntu.iso</url>
+                <url>ftp://old.example.com/ubuntu.iso</url>
+                <metaurl mediatype="torrent">https://example.com/ubuntu.torrent</metaurl>
+              </file>
+            </metalink>"#
+        );
+
+        let files = parse_metalink(&xml).unwrap();
+        assert_eq!(files.len(), 1);
+
+        let file = &files[0];
+        assert_eq!(file.name, "ubuntu.iso");
+        assert_eq!(file.size, Some(3));
+        assert_eq!(
+            file.mirrors,
+            [
+                "https://fast.example.com/ubuntu.iso",
+                "https://slow.example.com/ubuntu.iso"
+            ]
+        );
+
+        // piece hashes are left out, and SHA-256 beats MD5
+        assert_eq!(file.checksums.len(), 2);
+        assert_eq!(file.checksum().unwrap().algorithm(), "SHA-256");
+    }
+
+    #[test]
+    fn test_parse_metalink_v3() {
+        let xml = format!(
+            r#"<metalink version="3.0" xmlns="http://www.metalinker.org/">
+              <files>
+                <file name="../../etc/app.tar.gz">
+                  <verification><hash type="sha256">{SHA256_ABC}</hash></verification>
+                  <resources>
+                    <url type="http" preference="10">http://b.example.com/app.tar.gz</url>
+                    <url type="http" preference="90">http://a.example.com/app.tar.gz</url>
+                  </resources>
+                </file>
+              </files>
+            </metalink>"#
+        );
+
+        let file = parse_metalink(&xml).unwrap().remove(0);
+        assert_eq!(file.name, "app.tar.gz");
+        assert_eq!(file.size, None);
+        assert_eq!(file.mirrors[0], "http://a.example.com/app.tar.gz");
+        assert_eq!(file.checksum().unwrap().algorithm(), "SHA-256");
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_input() {
+        let invalid =
+            |xml: &str| matches!(parse_metalink(xml), Err(DownloadError::InvalidMetalink(_)));
+
+        assert!(invalid("<metalink><file name='a'>"));
+        assert!(invalid("<html><body/></html>"));
+        assert!(invalid("<metalink/>"));
+        assert!(invalid(
+            "<metalink><file><url>http://a/</url></file></metalink>"
+        ));
+        assert!(invalid(
+            "<metalink><file name='a'><size>big</size><url>http://a/</url></file></metalink>"
+        ));
+        // only unsupported mirrors
+        assert!(invalid(
+            "<metalink><file name='a'><url>ftp://a/</url></file></metalink>"
+        ));
+        // digest length doesn't fit the declared algorithm
+        assert!(invalid(&format!(
+            "<metalink><file name='a'><hash type='sha-1'>{MD5_ABC}</hash>\
+             <url>http://a/</url></file></metalink>"
+        )));
+    }
+}
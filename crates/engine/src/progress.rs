@@ -0,0 +1,161 @@
+//! Progress files for tools that watch a download without linking FluxDM
+
+use crate::{Download, DownloadError, DownloadStatus};
+use std::path::{Path, PathBuf};
+
+/// A small JSON file describing a download's progress
+///
+/// Scripts and status bars can poll it instead of embedding the engine.
+/// Each `update` replaces the file through a temporary file and a rename,
+/// so readers never see half-written JSON. Call it on the same cadence as
+/// other progress reporting; it doesn't throttle itself. The file looks
+/// like:
+///
+/// ```json
+/// {"bytes":5242880,"total":10485760,"percent":50.0,"speed":1048576.0,
+///  "status":"downloading","eta":5}
+/// ```
+///
+/// `total`, `speed` and `eta` are `null` while unknown.
+#[derive(Debug, Clone)]
+pub struct ProgressFile {
+    path: PathBuf,
+}
+
+impl ProgressFile {
+    /// Creates a progress file at `path`
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Creates a progress file next to `file_path`, named `<file name>.progress`
+    pub fn for_download(file_path: &Path) -> Self {
+        let mut name = file_path.as_os_str().to_os_string();
+        name.push(".progress");
+        Self::new(name)
+    }
+
+    /// Returns where the file is written
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `download`'s progress, or removes the file once it has completed
+    pub async fn update(&self, download: &Download) -> Result<(), DownloadError> {
+        if download.status() == DownloadStatus::Completed {
+            return self.remove().await;
+        }
+
+        let mut temp = self.path.as_os_str().to_os_string();
+        temp.push(".tmp");
+
+        let json = progress_json(download).to_string();
+        tokio::fs::write(&temp, json)
+            .await
+            .map_err(|e| DownloadError::FileError(e.to_string()))?;
+        tokio::fs::rename(&temp, &self.path)
+            .await
+            .map_err(|e| DownloadError::FileError(e.to_string()))
+    }
+
+    /// Removes the file, if it exists
+    pub async fn remove(&self) -> Result<(), DownloadError> {
+        match tokio::fs::remove_file(&self.path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(DownloadError::FileError(e.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Builds the JSON written by `ProgressFile::update`
+fn progress_json(download: &Download) -> serde_json::Value {
+    // the latest sampled speed reacts to changes, the average is a fallback
+    let speed = download
+        .speed_history()
+        .last()
+        .map(|&(_, speed)| speed)
+        .or_else(|| download.average_speed());
+
+    let eta = match (download.total_bytes(), speed) {
+        (Some(total), Some(speed)) if speed > 0.0 => {
+            let remaining = total.saturating_sub(download.bytes_downloaded());
+            Some((remaining as f64 / speed).ceil() as u64)
+        }
+        _ => None,
+    };
+
+    let status = match download.status() {
+        DownloadStatus::Pending => "pending",
+        DownloadStatus::Downloading => "downloading",
+        DownloadStatus::Paused => "paused",
+        DownloadStatus::Completed => "completed",
+        DownloadStatus::Failed => "failed",
+    };
+
+    serde_json::json!({
+        "bytes": download.bytes_downloaded(),
+        "total": download.total_bytes(),
+        "percent": download.progress_percent(),
+        "speed": speed,
+        "status": status,
+        "eta": eta,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DownloadId;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_progress_json() {
+        let mut download = Download::new(DownloadId::new(1), "https://example.com/a".to_string());
+        let json = progress_json(&download);
+        assert_eq!(json["status"], "pending");
+        assert!(json["total"].is_null());
+        assert!(json["eta"].is_null());
+
+        // 1MB/s with 4MB to go
+        download.start();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        download.update_progress(0, Some(6 << 20));
+        download.sample_speed(start);
+        download.update_progress(2 << 20, None);
+        download.sample_speed(start + Duration::from_secs(2));
+
+        let json = progress_json(&download);
+        assert_eq!(json["status"], "downloading");
+        assert_eq!(json["bytes"], 2 << 20);
+        assert_eq!(json["total"], 6 << 20);
+        assert_eq!(json["speed"], 1048576.0);
+        assert_eq!(json["eta"], 4);
+    }
+
+    #[tokio::test]
+    async fn test_progress_file_lifecycle() {
+        let file_path = std::env::temp_dir().join("test_progress_file.bin");
+        let progress = ProgressFile::for_download(&file_path);
+        assert_eq!(
+            progress.path(),
+            std::env::temp_dir().join("test_progress_file.bin.progress")
+        );
+
+        let mut download = Download::new(DownloadId::new(2), "https://example.com/b".to_string());
+        download.start();
+        download.update_progress(10, Some(40));
+        progress.update(&download).await.unwrap();
+
+        let written = tokio::fs::read_to_string(progress.path()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_str(&written).unwrap();
+        assert_eq!(json["percent"], 25.0);
+
+        // finishing removes the file, and removing it again is fine
+        download.complete();
+        progress.update(&download).await.unwrap();
+        assert!(!progress.path().exists());
+        progress.remove().await.unwrap();
+    }
+}
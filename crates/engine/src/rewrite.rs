@@ -0,0 +1,369 @@
+//! URL rewriting applied before a download starts, and other URL helpers
+
+use crate::DownloadError;
+use reqwest::Url;
+use std::sync::Arc;
+
+/// Returns `url` in a canonical form, for telling whether two links are the same
+///
+/// Lowercases the scheme and host, drops default ports and the fragment,
+/// which is never sent to the server, and resolves `.` and `..` in the
+/// path. With `sort_query` the query parameters are sorted too; that's
+/// opt-in, as some servers care about their order.
+pub fn normalize_url(url: &str, sort_query: bool) -> Result<String, DownloadError> {
+    let mut url =
+        Url::parse(url.trim()).map_err(|e| DownloadError::InvalidUrl(format!("{}: {}", url, e)))?;
+    url.set_fragment(None);
+
+    if sort_query && url.query().is_some() {
+        let mut pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        pairs.sort();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+
+    Ok(url.into())
+}
+
+/// Resolves a redirect's `Location` header against the URL that was requested
+///
+/// Needed wherever redirects are followed by hand, e.g. to show where a
+/// link leads. Servers often send relative locations (`file.iso`,
+/// `/dl/file.iso`, `?token=2`) or protocol-relative ones (`//cdn/file.iso`)
+/// despite older specs asking for absolute URLs; all of these resolve the
+/// way a browser would. Surrounding whitespace is ignored.
+pub fn resolve_location(request_url: &str, location: &str) -> Result<String, DownloadError> {
+    let base = Url::parse(request_url)
+        .map_err(|e| DownloadError::InvalidUrl(format!("{}: {}", request_url, e)))?;
+
+    let location = location.trim();
+    if location.is_empty() {
+        return Err(DownloadError::InvalidUrl(format!(
+            "Empty redirect location from {}",
+            request_url
+        )));
+    }
+
+    base.join(location)
+        .map(String::from)
+        .map_err(|e| DownloadError::InvalidUrl(format!("{}: {}", location, e)))
+}
+
+/// Turns a URL into the one that should actually be downloaded
+///
+/// Returns `None` to leave the URL unchanged. Closures taking `&str` and
+/// returning `Option<String>` are rewriters too.
+pub trait UrlRewriter: Send + Sync {
+    /// Rewrites `url`, or returns `None` if this rewriter doesn't apply
+    fn rewrite(&self, url: &str) -> Option<String>;
+}
+
+impl<F> UrlRewriter for F
+where
+    F: Fn(&str) -> Option<String> + Send + Sync,
+{
+    fn rewrite(&self, url: &str) -> Option<String> {
+        self(url)
+    }
+}
+
+/// Turns GitHub file pages (`/blob/`) into raw file links
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GitHubRaw;
+
+impl UrlRewriter for GitHubRaw {
+    fn rewrite(&self, url: &str) -> Option<String> {
+        let url = Url::parse(url).ok()?;
+        if url.host_str()? != "github.com" {
+            return None;
+        }
+
+        // /<owner>/<repo>/blob/<ref>/<path>
+        let segments: Vec<&str> = url.path_segments()?.collect();
+        match segments.as_slice() {
+            [owner, repo, "blob", rest @ ..] if rest.len() >= 2 => Some(format!(
+                "https://raw.githubusercontent.com/{}/{}/{}",
+                owner,
+                repo,
+                rest.join("/")
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Asks Dropbox for the file itself instead of its preview page
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DropboxDirect;
+
+impl UrlRewriter for DropboxDirect {
+    fn rewrite(&self, url: &str) -> Option<String> {
+        let mut url = Url::parse(url).ok()?;
+        let host = url.host_str()?;
+        if host != "dropbox.com" && !host.ends_with(".dropbox.com") {
+            return None;
+        }
+
+        if url.query_pairs().any(|(k, v)| k == "dl" && v == "1") {
+            return None;
+        }
+
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(k, _)| k != "dl")
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        url.query_pairs_mut()
+            .clear()
+            .extend_pairs(pairs)
+            .append_pair("dl", "1");
+
+        Some(url.into())
+    }
+}
+
+/// Adds a query parameter, e.g. an access token, to URLs for one host
+#[derive(Debug, Clone)]
+pub struct AddQueryParam {
+    /// Host the parameter is added for, e.g. "files.example.com"
+    pub host: String,
+    /// Parameter name
+    pub name: String,
+    /// Parameter value
+    pub value: String,
+}
+
+impl AddQueryParam {
+    /// Creates a rewriter adding `name=value` to URLs for `host`
+    pub fn new(host: impl Into<String>, name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
+
+impl UrlRewriter for AddQueryParam {
+    fn rewrite(&self, url: &str) -> Option<String> {
+        let mut url = Url::parse(url).ok()?;
+        if !url.host_str()?.eq_ignore_ascii_case(&self.host) {
+            return None;
+        }
+
+        // an explicit value in the URL wins
+        if url.query_pairs().any(|(k, _)| k == self.name.as_str()) {
+            return None;
+        }
+
+        url.query_pairs_mut().append_pair(&self.name, &self.value);
+
+        Some(url.into())
+    }
+}
+
+/// An ordered chain of rewriters
+///
+/// Each rewriter sees the output of the ones registered before it.
+#[derive(Clone, Default)]
+pub struct UrlRewriters {
+    rewriters: Vec<Arc<dyn UrlRewriter>>,
+}
+
+impl UrlRewriters {
+    /// Creates an empty chain, which leaves every URL unchanged
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a chain with the built-in rewriters (`GitHubRaw`, `DropboxDirect`)
+    pub fn with_builtin() -> Self {
+        let mut rewriters = Self::new();
+        rewriters.register(GitHubRaw);
+        rewriters.register(DropboxDirect);
+        rewriters
+    }
+
+    /// Adds a rewriter to the end of the chain
+    pub fn register(&mut self, rewriter: impl UrlRewriter + 'static) {
+        self.rewriters.push(Arc::new(rewriter));
+    }
+
+    /// Returns the number of registered rewriters
+    pub fn len(&self) -> usize {
+        self.rewriters.len()
+    }
+
+    /// Returns true if no rewriters are registered
+    pub fn is_empty(&self) -> bool {
+        self.rewriters.is_empty()
+    }
+
+    /// Runs `url` through every rewriter in order
+    pub fn rewrite(&self, url: &str) -> String {
+        self.rewriters
+            .iter()
+            .fold(url.to_string(), |url, rewriter| {
+                rewriter.rewrite(&url).unwrap_or(url)
+            })
+    }
+}
+
+impl std::fmt::Debug for UrlRewriters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UrlRewriters")
+            .field("len", &self.rewriters.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_location() {
+        let base = "https://example.com/files/v2/app.iso?token=1";
+        let resolve = |location| resolve_location(base, location).unwrap();
+
+        assert_eq!(
+            resolve("app-2.iso"),
+            "https://example.com/files/v2/app-2.iso"
+        );
+        assert_eq!(
+            resolve("../v3/app.iso"),
+            "https://example.com/files/v3/app.iso"
+        );
+        assert_eq!(resolve("/dl/app.iso"), "https://example.com/dl/app.iso");
+        assert_eq!(
+            resolve("?token=2"),
+            "https://example.com/files/v2/app.iso?token=2"
+        );
+        assert_eq!(
+            resolve("//cdn.example.net/app.iso"),
+            "https://cdn.example.net/app.iso"
+        );
+        assert_eq!(
+            resolve(" http://mirror.example.org/app.iso "),
+            "http://mirror.example.org/app.iso"
+        );
+
+        assert!(matches!(
+            resolve_location(base, "  "),
+            Err(DownloadError::InvalidUrl(_))
+        ));
+        assert!(matches!(
+            resolve_location("/relative/base", "app.iso"),
+            Err(DownloadError::InvalidUrl(_))
+        ));
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(
+            normalize_url("HTTPS://Files.Example.COM:443/a/./b/../c.iso#top", false).unwrap(),
+            "https://files.example.com/a/c.iso"
+        );
+        assert_eq!(
+            normalize_url(" http://example.com:80 ", false).unwrap(),
+            "http://example.com/"
+        );
+        assert_eq!(
+            normalize_url("http://example.com:8080/x", false).unwrap(),
+            "http://example.com:8080/x"
+        );
+
+        // query order is kept unless sorting is asked for
+        let url = "https://example.com/get?v=2&id=7";
+        assert_eq!(normalize_url(url, false).unwrap(), url);
+        assert_eq!(
+            normalize_url(url, true).unwrap(),
+            "https://example.com/get?id=7&v=2"
+        );
+
+        assert!(matches!(
+            normalize_url("not a url", false),
+            Err(DownloadError::InvalidUrl(_))
+        ));
+    }
+
+    #[test]
+    fn test_github_raw() {
+        assert_eq!(
+            GitHubRaw
+                .rewrite("https://github.com/fluxaus/fluxdm/blob/main/docs/guide.md")
+                .as_deref(),
+            Some("https://raw.githubusercontent.com/fluxaus/fluxdm/main/docs/guide.md")
+        );
+
+        // repository pages and other hosts are left alone
+        assert_eq!(GitHubRaw.rewrite("https://github.com/fluxaus/fluxdm"), None);
+        assert_eq!(
+            GitHubRaw.rewrite("https://example.com/a/b/blob/main/c"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_dropbox_direct() {
+        assert_eq!(
+            DropboxDirect
+                .rewrite("https://www.dropbox.com/s/abc/file.zip?dl=0")
+                .as_deref(),
+            Some("https://www.dropbox.com/s/abc/file.zip?dl=1")
+        );
+        assert_eq!(
+            DropboxDirect.rewrite("https://www.dropbox.com/s/abc/file.zip?dl=1"),
+            None
+        );
+        assert_eq!(
+            DropboxDirect.rewrite("https://notdropbox.com/file.zip"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_add_query_param() {
+        let token = AddQueryParam::new("files.example.com", "token", "s3cr3t");
+
+        assert_eq!(
+            token
+                .rewrite("https://files.example.com/a.iso?v=2")
+                .as_deref(),
+            Some("https://files.example.com/a.iso?v=2&token=s3cr3t")
+        );
+        assert_eq!(
+            token.rewrite("https://files.example.com/a.iso?token=mine"),
+            None
+        );
+        assert_eq!(token.rewrite("https://other.example.com/a.iso"), None);
+    }
+
+    #[test]
+    fn test_chain_applies_in_order() {
+        let mut rewriters = UrlRewriters::with_builtin();
+        rewriters.register(AddQueryParam::new(
+            "raw.githubusercontent.com",
+            "token",
+            "t",
+        ));
+        rewriters
+            .register(|url: &str| url.strip_suffix("?token=t").map(|u| format!("{u}?token=T")));
+        assert_eq!(rewriters.len(), 4);
+
+        // GitHubRaw's output is what the token rewriter sees
+        assert_eq!(
+            rewriters.rewrite("https://github.com/o/r/blob/v1/app.tar.gz"),
+            "https://raw.githubusercontent.com/o/r/v1/app.tar.gz?token=T"
+        );
+
+        // no rewriter applies, the URL passes through unchanged
+        assert_eq!(
+            rewriters.rewrite("https://example.com/file.bin"),
+            "https://example.com/file.bin"
+        );
+        assert_eq!(UrlRewriters::new().rewrite("not a url"), "not a url");
+    }
+}
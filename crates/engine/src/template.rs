@@ -0,0 +1,280 @@
+//! Download paths built from a template, e.g. `{category}/{date}-{filename}`
+
+use crate::{sanitize_filename, Download, DownloadError};
+use reqwest::Url;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Tokens a `PathTemplate` may contain
+const TOKENS: [&str; 5] = ["filename", "ext", "host", "date", "category"];
+
+/// What `{category}` becomes for a download without one
+pub const UNCATEGORIZED: &str = "Uncategorized";
+
+/// What `{host}` becomes when the URL has no host
+const UNKNOWN_HOST: &str = "unknown-host";
+
+/// A relative path with placeholders, filled in per download
+///
+/// Supported tokens:
+/// - `{filename}`: the file name, e.g. from `ChunkedDownloader::resolve_filename`
+/// - `{ext}`: its extension without the dot, empty if it has none
+/// - `{host}`: the download URL's host, or `unknown-host`
+/// - `{date}`: the UTC date the download starts, as `YYYY-MM-DD`
+/// - `{category}`: the download's category, or `UNCATEGORIZED`
+///
+/// `/` separates directories. Each directory and the file name are
+/// sanitized after substitution, so a value can't add path separators. A
+/// directory that ends up empty, e.g. a lone `{ext}` for a file without
+/// one, is left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTemplate {
+    components: Vec<String>,
+}
+
+impl PathTemplate {
+    /// Parses `template`, failing with `DownloadError::InvalidConfig` if it's malformed
+    ///
+    /// Unknown tokens, unbalanced braces, absolute paths, empty directories
+    /// (`a//b`) and `.` or `..` are rejected here, so a bad template is
+    /// caught when it's configured rather than when a download starts.
+    pub fn parse(template: &str) -> Result<Self, DownloadError> {
+        let invalid = |reason: &str| {
+            DownloadError::InvalidConfig(format!(
+                "Invalid path template {:?}: {}",
+                template, reason
+            ))
+        };
+
+        if template.is_empty() {
+            return Err(invalid("empty"));
+        }
+        if template.starts_with('/') || template.starts_with('\\') {
+            return Err(invalid("must be relative"));
+        }
+
+        let components: Vec<String> = template.split('/').map(String::from).collect();
+
+        for component in &components {
+            match component.as_str() {
+                "" => return Err(invalid("empty directory name")),
+                "." | ".." => return Err(invalid("`.` and `..` aren't allowed")),
+                _ => {}
+            }
+
+            for token in tokens(component).map_err(|reason| invalid(&reason))? {
+                if !TOKENS.contains(&token) {
+                    return Err(invalid(&format!("unknown token {{{}}}", token)));
+                }
+            }
+        }
+
+        Ok(Self { components })
+    }
+
+    /// Returns the path for `download` saved as `filename`, inside `dir`
+    ///
+    /// `now` is used for `{date}`.
+    pub fn render(
+        &self,
+        dir: &Path,
+        download: &Download,
+        filename: &str,
+        now: SystemTime,
+    ) -> PathBuf {
+        let ext = match filename.rfind('.') {
+            Some(dot) if dot > 0 => &filename[dot + 1..],
+            _ => "",
+        };
+        let host = Url::parse(download.download_url())
+            .ok()
+            .and_then(|url| url.host_str().map(String::from))
+            .unwrap_or_else(|| UNKNOWN_HOST.to_string());
+        let date = utc_date(now);
+        let category = download.category().unwrap_or(UNCATEGORIZED);
+
+        let value = |token: &str| match token {
+            "filename" => filename,
+            "ext" => ext,
+            "host" => &host,
+            "date" => &date,
+            "category" => category,
+            _ => unreachable!("tokens are checked by parse"),
+        };
+
+        let mut path = dir.to_path_buf();
+        let last = self.components.len() - 1;
+
+        for (i, component) in self.components.iter().enumerate() {
+            let rendered = substitute(component, value);
+
+            // the file name always gets a name, a directory may be skipped
+            if rendered.is_empty() && i != last {
+                continue;
+            }
+            path.push(sanitize_filename(&rendered));
+        }
+
+        path
+    }
+}
+
+impl std::str::FromStr for PathTemplate {
+    type Err = DownloadError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        Self::parse(template)
+    }
+}
+
+/// Returns the names of the `{token}`s in `component`
+fn tokens(component: &str) -> Result<Vec<&str>, String> {
+    let mut found = Vec::new();
+    let mut rest = component;
+
+    while let Some(open) = rest.find(['{', '}']) {
+        if rest[open..].starts_with('}') {
+            return Err("unmatched `}`".to_string());
+        }
+
+        let after = &rest[open + 1..];
+        let close = after.find('}').ok_or("unclosed `{`")?;
+        let token = &after[..close];
+        if token.contains('{') {
+            return Err("nested `{`".to_string());
+        }
+
+        found.push(token);
+        rest = &after[close + 1..];
+    }
+
+    Ok(found)
+}
+
+/// Replaces each `{token}` in a checked `component` with `value(token)`
+fn substitute<'a>(component: &str, value: impl Fn(&str) -> &'a str) -> String {
+    let mut rendered = String::with_capacity(component.len());
+    let mut rest = component;
+
+    while let Some(open) = rest.find('{') {
+        let close = open + rest[open..].find('}').unwrap_or(rest.len() - open);
+        rendered.push_str(&rest[..open]);
+        rendered.push_str(value(&rest[open + 1..close]));
+        rest = rest.get(close + 1..).unwrap_or("");
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Formats `time` as a UTC `YYYY-MM-DD` date
+fn utc_date(time: SystemTime) -> String {
+    let days = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86_400;
+
+    // days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DownloadId;
+    use std::time::Duration;
+
+    /// 2024-02-29 12:00 UTC
+    fn leap_day() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_208_000)
+    }
+
+    #[test]
+    fn test_render_tokens() {
+        let template = PathTemplate::parse("{category}/{host}/{date}-{filename}").unwrap();
+        let mut download = Download::new(
+            DownloadId::new(1),
+            "https://Files.Example.com/iso/app.iso".to_string(),
+        );
+        download.set_category("Software".to_string());
+
+        assert_eq!(
+            template.render(Path::new("/dl"), &download, "app.iso", leap_day()),
+            Path::new("/dl/Software/files.example.com/2024-02-29-app.iso")
+        );
+    }
+
+    #[test]
+    fn test_render_fallbacks() {
+        let download = Download::new(DownloadId::new(2), "not a url".to_string());
+
+        // no category or host, and an empty {ext} directory is dropped
+        let template = PathTemplate::parse("{category}/{host}/{ext}/{filename}").unwrap();
+        assert_eq!(
+            template.render(Path::new("dl"), &download, "README", leap_day()),
+            Path::new("dl/Uncategorized/unknown-host/README")
+        );
+
+        // values can't escape their component
+        let mut download = Download::new(DownloadId::new(3), "https://a.example/x".to_string());
+        download.set_category("../etc".to_string());
+        let template = PathTemplate::parse("{category}/{ext}/{filename}").unwrap();
+        assert_eq!(
+            template.render(Path::new("dl"), &download, "notes.txt", leap_day()),
+            Path::new("dl/.._etc/txt/notes.txt")
+        );
+
+        // a file name that renders empty still gets one
+        let template = PathTemplate::parse("{ext}").unwrap();
+        assert_eq!(
+            template.render(Path::new("dl"), &download, "README", leap_day()),
+            Path::new("dl/download")
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_templates() {
+        for template in [
+            "",
+            "/abs/{filename}",
+            "{category}//{filename}",
+            "../{filename}",
+            "{name}",
+            "{filename",
+            "filename}",
+            "{{filename}}",
+        ] {
+            assert!(
+                matches!(
+                    template.parse::<PathTemplate>(),
+                    Err(DownloadError::InvalidConfig(_))
+                ),
+                "{:?}",
+                template
+            );
+        }
+
+        assert!("plain/{filename}".parse::<PathTemplate>().is_ok());
+    }
+
+    #[test]
+    fn test_utc_date() {
+        assert_eq!(utc_date(SystemTime::UNIX_EPOCH), "1970-01-01");
+        assert_eq!(utc_date(leap_day()), "2024-02-29");
+        assert_eq!(
+            utc_date(SystemTime::UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29"
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! The HTTP requests made by the chunked downloader
+
+use crate::DownloadError;
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, RequestBuilder, StatusCode, Url, Version};
+
+/// Body of a `TransportResponse`, read a buffer at a time
+pub type BodyStream = BoxStream<'static, Result<Bytes, DownloadError>>;
+
+/// Future returned by the `HttpTransport` methods
+pub type ResponseFuture<'a> = BoxFuture<'a, Result<TransportResponse, DownloadError>>;
+
+/// Sends the requests a `ChunkedDownloader` needs
+///
+/// The default transport uses reqwest. Another one, e.g. a mock that
+/// answers with canned responses or stalls on cue, can be passed to
+/// `ChunkedDownloader::with_transport` to exercise the chunking, resume
+/// and retry logic without a server. Redirects are the transport's job:
+/// the response's `url` is where it ended up.
+pub trait HttpTransport: Send + Sync {
+    /// Sends a HEAD request
+    fn head<'a>(&'a self, url: &'a str) -> ResponseFuture<'a>;
+
+    /// Sends a GET request for the whole resource
+    fn get<'a>(&'a self, url: &'a str) -> ResponseFuture<'a>;
+
+    /// Sends a GET request for bytes `start..=end`, or `start..` with no `end`
+    fn get_range<'a>(&'a self, url: &'a str, start: u64, end: Option<u64>) -> ResponseFuture<'a>;
+}
+
+/// A response to one `HttpTransport` request
+pub struct TransportResponse {
+    status: StatusCode,
+    version: Version,
+    url: Url,
+    headers: HeaderMap,
+    body: BodyStream,
+}
+
+impl TransportResponse {
+    /// Creates an HTTP/1.1 response with no headers and an empty body
+    pub fn new(status: StatusCode, url: Url) -> Self {
+        Self {
+            status,
+            version: Version::HTTP_11,
+            url,
+            headers: HeaderMap::new(),
+            body: futures_util::stream::empty().boxed(),
+        }
+    }
+
+    /// Adds a header
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` isn't a valid header value; this is meant for
+    /// building canned responses.
+    pub fn with_header(mut self, name: HeaderName, value: impl AsRef<str>) -> Self {
+        let value = HeaderValue::from_str(value.as_ref()).expect("invalid header value");
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Sets the HTTP version the response was received over
+    pub fn with_version(mut self, version: Version) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Replaces the body with `body`, sent as one buffer
+    pub fn with_body(self, body: impl Into<Bytes>) -> Self {
+        let body = body.into();
+        self.with_body_stream(futures_util::stream::once(async move { Ok(body) }).boxed())
+    }
+
+    /// Replaces the body with a stream, e.g. one that stalls or fails midway
+    pub fn with_body_stream(mut self, body: BodyStream) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// Returns the status code
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns the HTTP version the response was received over
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns the URL the response came from, after any redirects
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Returns the response headers
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Returns the body as a stream of buffers
+    pub fn bytes_stream(self) -> BodyStream {
+        self.body
+    }
+
+    /// Reads the whole body into memory
+    pub async fn bytes(self) -> Result<Bytes, DownloadError> {
+        let mut body = self.body;
+        let mut data = Vec::new();
+
+        while let Some(buffer) = body.next().await {
+            data.extend_from_slice(&buffer?);
+        }
+
+        Ok(data.into())
+    }
+}
+
+impl std::fmt::Debug for TransportResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransportResponse")
+            .field("status", &self.status)
+            .field("version", &self.version)
+            .field("url", &self.url.as_str())
+            .field("headers", &self.headers)
+            .finish_non_exhaustive()
+    }
+}
+
+/// `HttpTransport` over a reqwest client
+pub(crate) struct ReqwestTransport {
+    client: Client,
+    identity_encoding: bool, // see `ChunkConfig::identity_encoding`
+}
+
+impl ReqwestTransport {
+    pub(crate) fn new(client: Client, identity_encoding: bool) -> Self {
+        Self {
+            client,
+            identity_encoding,
+        }
+    }
+
+    /// Builds a request, asking for the identity encoding if configured
+    fn request(&self, method: reqwest::Method, url: &str) -> RequestBuilder {
+        let request = self.client.request(method, url);
+
+        if self.identity_encoding {
+            request.header("Accept-Encoding", "identity")
+        } else {
+            request
+        }
+    }
+
+    /// Sends `request`, keeping the body as a stream
+    async fn send(request: RequestBuilder) -> Result<TransportResponse, DownloadError> {
+        let response = request.send().await.map_err(DownloadError::from)?;
+
+        Ok(TransportResponse {
+            status: response.status(),
+            version: response.version(),
+            url: response.url().clone(),
+            headers: response.headers().clone(),
+            body: response
+                .bytes_stream()
+                .map(|buffer| buffer.map_err(DownloadError::from))
+                .boxed(),
+        })
+    }
+}
+
+impl HttpTransport for ReqwestTransport {
+    fn head<'a>(&'a self, url: &'a str) -> ResponseFuture<'a> {
+        Box::pin(Self::send(self.request(reqwest::Method::HEAD, url)))
+    }
+
+    fn get<'a>(&'a self, url: &'a str) -> ResponseFuture<'a> {
+        // a whole body can be decoded, so the encoding is left to the server
+        Box::pin(Self::send(self.client.get(url)))
+    }
+
+    fn get_range<'a>(&'a self, url: &'a str, start: u64, end: Option<u64>) -> ResponseFuture<'a> {
+        let range = match end {
+            Some(end) => format!("bytes={}-{}", start, end),
+            None => format!("bytes={}-", start),
+        };
+
+        Box::pin(Self::send(
+            self.request(reqwest::Method::GET, url)
+                .header("Range", range),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_encoding_header() {
+        let transport = ReqwestTransport::new(Client::new(), true);
+        let request = transport
+            .request(reqwest::Method::HEAD, "https://example.com/file.bin")
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["accept-encoding"], "identity");
+
+        let transport = ReqwestTransport::new(Client::new(), false);
+        let request = transport
+            .request(reqwest::Method::HEAD, "https://example.com/file.bin")
+            .build()
+            .unwrap();
+        assert!(request.headers().get("accept-encoding").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_canned_response() {
+        let url = Url::parse("https://example.com/file.bin").unwrap();
+        let response = TransportResponse::new(StatusCode::PARTIAL_CONTENT, url)
+            .with_header(reqwest::header::CONTENT_RANGE, "bytes 0-4/10")
+            .with_body(&b"hello"[..]);
+
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()["content-range"], "bytes 0-4/10");
+        assert_eq!(response.bytes().await.unwrap(), &b"hello"[..]);
+    }
+}
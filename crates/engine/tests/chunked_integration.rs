@@ -1,6 +1,12 @@
 //! Integration tests for chunked downloads
 
-use engine::ChunkedDownloader;
+mod common;
+
+use engine::{
+    parse_metalink, Checksum, Chunk, ChunkConfig, ChunkedDownloader, ClientConfig, Download,
+    DownloadError, DownloadId, DownloadOutcome, DownloadStatus, HttpVersionPref, IpPreference,
+    OutcomeKind, ProgressFile, RemoteInfo, RetryStatusPolicy, SegmentMode,
+};
 use tokio::fs;
 
 #[tokio::test]
@@ -82,3 +88,2135 @@ async fn test_chunk_calculation_integration() {
     let total_size: u64 = chunks.iter().map(|c| c.size()).sum();
     assert_eq!(total_size, file_size);
 }
+
+#[tokio::test]
+async fn test_download_without_content_length() {
+    // server streams with chunked encoding and never sends a length
+    let body: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+    let expected = body.clone();
+
+    let addr = common::serve(move |request| {
+        let headers = [("Transfer-Encoding", "chunked".to_string())];
+
+        if request.starts_with("HEAD") {
+            common::response("200 OK", &headers, b"")
+        } else {
+            common::response("200 OK", &headers, &common::chunked_body(&body, 1000))
+        }
+    })
+    .await;
+
+    let downloader = ChunkedDownloader::new();
+    let file_path = std::env::temp_dir().join("test_no_content_length.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    // should fall back to a single stream instead of failing
+    let url = format!("http://{}/stream", addr);
+    let bytes = downloader.download(&url, &file_path).await.unwrap();
+    assert_eq!(bytes, 5000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), expected);
+
+    // get_file_info still reports the missing length as an error
+    assert!(downloader.get_file_info(&url).await.is_err());
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_resume_without_content_length() {
+    use std::sync::{Arc, Mutex};
+
+    // ranges work, but the length is never reported, not even in Content-Range
+    let body: Vec<u8> = (0..5000u32).map(|i| (i % 241) as u8).collect();
+    let served = body.clone();
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+    let seen = ranges.clone();
+    let addr = common::serve(move |request| {
+        let mut headers = vec![
+            ("Accept-Ranges", "bytes".to_string()),
+            ("Transfer-Encoding", "chunked".to_string()),
+        ];
+        if request.starts_with("HEAD") {
+            return common::response("200 OK", &headers, b"");
+        }
+
+        let range = common::header(request, "range").map(String::from);
+        seen.lock().unwrap().push(range.clone());
+        let total = served.len() as u64;
+        match range.as_deref().and_then(|r| common::parse_range(r, total)) {
+            Some((start, end)) => {
+                headers.push(("Content-Range", format!("bytes {}-{}/*", start, end)));
+                let slice = &served[start as usize..=end as usize];
+                common::response("206 Partial Content", &headers, &common::chunked_body(slice, 700))
+            }
+            // past the end, where even a length-less server knows the total
+            None if range.is_some() => {
+                let headers = [
+                    ("Content-Range", format!("bytes */{}", total)),
+                    ("Content-Length", "0".to_string()),
+                ];
+                common::response("416 Range Not Satisfiable", &headers, b"")
+            }
+            None => common::response("200 OK", &headers, &common::chunked_body(&served, 700)),
+        }
+    })
+    .await;
+    let url = format!("http://{}/stream", addr);
+    let file_path = std::env::temp_dir().join("test_resume_no_content_length.bin");
+    fs::write(&file_path, &body[..2000]).await.unwrap();
+
+    let downloader = ChunkedDownloader::new();
+    let plan = downloader.inspect(&url, &file_path).await.unwrap();
+    assert!(plan.supports_ranges && !plan.chunked);
+    assert_eq!((plan.total_size, plan.existing_bytes), (None, 2000));
+
+    // the rest is fetched in one stream, and the total is known once it ends
+    let outcome = downloader.download_resumable_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.kind, OutcomeKind::Resumed);
+    assert_eq!((outcome.bytes_transferred, outcome.total_size), (3000, 5000));
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    // asking past the end of a finished file doesn't start it over
+    let outcome = downloader.download_resumable_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.kind, OutcomeKind::AlreadyComplete);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let ranges = ranges.lock().unwrap().clone();
+    assert_eq!(ranges, [Some("bytes=2000-".to_string()), Some("bytes=5000-".to_string())]);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_chunked_download_local() {
+    let body: Vec<u8> = (0..40_000u32).map(|i| (i % 253) as u8).collect();
+    let addr = common::serve_file(body.clone()).await;
+
+    // small chunks so the file is actually split
+    let config = ChunkConfig {
+        chunk_count: 4,
+        min_chunk_size: 1024,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/file.bin", addr);
+
+    let (size, supports_ranges) = downloader.get_file_info(&url).await.unwrap();
+    assert_eq!(size, 40_000);
+    assert!(supports_ranges);
+
+    let file_path = std::env::temp_dir().join("test_chunked_local.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    let bytes = downloader.download(&url, &file_path).await.unwrap();
+    assert_eq!(bytes, 40_000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_chunk_timeout_keeps_partial_bytes() {
+    use std::sync::{Arc, Mutex};
+
+    let body: Vec<u8> = (0..2000u32).map(|i| (i % 241) as u8).collect();
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+
+    let served = body.clone();
+    let seen = ranges.clone();
+    let addr = common::serve_stalling(move |request| {
+        let total = served.len() as u64;
+        let headers = vec![
+            ("Accept-Ranges", "bytes".to_string()),
+            ("Content-Length", total.to_string()),
+        ];
+
+        if request.starts_with("HEAD") {
+            return (common::response("200 OK", &headers, b""), false);
+        }
+
+        let range = common::header(request, "range").unwrap().to_string();
+        let (start, end) = common::parse_range(&range, total).unwrap();
+        let first_attempt = {
+            let mut seen = seen.lock().unwrap();
+            seen.push(range);
+            seen.len() == 1
+        };
+
+        let slice = &served[start as usize..=end as usize];
+        let headers = [("Content-Length", slice.len().to_string())];
+
+        if first_attempt {
+            // send half the chunk, then stop talking
+            let raw = common::response("206 Partial Content", &headers, &slice[..1000]);
+            (raw, true)
+        } else {
+            (common::response("206 Partial Content", &headers, slice), false)
+        }
+    })
+    .await;
+
+    let config = ChunkConfig {
+        chunk_count: 1,
+        per_chunk_timeout_ms: Some(300),
+        retry_delay_ms: 10,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/slow.bin", addr);
+    let file_path = std::env::temp_dir().join("test_chunk_timeout.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    let outcome = downloader.download_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.bytes_transferred, 2000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+    assert_eq!(outcome.retries, 1);
+
+    // the retry picked up after the 1000 bytes that made it through
+    let ranges = ranges.lock().unwrap().clone();
+    assert_eq!(ranges, ["bytes=0-1999", "bytes=1000-1999"]);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_chunk_timeout_reported_as_timeout() {
+    let body = vec![7u8; 2000];
+    let addr = common::serve_stalling(move |request| {
+        let headers = vec![
+            ("Accept-Ranges", "bytes".to_string()),
+            ("Content-Length", body.len().to_string()),
+        ];
+
+        if request.starts_with("HEAD") {
+            return (common::response("200 OK", &headers, b""), false);
+        }
+
+        // never finish the chunk
+        (common::response("206 Partial Content", &headers, &body[..500]), true)
+    })
+    .await;
+
+    let config = ChunkConfig {
+        chunk_count: 1,
+        per_chunk_timeout_ms: Some(200),
+        max_retries: 0,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/stuck.bin", addr);
+    let file_path = std::env::temp_dir().join("test_chunk_timeout_variant.bin");
+
+    let err = downloader.download(&url, &file_path).await.unwrap_err();
+    assert!(matches!(err, DownloadError::Timeout(_)), "got {:?}", err);
+    assert!(err.is_retryable());
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_probe_optimal_chunks() {
+    let addr = common::serve_file(vec![7u8; 2_000_000]).await;
+    let downloader = ChunkedDownloader::new();
+    let url = format!("http://{}/big.bin", addr);
+
+    // the exact answer depends on timing, but it must be a probed level
+    let chunks = downloader.probe_optimal_chunks(&url).await.unwrap();
+    assert!([1, 2, 4].contains(&chunks), "unexpected chunk count {}", chunks);
+}
+
+#[tokio::test]
+async fn test_probe_optimal_chunks_without_ranges() {
+    // no Accept-Ranges header means one connection is all we can use
+    let addr = common::serve(|_| {
+        common::response("200 OK", &[("Content-Length", "10".to_string())], b"0123456789")
+    })
+    .await;
+    let downloader = ChunkedDownloader::new();
+
+    let chunks = downloader
+        .probe_optimal_chunks(&format!("http://{}/plain.bin", addr))
+        .await
+        .unwrap();
+    assert_eq!(chunks, 1);
+}
+
+#[tokio::test]
+async fn test_download_to_dir() {
+    let addr = common::serve(|request| {
+        let headers = [
+            ("Content-Length", "5".to_string()),
+            ("Content-Disposition", "attachment; filename=\"notes.txt\"".to_string()),
+        ];
+        let body: &[u8] = if request.starts_with("HEAD") { b"" } else { b"hello" };
+        common::response("200 OK", &headers, body)
+    })
+    .await;
+
+    let downloader = ChunkedDownloader::new();
+    let dir = std::env::temp_dir().join("test_download_to_dir");
+    let _ = fs::remove_dir_all(&dir).await;
+
+    // server's name wins over the URL's
+    let url = format!("http://{}/get?id=1", addr);
+    let (path, bytes) = downloader.download_to_dir(&url, &dir).await.unwrap();
+
+    assert_eq!(path, dir.join("notes.txt"));
+    assert_eq!(bytes, 5);
+    assert_eq!(fs::read(&path).await.unwrap(), b"hello");
+
+    // the name is taken now, so the next one is numbered
+    fs::write(&path, b"edited").await.unwrap();
+    let (second, _) = downloader.download_to_dir(&url, &dir).await.unwrap();
+    assert_eq!(second, dir.join("notes (1).txt"));
+    assert_eq!(fs::read(&second).await.unwrap(), b"hello");
+    assert_eq!(fs::read(&path).await.unwrap(), b"edited");
+
+    let _ = fs::remove_dir_all(&dir).await;
+}
+
+#[tokio::test]
+async fn test_rebalance_takes_over_slow_chunk() {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    let body: Vec<u8> = (0..65_536u32).map(|i| (i % 239) as u8).collect();
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+
+    let served = body.clone();
+    let seen = ranges.clone();
+    let addr = common::serve_with(move |request| {
+        if let Some(range) = common::header(request, "range") {
+            seen.lock().unwrap().push(range.to_string());
+        }
+
+        // the first chunk's connection is slow to answer
+        let slow = common::header(request, "range") == Some("bytes=0-16383");
+        common::Reply {
+            bytes: common::file_response(request, &served),
+            delay: if slow { Duration::from_millis(500) } else { Duration::ZERO },
+            stall: false,
+        }
+    })
+    .await;
+
+    let config = ChunkConfig {
+        chunk_count: 4,
+        min_chunk_size: 1024,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/file.bin", addr);
+    let file_path = std::env::temp_dir().join("test_rebalance.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    let bytes = downloader.download(&url, &file_path).await.unwrap();
+
+    // every byte written exactly once, and the content is intact
+    assert_eq!(bytes, 65_536);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    // a free connection took over the back half of the slow chunk
+    let ranges = ranges.lock().unwrap().clone();
+    assert!(ranges.iter().any(|r| r.starts_with("bytes=8192-")), "ranges: {:?}", ranges);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_open_file_budget_serializes_chunks() {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    let body: Vec<u8> = (0..16_384u32).map(|i| (i % 229) as u8).collect();
+    let arrivals = Arc::new(Mutex::new(Vec::new()));
+
+    let served = body.clone();
+    let seen = arrivals.clone();
+    let addr = common::serve_with(move |request| {
+        let ranged = common::header(request, "range").is_some();
+        if ranged {
+            seen.lock().unwrap().push(Instant::now());
+        }
+
+        common::Reply {
+            bytes: common::file_response(request, &served),
+            delay: if ranged { Duration::from_millis(150) } else { Duration::ZERO },
+            stall: false,
+        }
+    })
+    .await;
+
+    // four segment files, but only one may be open at a time
+    let config = ChunkConfig {
+        chunk_count: 4,
+        min_chunk_size: 1024,
+        rebalance_chunks: false,
+        segment_mode: SegmentMode::SeparateFiles,
+        max_open_files: Some(1),
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/file.bin", addr);
+    let file_path = std::env::temp_dir().join("test_open_file_budget.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    assert_eq!(downloader.download(&url, &file_path).await.unwrap(), 16_384);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    // each chunk only asked for its range once the previous one had finished
+    let arrivals = arrivals.lock().unwrap().clone();
+    assert_eq!(arrivals.len(), 4);
+    for pair in arrivals.windows(2) {
+        assert!(pair[1] - pair[0] >= Duration::from_millis(150), "{:?}", arrivals);
+    }
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_retry_status_policy() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let body: Vec<u8> = (0..2000u32).map(|i| (i % 229) as u8).collect();
+    let failures = Arc::new(AtomicUsize::new(0));
+
+    // every GET fails with `status` until `failures` runs out
+    let serve_failing = |status: &'static str| {
+        let served = body.clone();
+        let failures = failures.clone();
+        common::serve(move |request| {
+            let failing = request.starts_with("GET")
+                && failures
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                    .is_ok();
+            if failing {
+                return common::response(status, &[("Content-Length", "0".into())], b"");
+            }
+            common::file_response(request, &served)
+        })
+    };
+    let file_path = std::env::temp_dir().join("test_retry_status_policy.bin");
+    let config = ChunkConfig {
+        chunk_count: 1,
+        retry_delay_ms: 10,
+        ..ChunkConfig::default()
+    };
+
+    // a 404 is fatal by default, but can be retried on request
+    let url = format!("http://{}/file.bin", serve_failing("404 Not Found").await);
+    failures.store(1, Ordering::SeqCst);
+    let downloader = ChunkedDownloader::with_config(config.clone());
+    let err = downloader.download(&url, &file_path).await.unwrap_err();
+    assert_eq!(err, DownloadError::HttpError(404));
+
+    failures.store(1, Ordering::SeqCst);
+    let retry_404 = ChunkConfig {
+        retry_statuses: RetryStatusPolicy {
+            retry: vec![404],
+            ..RetryStatusPolicy::default()
+        },
+        ..config.clone()
+    };
+    let downloader = ChunkedDownloader::with_config(retry_404);
+    let outcome = downloader.download_outcome(&url, &file_path).await.unwrap();
+    assert_eq!((outcome.bytes_transferred, outcome.retries), (2000, 1));
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    // a 503 is retried by default, but can be given up on at once
+    let url = format!("http://{}/file.bin", serve_failing("503 Service Unavailable").await);
+    failures.store(1, Ordering::SeqCst);
+    let downloader = ChunkedDownloader::with_config(config.clone());
+    let outcome = downloader.download_outcome(&url, &file_path).await.unwrap();
+    assert_eq!((outcome.bytes_transferred, outcome.retries), (2000, 1));
+
+    failures.store(1, Ordering::SeqCst);
+    let never_503 = ChunkConfig {
+        retry_statuses: RetryStatusPolicy {
+            never_retry: vec![503],
+            ..RetryStatusPolicy::default()
+        },
+        ..config
+    };
+    let downloader = ChunkedDownloader::with_config(never_503);
+    let err = downloader.download(&url, &file_path).await.unwrap_err();
+    assert_eq!(err, DownloadError::HttpError(503));
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_min_expected_size() {
+    let strict = ChunkConfig {
+        min_expected_size: Some(1),
+        ..ChunkConfig::default()
+    };
+
+    // an intentionally empty file is fine by default, but not when a
+    // minimum is set, and then fails before anything is written
+    let addr = common::serve_file(Vec::new()).await;
+    let url = format!("http://{}/empty.txt", addr);
+    let file_path = std::env::temp_dir().join("test_min_expected_empty.txt");
+    let _ = fs::remove_file(&file_path).await;
+
+    assert_eq!(ChunkedDownloader::new().download(&url, &file_path).await.unwrap(), 0);
+    assert_eq!(fs::metadata(&file_path).await.unwrap().len(), 0);
+    let _ = fs::remove_file(&file_path).await;
+
+    let downloader = ChunkedDownloader::with_config(strict.clone());
+    let err = downloader.download(&url, &file_path).await.unwrap_err();
+    assert!(matches!(err, DownloadError::EmptyResponse(0)), "{:?}", err);
+    assert!(!file_path.exists());
+
+    // a login wall answering 200 with no length and no body is only
+    // caught once the body has arrived, and the file is removed again
+    let addr = common::serve(|request| {
+        let headers = [("Transfer-Encoding", "chunked".to_string())];
+        let body: &[u8] = if request.starts_with("HEAD") { b"" } else { b"0\r\n\r\n" };
+        common::response("200 OK", &headers, body)
+    })
+    .await;
+    let url = format!("http://{}/download?id=7", addr);
+
+    let err = downloader.download_resumable(&url, &file_path).await.unwrap_err();
+    assert!(matches!(err, DownloadError::EmptyResponse(0)), "{:?}", err);
+    assert!(!file_path.exists());
+}
+
+#[tokio::test]
+async fn test_refresh_expired_resolved_url() {
+    let body: Vec<u8> = (0..6000u32).map(|i| (i % 211) as u8).collect();
+    let served = body.clone();
+    let addr = common::serve(move |request| {
+        let path = request.split_whitespace().nth(1).unwrap_or("");
+        let redirect = |to: &str| {
+            let headers = [("Location", to.to_string()), ("Content-Length", "0".into())];
+            common::response("302 Found", &headers, b"")
+        };
+
+        match path {
+            "/get/file.bin" => redirect("/cdn/file.bin?sig=fresh"),
+            "/get/other.bin" => redirect("/cdn/other.bin?sig=fresh"),
+            "/cdn/file.bin?sig=fresh" => common::file_response(request, &served),
+            "/cdn/other.bin?sig=fresh" => common::file_response(request, &served[..100]),
+            _ => common::response("403 Forbidden", &[("Content-Length", "0".into())], b""),
+        }
+    })
+    .await;
+    let original = format!("http://{}/get/file.bin", addr);
+    let stale = format!("http://{}/cdn/file.bin?sig=stale", addr);
+    let fresh = format!("http://{}/cdn/file.bin?sig=fresh", addr);
+    let downloader = ChunkedDownloader::new();
+
+    // a working URL is kept, a refused one is re-resolved from the original
+    let url = downloader.refresh_resolved_url(&original, &fresh, Some(6000)).await.unwrap();
+    assert_eq!(url, fresh);
+    let url = downloader.refresh_resolved_url(&original, &stale, Some(6000)).await.unwrap();
+    assert_eq!(url, fresh);
+
+    let file_path = std::env::temp_dir().join("test_refresh_resolved_url.bin");
+    fs::write(&file_path, &body[..2000]).await.unwrap();
+    downloader.download_resumable(&url, &file_path).await.unwrap();
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    // a re-resolved URL for a different size isn't trusted
+    let original = format!("http://{}/get/other.bin", addr);
+    let stale = format!("http://{}/cdn/other.bin?sig=stale", addr);
+    let err = downloader
+        .refresh_resolved_url(&original, &stale, Some(6000))
+        .await
+        .unwrap_err();
+    assert_eq!(err, DownloadError::ResourceChanged { expected: 6000, actual: 100 });
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+/// Serves `body` without advertising ranges, honouring them only if `honour_range`
+fn single_stream_server(
+    body: Vec<u8>,
+    honour_range: bool,
+    seen: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+) -> impl Fn(&str) -> Vec<u8> + Send + Sync + 'static {
+    move |request| {
+        let total = body.len() as u64;
+
+        if request.starts_with("HEAD") {
+            let headers = [("Content-Length", total.to_string())];
+            return common::response("200 OK", &headers, b"");
+        }
+
+        let range = common::header(request, "range").map(str::to_string);
+        seen.lock().unwrap().push(range.clone().unwrap_or_default());
+
+        match range.and_then(|r| common::parse_range(&r, total)) {
+            Some((start, end)) if honour_range => {
+                let headers = [
+                    ("Content-Length", (end - start + 1).to_string()),
+                    ("Content-Range", format!("bytes {}-{}/{}", start, end, total)),
+                ];
+                let slice = &body[start as usize..=end as usize];
+                common::response("206 Partial Content", &headers, slice)
+            }
+            _ => {
+                let headers = [("Content-Length", total.to_string())];
+                common::response("200 OK", &headers, &body)
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_resume_single_stream() {
+    use std::sync::{Arc, Mutex};
+
+    let body: Vec<u8> = (0..3000u32).map(|i| (i % 199) as u8).collect();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let addr = common::serve(single_stream_server(body.clone(), true, seen.clone())).await;
+
+    let file_path = std::env::temp_dir().join("test_resume_single_stream.bin");
+    fs::write(&file_path, &body[..1200]).await.unwrap();
+
+    let downloader = ChunkedDownloader::new();
+    let url = format!("http://{}/plain.bin", addr);
+    let bytes = downloader.download_resumable(&url, &file_path).await.unwrap();
+
+    assert_eq!(bytes, 1800);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+    assert_eq!(seen.lock().unwrap().clone(), ["bytes=1200-"]);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_resume_single_stream_range_ignored() {
+    use std::sync::{Arc, Mutex};
+
+    let body: Vec<u8> = (0..3000u32).map(|i| (i % 211) as u8).collect();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let addr = common::serve(single_stream_server(body.clone(), false, seen.clone())).await;
+
+    let file_path = std::env::temp_dir().join("test_resume_single_ignored.bin");
+    fs::write(&file_path, &body[..1200]).await.unwrap();
+
+    let downloader = ChunkedDownloader::new();
+    let url = format!("http://{}/plain.bin", addr);
+    let bytes = downloader.download_resumable(&url, &file_path).await.unwrap();
+
+    // the full body came back, so the file was rewritten rather than appended to
+    assert_eq!(bytes, 3000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_download_range() {
+    use std::sync::{Arc, Mutex};
+
+    let body: Vec<u8> = (0..5000u32).map(|i| (i % 227) as u8).collect();
+    let addr = common::serve_file(body.clone()).await;
+    let url = format!("http://{}/file.bin", addr);
+    let downloader = ChunkedDownloader::new();
+
+    let mut head = Vec::new();
+    let bytes = downloader.download_range(&url, 0, 999, &mut head).await.unwrap();
+    assert_eq!(bytes, 1000);
+    assert_eq!(head, body[..1000]);
+
+    // asking past the end gets the rest of the file
+    let mut tail = Vec::new();
+    let bytes = downloader.download_range(&url, 4500, 9999, &mut tail).await.unwrap();
+    assert_eq!(bytes, 500);
+    assert_eq!(tail, body[4500..]);
+
+    // a server that ignores the range sends the whole file instead
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let addr = common::serve(single_stream_server(body, false, seen)).await;
+    let url = format!("http://{}/plain.bin", addr);
+
+    let mut sink = Vec::new();
+    let err = downloader.download_range(&url, 0, 999, &mut sink).await.unwrap_err();
+    assert!(matches!(err, DownloadError::RangeNotSupported));
+    assert!(sink.is_empty());
+}
+
+#[tokio::test]
+async fn test_download_outcomes() {
+    use std::sync::{Arc, Mutex};
+
+    let body: Vec<u8> = (0..4000u32).map(|i| (i % 223) as u8).collect();
+    let addr = common::serve_file(body.clone()).await;
+    let url = format!("http://{}/file.bin", addr);
+
+    let file_path = std::env::temp_dir().join("test_download_outcomes.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    let downloader = ChunkedDownloader::new();
+    let outcome = downloader.download_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(
+        outcome,
+        DownloadOutcome {
+            bytes_transferred: 4000,
+            total_size: 4000,
+            kind: OutcomeKind::Fresh,
+            last_modified: None,
+            retries: 0,
+            wasted_bytes: 0,
+            timings: outcome.timings.clone(),
+        }
+    );
+    assert!(!outcome.timings.is_empty());
+
+    // nothing left to fetch the second time round
+    let outcome = downloader.download_resumable_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.kind, OutcomeKind::AlreadyComplete);
+    assert_eq!((outcome.bytes_transferred, outcome.total_size), (0, 4000));
+
+    // a partial single-stream file is continued, not restarted
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let addr = common::serve(single_stream_server(body.clone(), true, seen)).await;
+    let url = format!("http://{}/plain.bin", addr);
+    fs::write(&file_path, &body[..1500]).await.unwrap();
+
+    let outcome = downloader.download_resumable_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.kind, OutcomeKind::Resumed);
+    assert_eq!((outcome.bytes_transferred, outcome.total_size), (2500, 4000));
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_inspect_reports_resume_plan() {
+    let body = vec![3u8; 4000];
+    let addr = common::serve_file(body.clone()).await;
+
+    let file_path = std::env::temp_dir().join("test_inspect_plan.bin");
+    fs::write(&file_path, &body[..1500]).await.unwrap();
+
+    let config = ChunkConfig {
+        chunk_count: 4,
+        min_chunk_size: 100,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/plan.bin", addr);
+    let plan = downloader.inspect(&url, &file_path).await.unwrap();
+
+    assert_eq!(plan.final_url, url);
+    assert_eq!(plan.total_size, Some(4000));
+    assert!(plan.supports_ranges && plan.chunked);
+    assert_eq!(plan.chunks.len(), 4);
+    assert_eq!(plan.existing_bytes, 1500);
+    assert_eq!(plan.bytes_remaining, Some(2500));
+    assert_eq!(plan.disk_usage, Some(4000));
+    assert!(plan.is_resume());
+
+    // inspecting doesn't touch the partial file
+    assert_eq!(fs::metadata(&file_path).await.unwrap().len(), 1500);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_inspect_without_ranges() {
+    use std::sync::{Arc, Mutex};
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let server = single_stream_server(vec![1u8; 1000], true, seen.clone());
+    let addr = common::serve(server).await;
+
+    let file_path = std::env::temp_dir().join("test_inspect_no_ranges.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    let downloader = ChunkedDownloader::new();
+    let url = format!("http://{}/plain.bin", addr);
+    let plan = downloader.inspect(&url, &file_path).await.unwrap();
+
+    assert!(!plan.chunked);
+    assert_eq!(plan.chunks.len(), 1);
+    assert_eq!(plan.bytes_remaining, Some(1000));
+    assert!(!plan.is_resume());
+
+    // only the HEAD went out, no body requests
+    assert!(seen.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_resolve_override() {
+    let body: Vec<u8> = (0..3000u32).map(|i| (i % 233) as u8).collect();
+    let addr = common::serve_file(body.clone()).await;
+
+    // the host doesn't exist, the override is the only way to reach it
+    let client_config = ClientConfig::default().resolve("mirror.fluxdm.invalid", "127.0.0.1");
+    let config = ChunkConfig {
+        chunk_count: 3,
+        min_chunk_size: 100,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_client_config(config, &client_config).unwrap();
+
+    let url = format!("http://mirror.fluxdm.invalid:{}/file.bin", addr.port());
+    let file_path = std::env::temp_dir().join("test_resolve_override.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    let bytes = downloader.download(&url, &file_path).await.unwrap();
+    assert_eq!(bytes, 3000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_ip_version_filter() {
+    let body: Vec<u8> = (0..3000u32).map(|i| (i % 239) as u8).collect();
+    let addr = common::serve_file(body.clone()).await;
+
+    // "localhost" usually resolves to both ::1 and 127.0.0.1, but the
+    // server only listens on IPv4
+    let url = format!("http://localhost:{}/file.bin", addr.port());
+    let file_path = std::env::temp_dir().join("test_ip_version_filter.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    let v4_only = ClientConfig::default().ip_version(IpPreference::V4Only);
+    let downloader =
+        ChunkedDownloader::with_client_config(ChunkConfig::default(), &v4_only).unwrap();
+    assert_eq!(downloader.download(&url, &file_path).await.unwrap(), 3000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let v6_only = ClientConfig::default().ip_version(IpPreference::V6Only);
+    let downloader =
+        ChunkedDownloader::with_client_config(ChunkConfig::default(), &v6_only).unwrap();
+    let err = downloader.download(&url, &file_path).await.unwrap_err();
+    assert!(matches!(err, DownloadError::NetworkError(_)), "{:?}", err);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_http_version_preference() {
+    let body: Vec<u8> = (0..3000u32).map(|i| (i % 233) as u8).collect();
+    let addr = common::serve_file(body.clone()).await;
+    let url = format!("http://{}/file.bin", addr);
+    let file_path = std::env::temp_dir().join("test_http_version.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    // the test server only speaks HTTP/1.1, and the plan says so
+    let http1 = ClientConfig::default().http_version(HttpVersionPref::Http1Only);
+    let downloader =
+        ChunkedDownloader::with_client_config(ChunkConfig::default(), &http1).unwrap();
+    let plan = downloader.inspect(&url, &file_path).await.unwrap();
+    assert_eq!(plan.http_version, reqwest::Version::HTTP_11);
+    assert_eq!(downloader.download(&url, &file_path).await.unwrap(), 3000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    // forcing HTTP/2 on it fails rather than quietly falling back
+    let http2 = ClientConfig::default().http_version(HttpVersionPref::Http2Prior);
+    let downloader =
+        ChunkedDownloader::with_client_config(ChunkConfig::default(), &http2).unwrap();
+    assert!(downloader.inspect(&url, &file_path).await.is_err());
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_preflight_mixed_urls() {
+    let body = vec![3u8; 1500];
+    let addr = common::serve(move |request| {
+        if request.contains(" /gone.bin ") {
+            return common::response("404 Not Found", &[("Content-Length", "0".into())], b"");
+        }
+        if request.contains(" /plain.bin ") {
+            return common::response("200 OK", &[("Content-Length", "1500".into())], b"");
+        }
+        common::file_response(request, &body)
+    })
+    .await;
+
+    // nothing listens on a port that was just released
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    let urls = vec![
+        format!("http://{}/file.bin", addr),
+        format!("http://{}/gone.bin", addr),
+        format!("http://{}/file.bin", closed),
+        format!("http://{}/plain.bin", addr),
+    ];
+    let downloader = ChunkedDownloader::new();
+    let results = downloader.preflight(&urls).await;
+    assert_eq!(results.len(), 4);
+
+    let info = results[0].as_ref().unwrap();
+    assert_eq!(
+        info,
+        &RemoteInfo {
+            final_url: urls[0].clone(),
+            size: Some(1500),
+            supports_ranges: true,
+        }
+    );
+    assert!(info.is_resumable());
+
+    assert_eq!(results[1], Err(DownloadError::HttpError(404)));
+    assert!(matches!(results[2], Err(DownloadError::NetworkError(_))));
+
+    // reachable, but an interrupted download would start over
+    let info = results[3].as_ref().unwrap();
+    assert_eq!(info.size, Some(1500));
+    assert!(!info.is_resumable());
+}
+
+#[test]
+fn test_resolve_override_malformed() {
+    let client_config = ClientConfig::default().resolve("mirror.fluxdm.invalid", "127.0.0");
+    let result = ChunkedDownloader::with_client_config(ChunkConfig::default(), &client_config);
+
+    assert!(matches!(result, Err(DownloadError::InvalidConfig(_))));
+}
+
+#[tokio::test]
+async fn test_resume_after_remote_shrank() {
+    use std::sync::{Arc, Mutex};
+
+    // the file is replaced by a smaller version between attempts
+    let old: Vec<u8> = (0..6000u32).map(|i| (i % 199) as u8).collect();
+    let new: Vec<u8> = (0..4000u32).map(|i| (i % 197) as u8).collect();
+    let serving = Arc::new(Mutex::new(old.clone()));
+    let current = serving.clone();
+    let addr = common::serve(move |request| {
+        common::file_response(request, &current.lock().unwrap())
+    })
+    .await;
+    let url = format!("http://{}/shrunk.bin", addr);
+    let file_path = std::env::temp_dir().join("test_resume_remote_shrank.bin");
+
+    // a preallocated partial of the old version is as long as the old file
+    let mut partial = vec![0u8; 6000];
+    partial[..2500].copy_from_slice(&old[..2500]);
+    fs::write(&file_path, &partial).await.unwrap();
+    *serving.lock().unwrap() = new.clone();
+
+    let config = ChunkConfig {
+        chunk_count: 2,
+        min_chunk_size: 100,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let plan = downloader.inspect(&url, &file_path).await.unwrap();
+    assert!(!plan.is_resume());
+    assert_eq!(plan.bytes_remaining, Some(4000));
+
+    // it would look complete, but it's from another version and starts over
+    let outcome = downloader.download_resumable_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.kind, OutcomeKind::Fresh);
+    assert_eq!(outcome.total_size, 4000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), new);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_custom_user_agent_sent() {
+    use std::sync::{Arc, Mutex};
+
+    let agents = Arc::new(Mutex::new(Vec::new()));
+    let seen = agents.clone();
+    let addr = common::serve(move |request| {
+        seen.lock().unwrap().push(common::header(request, "user-agent").unwrap_or("").to_string());
+        common::file_response(request, &[9u8; 500])
+    })
+    .await;
+
+    let client_config = ClientConfig::default().user_agent("Mozilla/5.0 (X11; Linux x86_64)");
+    let downloader =
+        ChunkedDownloader::with_client_config(ChunkConfig::default(), &client_config).unwrap();
+    let url = format!("http://{}/ua.bin", addr);
+    let file_path = std::env::temp_dir().join("test_custom_user_agent.bin");
+
+    downloader.download(&url, &file_path).await.unwrap();
+
+    // the probe and the chunk request both carry it
+    let agents = agents.lock().unwrap().clone();
+    assert_eq!(agents.len(), 2);
+    assert!(agents.iter().all(|a| a == "Mozilla/5.0 (X11; Linux x86_64)"));
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_stalled_connection_reconnects() {
+    use std::sync::{Arc, Mutex};
+
+    let body: Vec<u8> = (0..2000u32).map(|i| (i % 227) as u8).collect();
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+
+    let served = body.clone();
+    let seen = ranges.clone();
+    let addr = common::serve_stalling(move |request| {
+        if request.starts_with("HEAD") {
+            return (common::file_response(request, &served), false);
+        }
+
+        let range = common::header(request, "range").unwrap().to_string();
+        let first_attempt = {
+            let mut seen = seen.lock().unwrap();
+            seen.push(range);
+            seen.len() == 1
+        };
+
+        let raw = common::file_response(request, &served);
+        if first_attempt {
+            // headers and part of the body, then the socket goes quiet
+            let cut = raw.len() - 1200;
+            (raw[..cut].to_vec(), true)
+        } else {
+            (raw, false)
+        }
+    })
+    .await;
+
+    let config = ChunkConfig {
+        chunk_count: 1,
+        stall_timeout_ms: Some(200),
+        retry_delay_ms: 10,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/stall.bin", addr);
+    let file_path = std::env::temp_dir().join("test_stalled_connection.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    let outcome = downloader.download_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.bytes_transferred, 2000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+    assert_eq!(outcome.retries, 1);
+
+    // reconnected from the last byte received, not from the chunk start
+    let ranges = ranges.lock().unwrap().clone();
+    assert_eq!(ranges, ["bytes=0-1999", "bytes=800-1999"]);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+/// Path of a segment file, matching the engine's `<name>.part<index>` naming
+fn segment(path: &std::path::Path, index: u8) -> std::path::PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".part{}", index));
+    name.into()
+}
+
+#[tokio::test]
+async fn test_separate_segment_files() {
+    let body: Vec<u8> = (0..6000u32).map(|i| (i % 239) as u8).collect();
+    let addr = common::serve_file(body.clone()).await;
+
+    let config = ChunkConfig {
+        chunk_count: 3,
+        min_chunk_size: 100,
+        segment_mode: SegmentMode::SeparateFiles,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/segments.bin", addr);
+    let file_path = std::env::temp_dir().join("test_separate_segments.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    let bytes = downloader.download(&url, &file_path).await.unwrap();
+    assert_eq!(bytes, 6000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    // segments are cleaned up after the merge
+    for index in 0..8 {
+        assert!(!segment(&file_path, index).exists());
+    }
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_separate_segment_files_resume() {
+    use std::sync::{Arc, Mutex};
+
+    let body: Vec<u8> = (0..6000u32).map(|i| (i % 251) as u8).collect();
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+
+    let served = body.clone();
+    let seen = ranges.clone();
+    let addr = common::serve(move |request| {
+        if let Some(range) = common::header(request, "range") {
+            seen.lock().unwrap().push(range.to_string());
+        }
+        common::file_response(request, &served)
+    })
+    .await;
+
+    let file_path = std::env::temp_dir().join("test_separate_segments_resume.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    // first segment done, second half done, third not started,
+    // plus a stale split segment from an earlier run
+    fs::write(segment(&file_path, 0), &body[..2000]).await.unwrap();
+    fs::write(segment(&file_path, 1), &body[2000..3000]).await.unwrap();
+    fs::write(segment(&file_path, 3), b"stale").await.unwrap();
+
+    let config = ChunkConfig {
+        chunk_count: 3,
+        min_chunk_size: 100,
+        segment_mode: SegmentMode::SeparateFiles,
+        rebalance_chunks: false,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/segments.bin", addr);
+
+    let bytes = downloader.download_resumable(&url, &file_path).await.unwrap();
+    assert_eq!(bytes, 3000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+    assert!(!segment(&file_path, 3).exists());
+
+    let mut ranges = ranges.lock().unwrap().clone();
+    ranges.sort();
+    assert_eq!(ranges, ["bytes=3000-3999", "bytes=4000-5999"]);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_temp_dir_moves_finished_file() {
+    let body: Vec<u8> = (0..5000u32).map(|i| (i % 197) as u8).collect();
+    let addr = common::serve_file(body.clone()).await;
+
+    let root = std::env::temp_dir().join("test_temp_dir_download");
+    let temp_dir = root.join("partials");
+    let dest_dir = root.join("done");
+    let _ = fs::remove_dir_all(&root).await;
+    fs::create_dir_all(&dest_dir).await.unwrap();
+
+    let config = ChunkConfig {
+        chunk_count: 2,
+        min_chunk_size: 100,
+        temp_dir: Some(temp_dir.clone()),
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/file.bin", addr);
+    let dest = dest_dir.join("file.bin");
+
+    // a partial left in the temp dir by an earlier run is picked up
+    let partial = downloader.work_path(&dest).unwrap();
+    assert_eq!(partial.parent(), Some(temp_dir.as_path()));
+    assert!(partial.to_string_lossy().ends_with("-file.bin"));
+    fs::create_dir_all(&temp_dir).await.unwrap();
+    fs::write(&partial, &body[..2500]).await.unwrap();
+
+    let plan = downloader.inspect(&url, &dest).await.unwrap();
+    assert_eq!(plan.existing_bytes, 2500);
+
+    let bytes = downloader.download_resumable(&url, &dest).await.unwrap();
+    assert_eq!(bytes, 2500);
+    assert_eq!(fs::read(&dest).await.unwrap(), body);
+    assert!(!partial.exists());
+
+    let _ = fs::remove_dir_all(&root).await;
+}
+
+#[tokio::test]
+async fn test_temp_dir_same_names_kept_apart() {
+    let first: Vec<u8> = (0..200_000u32).map(|i| (i % 197) as u8).collect();
+    let second: Vec<u8> = (0..300_000u32).map(|i| (i % 193) as u8).collect();
+    let first_addr = common::serve_file(first.clone()).await;
+    let second_addr = common::serve_file(second.clone()).await;
+
+    let root = std::env::temp_dir().join("test_temp_dir_same_names");
+    let _ = fs::remove_dir_all(&root).await;
+
+    let config = ChunkConfig {
+        chunk_count: 4,
+        min_chunk_size: 1000,
+        temp_dir: Some(root.join("partials")),
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let (first_dest, second_dest) = (root.join("a/data.bin"), root.join("b/data.bin"));
+    for dir in ["a", "b"] {
+        fs::create_dir_all(root.join(dir)).await.unwrap();
+    }
+    assert_ne!(
+        downloader.work_path(&first_dest).unwrap(),
+        downloader.work_path(&second_dest).unwrap()
+    );
+
+    // both at once, each with a partial of its own
+    let results = downloader
+        .download_batch(
+            &[
+                (format!("http://{}/data.bin", first_addr), first_dest.clone()),
+                (format!("http://{}/data.bin", second_addr), second_dest.clone()),
+            ],
+            2,
+        )
+        .await;
+    assert_eq!(results, [Ok(200_000), Ok(300_000)]);
+    assert_eq!(fs::read(&first_dest).await.unwrap(), first);
+    assert_eq!(fs::read(&second_dest).await.unwrap(), second);
+
+    let _ = fs::remove_dir_all(&root).await;
+}
+
+#[tokio::test]
+async fn test_download_verified() {
+    let body = b"abc".repeat(1000);
+    let addr = common::serve_file(body.clone()).await;
+
+    let root = std::env::temp_dir().join("test_download_verified");
+    let _ = fs::remove_dir_all(&root).await;
+
+    let config = ChunkConfig {
+        temp_dir: Some(root.join("partials")),
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/verified.bin", addr);
+    let dest = root.join("verified.bin");
+
+    // a wrong digest fails and nothing reaches the destination
+    let bad = Checksum::Sha256(vec![0; 32]);
+    let err = downloader.download_verified(&url, &dest, &bad).await.unwrap_err();
+    assert!(matches!(err, DownloadError::ChecksumMismatch { .. }));
+    assert!(!dest.exists());
+
+    // sha256 of "abc" repeated 1000 times
+    let good =
+        Checksum::from_hex("328de8f1895f8bb09f6e6b4c2012ef2b2a6f067cd002794b750aa040a6f6d8bd")
+            .unwrap();
+    let bytes = downloader.download_verified(&url, &dest, &good).await.unwrap();
+    assert_eq!(bytes, 3000);
+    assert_eq!(fs::read(&dest).await.unwrap(), body);
+
+    let _ = fs::remove_dir_all(&root).await;
+}
+
+#[tokio::test]
+async fn test_download_verified_retries_corrupt_download() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let body = b"abc".repeat(1000);
+    let gets = Arc::new(AtomicUsize::new(0));
+
+    let served = body.clone();
+    let counter = gets.clone();
+    let addr = common::serve(move |request| {
+        if request.starts_with("GET") && counter.fetch_add(1, Ordering::SeqCst) == 0 {
+            // first transfer arrives with a flipped byte
+            let mut corrupt = served.clone();
+            corrupt[1234] ^= 0xff;
+            return common::file_response(request, &corrupt);
+        }
+        common::file_response(request, &served)
+    })
+    .await;
+
+    let downloader = ChunkedDownloader::new();
+    let url = format!("http://{}/flaky.bin", addr);
+    let file_path = std::env::temp_dir().join("test_download_verified_retry.bin");
+
+    let good =
+        Checksum::from_hex("328de8f1895f8bb09f6e6b4c2012ef2b2a6f067cd002794b750aa040a6f6d8bd")
+            .unwrap();
+    downloader.download_verified(&url, &file_path, &good).await.unwrap();
+
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+    assert_eq!(gets.load(Ordering::SeqCst), 2);
+
+    // with retries off the first bad transfer is final
+    gets.store(0, Ordering::SeqCst);
+    let config = ChunkConfig {
+        max_verify_retries: 0,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let err = downloader.download_verified(&url, &file_path, &good).await.unwrap_err();
+    assert!(matches!(err, DownloadError::ChecksumMismatch { .. }));
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_discovered_size_gives_real_progress() {
+    use engine::{Download, DownloadId};
+
+    let body = vec![4u8; 8000];
+    let addr = common::serve_file(body).await;
+
+    let url = format!("http://{}/progress.bin", addr);
+    let file_path = std::env::temp_dir().join("test_discovered_size.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    let mut download = Download::new(DownloadId::new(1), url.clone());
+    download.start();
+
+    let config = ChunkConfig {
+        chunk_count: 4,
+        min_chunk_size: 100,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let plan = downloader.inspect(&url, &file_path).await.unwrap();
+    download.set_total_bytes(plan.total_size.unwrap());
+
+    // two of the four chunks done
+    let done: u64 = plan.chunks.iter().take(2).map(|c| c.size()).sum();
+    download.update_progress(done, None);
+    assert_eq!(download.progress_percent(), 50.0);
+}
+
+/// Serves `body` only to requests carrying `cookie`, setting it on HEAD
+fn session_server(
+    body: Vec<u8>,
+    cookie: &'static str,
+    set_on_head: bool,
+) -> impl Fn(&str) -> Vec<u8> + Send + Sync + 'static {
+    move |request| {
+        if request.starts_with("HEAD") && set_on_head {
+            // log in on the first request, like a redirect through a login page
+            let raw = common::file_response(request, &body);
+            let raw = String::from_utf8(raw).unwrap();
+            let header = format!("Set-Cookie: {}; Path=/\r\n", cookie);
+            return raw.replacen("\r\n", &format!("\r\n{}", header), 1).into_bytes();
+        }
+
+        if common::header(request, "cookie") != Some(cookie) {
+            return common::response("403 Forbidden", &[("Content-Length", "0".into())], b"");
+        }
+
+        common::file_response(request, &body)
+    }
+}
+
+#[tokio::test]
+async fn test_cookies_carry_to_chunk_requests() {
+    let body: Vec<u8> = (0..4000u32).map(|i| (i % 193) as u8).collect();
+    let addr = common::serve(session_server(body.clone(), "session=s3cr3t", true)).await;
+
+    let config = ChunkConfig {
+        chunk_count: 4,
+        min_chunk_size: 100,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/members/file.bin", addr);
+    let file_path = std::env::temp_dir().join("test_cookies_chunks.bin");
+
+    let bytes = downloader.download(&url, &file_path).await.unwrap();
+    assert_eq!(bytes, 4000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_seeded_cookie_sent() {
+    let body = vec![8u8; 1000];
+    let addr = common::serve(session_server(body.clone(), "token=known", false)).await;
+
+    let url = format!("http://{}/file.bin", addr);
+    let client_config = ClientConfig::default().cookie(format!("http://{}/", addr), "token=known");
+    let downloader =
+        ChunkedDownloader::with_client_config(ChunkConfig::default(), &client_config).unwrap();
+    let file_path = std::env::temp_dir().join("test_seeded_cookie.bin");
+
+    downloader.download(&url, &file_path).await.unwrap();
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_wasted_bytes_when_ranges_ignored() {
+    // advertises ranges, then answers every request with the whole file
+    let body: Vec<u8> = (0..8000u32).map(|i| (i % 223) as u8).collect();
+    let served = body.clone();
+    let addr = common::serve(move |request| {
+        let mut headers = vec![("Content-Length", served.len().to_string())];
+        if request.starts_with("HEAD") {
+            headers.push(("Accept-Ranges", "bytes".to_string()));
+            return common::response("200 OK", &headers, b"");
+        }
+        common::response("200 OK", &headers, &served)
+    })
+    .await;
+    let url = format!("http://{}/file.bin", addr);
+    let file_path = std::env::temp_dir().join("test_wasted_bytes.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    // each of the two chunks gets all 8000 bytes and keeps its own 4000,
+    // with no stolen ranges requesting the whole file again
+    let config = ChunkConfig {
+        chunk_count: 2,
+        min_chunk_size: 1000,
+        rebalance_chunks: false,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let outcome = downloader.download_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.bytes_transferred, 8000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+    assert_eq!(outcome.wasted_bytes, 8000);
+
+    // the next download on the same downloader starts from zero
+    let outcome = downloader.download_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.wasted_bytes, 8000);
+
+    // a single-stream resume that gets the whole file again wastes what we had
+    let no_ranges = common::serve({
+        let body = body.clone();
+        move |request| {
+            let headers = [("Content-Length", body.len().to_string())];
+            let sent: &[u8] = if request.starts_with("HEAD") { b"" } else { &body };
+            common::response("200 OK", &headers, sent)
+        }
+    })
+    .await;
+    let url = format!("http://{}/file.bin", no_ranges);
+    fs::write(&file_path, &body[..3000]).await.unwrap();
+
+    let downloader = ChunkedDownloader::new();
+    let outcome = downloader.download_resumable_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.kind, OutcomeKind::Fresh);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+    assert_eq!(outcome.wasted_bytes, 3000);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_failure_limit() {
+    let body = vec![4u8; 2000];
+    let addr = common::serve(move |request| {
+        if request.starts_with("HEAD") {
+            return common::file_response(request, &body);
+        }
+        common::response("503 Service Unavailable", &[("Content-Length", "0".into())], b"")
+    })
+    .await;
+    let url = format!("http://{}/file.bin", addr);
+    let file_path = std::env::temp_dir().join("test_failure_limit.bin");
+    let config = ChunkConfig {
+        chunk_count: 1,
+        max_retries: 10,
+        retry_delay_ms: 10,
+        exponential_backoff: false,
+        failure_limit: Some(3),
+        ..ChunkConfig::default()
+    };
+
+    // the fourth failure within the window gives up before retries run out
+    let downloader = ChunkedDownloader::with_config(config.clone());
+    let err = downloader.download(&url, &file_path).await.unwrap_err();
+    assert_eq!(err, DownloadError::TooManyFailures { failures: 4, window_ms: 60_000 });
+
+    let mut download = Download::new(DownloadId::new(1), url.clone());
+    download.start();
+    download.fail_with(&err);
+    assert_eq!(download.status(), DownloadStatus::Paused);
+
+    // each download counts its own failures, even on a shared downloader
+    let per_download = ChunkConfig {
+        max_retries: 2,
+        ..config.clone()
+    };
+    let downloader = ChunkedDownloader::with_config(per_download);
+    for _ in 0..2 {
+        let err = downloader.download(&url, &file_path).await.unwrap_err();
+        assert_eq!(err, DownloadError::HttpError(503));
+    }
+
+    // failures spread wider than the window don't add up
+    let spread = ChunkConfig {
+        max_retries: 4,
+        retry_delay_ms: 30,
+        failure_window_ms: 20,
+        ..config
+    };
+    let downloader = ChunkedDownloader::with_config(spread);
+    let err = downloader.download(&url, &file_path).await.unwrap_err();
+    assert_eq!(err, DownloadError::HttpError(503));
+
+    download.fail_with(&err);
+    assert_eq!(download.status(), DownloadStatus::Failed);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_sequential_availability() {
+    use std::time::Duration;
+
+    let body: Vec<u8> = (0..8000u32).map(|i| (i % 251) as u8).collect();
+    let served = body.clone();
+    let addr = common::serve_with(move |request| {
+        // the leading chunk is slow, the others finish first
+        let slow = common::header(request, "range") == Some("bytes=0-1999");
+        common::Reply {
+            bytes: common::file_response(request, &served),
+            delay: if slow { Duration::from_millis(400) } else { Duration::ZERO },
+            stall: false,
+        }
+    })
+    .await;
+
+    let config = ChunkConfig {
+        chunk_count: 4,
+        min_chunk_size: 1000,
+        rebalance_chunks: false,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/media.bin", addr);
+    let file_path = std::env::temp_dir().join("test_sequential_availability.bin");
+    let other_path = std::env::temp_dir().join("test_sequential_availability_other.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    // a second download alongside gets its own count
+    let (available, download) = downloader.download_watched(&url, &file_path);
+    let (other_available, other) = downloader.download_watched(&url, &other_path);
+    let check = async {
+        // three quarters are on disk, but nothing can be read in order yet
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(*available.borrow(), 0);
+        assert_eq!(*other_available.borrow(), 0);
+    };
+
+    let (outcome, other_outcome, ()) = tokio::join!(download, other, check);
+    assert_eq!(outcome.unwrap().bytes_transferred, 8000);
+    assert_eq!(other_outcome.unwrap().bytes_transferred, 8000);
+    assert_eq!(*available.borrow(), 8000);
+    assert_eq!(*other_available.borrow(), 8000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let _ = fs::remove_file(&file_path).await;
+    let _ = fs::remove_file(&other_path).await;
+}
+
+#[tokio::test]
+async fn test_write_buffer_download() {
+    use std::time::Duration;
+
+    let body: Vec<u8> = (0..65_536u32).map(|i| (i % 241) as u8).collect();
+
+    let served = body.clone();
+    let addr = common::serve_with(move |request| {
+        // the first chunk is slow, so its tail gets taken over mid-stream
+        let slow = common::header(request, "range") == Some("bytes=0-16383");
+        common::Reply {
+            bytes: common::file_response(request, &served),
+            delay: if slow { Duration::from_millis(300) } else { Duration::ZERO },
+            stall: false,
+        }
+    })
+    .await;
+
+    let config = ChunkConfig {
+        chunk_count: 4,
+        min_chunk_size: 1024,
+        write_buffer_chunks: 1,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/buffered.bin", addr);
+    let file_path = std::env::temp_dir().join("test_write_buffer.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    // the writer stops at a moved end while the network side is still sending
+    let bytes = downloader.download(&url, &file_path).await.unwrap();
+    assert_eq!(bytes, 65_536);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_flush_interval_download() {
+    let body: Vec<u8> = (0..50_000u32).map(|i| (i % 239) as u8).collect();
+    let addr = common::serve_file(body.clone()).await;
+    let url = format!("http://{}/durable.bin", addr);
+    let file_path = std::env::temp_dir().join("test_flush_interval.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    // flushing and syncing far more often than the chunk size, in chunks
+    // and in a single stream, leaves the same file
+    let config = ChunkConfig {
+        chunk_count: 4,
+        min_chunk_size: 1024,
+        flush_interval_bytes: Some(1000),
+        sync_on_flush: true,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config.clone());
+    assert_eq!(downloader.download(&url, &file_path).await.unwrap(), 50_000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let single = ChunkConfig { chunk_count: 1, ..config };
+    let downloader = ChunkedDownloader::with_config(single);
+    assert_eq!(downloader.download(&url, &file_path).await.unwrap(), 50_000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_deadline_keeps_partial_for_resume() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let body: Vec<u8> = (0..40_000u32).map(|i| (i % 227) as u8).collect();
+    let throttled = Arc::new(AtomicBool::new(true));
+
+    // a slow server: the first GET sends half the body, then nothing more
+    let served = body.clone();
+    let slow = throttled.clone();
+    let addr = common::serve_stalling(move |request| {
+        let mut raw = common::file_response(request, &served);
+        let stall = request.starts_with("GET") && slow.swap(false, Ordering::SeqCst);
+        if stall {
+            raw.truncate(raw.len() - served.len() / 2);
+        }
+        (raw, stall)
+    })
+    .await;
+    let url = format!("http://{}/slow.bin", addr);
+    let file_path = std::env::temp_dir().join("test_deadline.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    // segment files show how far each chunk got, a preallocated file can't
+    let config = ChunkConfig {
+        chunk_count: 1,
+        segment_mode: SegmentMode::SeparateFiles,
+        deadline_ms: Some(500),
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config.clone());
+    let started = std::time::Instant::now();
+    let err = downloader.download_resumable(&url, &file_path).await.unwrap_err();
+    assert_eq!(err, DownloadError::DeadlineExceeded(500));
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // the bytes that arrived in time are kept for the next attempt
+    let downloader = ChunkedDownloader::with_config(config);
+    let outcome = downloader.download_resumable_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.kind, OutcomeKind::Resumed);
+    assert_eq!(outcome.bytes_transferred, 20_000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_preallocated_partial_resumes() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let body: Vec<u8> = (0..40_000u32).map(|i| (i % 229) as u8).collect();
+    let throttled = Arc::new(AtomicBool::new(true));
+
+    // the second chunk sends half its bytes the first time, then nothing more
+    let served = body.clone();
+    let slow = throttled.clone();
+    let addr = common::serve_stalling(move |request| {
+        let mut raw = common::file_response(request, &served);
+        let second = common::header(request, "range") == Some("bytes=20000-39999");
+        let stall = second && slow.swap(false, Ordering::SeqCst);
+        if stall {
+            raw.truncate(raw.len() - 10_000);
+        }
+        (raw, stall)
+    })
+    .await;
+    let url = format!("http://{}/prealloc.bin", addr);
+    let file_path = std::env::temp_dir().join("test_preallocated_partial.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    let config = ChunkConfig {
+        chunk_count: 2,
+        min_chunk_size: 1000,
+        rebalance_chunks: false,
+        deadline_ms: Some(500),
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config.clone());
+    let err = downloader.download(&url, &file_path).await.unwrap_err();
+    assert_eq!(err, DownloadError::DeadlineExceeded(500));
+
+    // the file already has its full length, but only the first chunk is done
+    assert_eq!(fs::metadata(&file_path).await.unwrap().len(), 40_000);
+
+    // a progress file for watchers sits next to it and doesn't get in the way
+    let progress = ProgressFile::for_download(&file_path);
+    let download = Download::new(DownloadId::new(42), url.clone());
+    progress.update(&download).await.unwrap();
+
+    let downloader = ChunkedDownloader::with_config(ChunkConfig {
+        deadline_ms: None,
+        ..config
+    });
+    let outcome = downloader.download_resumable_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.kind, OutcomeKind::Resumed);
+    assert_eq!(outcome.bytes_transferred, 20_000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    // the chunk layout goes once the file is done, the progress file is the caller's
+    let mut layout = file_path.clone().into_os_string();
+    layout.push(".chunks");
+    assert!(!std::path::Path::new(&layout).exists());
+    assert!(progress.path().exists());
+
+    let _ = progress.remove().await;
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_progress_file_keeps_single_stream_resume() {
+    let body: Vec<u8> = (0..4_194_304u32).map(|i| (i % 227) as u8).collect();
+    let addr = common::serve_file(body.clone()).await;
+    let url = format!("http://{}/single.bin", addr);
+    let file_path = std::env::temp_dir().join("test_progress_file_single.bin");
+
+    // the first MiB was written in order, and a watcher's progress file is next to it
+    fs::write(&file_path, &body[..1_048_576]).await.unwrap();
+    let progress = ProgressFile::for_download(&file_path);
+    let download = Download::new(DownloadId::new(43), url.clone());
+    progress.update(&download).await.unwrap();
+
+    let outcome = ChunkedDownloader::new()
+        .download_resumable_outcome(&url, &file_path)
+        .await
+        .unwrap();
+    assert_eq!(outcome.kind, OutcomeKind::Resumed);
+    assert_eq!(outcome.bytes_transferred, 3_145_728);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let _ = progress.remove().await;
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_zero_chunk_count_still_downloads() {
+    let body: Vec<u8> = (0..4_194_304u32).map(|i| (i % 241) as u8).collect();
+    let addr = common::serve_file(body.clone()).await;
+    let url = format!("http://{}/zero.bin", addr);
+    let file_path = std::env::temp_dir().join("test_zero_chunk_count.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    // nothing on the download's side rejects a count of 0
+    let mut download = Download::new(DownloadId::new(41), url.clone());
+    download.set_chunk_count(0);
+    let config = ChunkConfig::default().for_download(&download);
+    assert_eq!(config.chunk_count, 0);
+
+    let downloader = ChunkedDownloader::with_config(config);
+    assert_eq!(downloader.download(&url, &file_path).await.unwrap(), 4_194_304);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_deadline_covers_checksum_retries() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let body = b"abc".repeat(1000);
+    let gets = Arc::new(AtomicUsize::new(0));
+
+    // every transfer takes 300ms, and the first one arrives corrupted
+    let served = body.clone();
+    let counter = gets.clone();
+    let addr = common::serve_with(move |request| {
+        let get = request.starts_with("GET");
+        let mut sent = served.clone();
+        if get && counter.fetch_add(1, Ordering::SeqCst) == 0 {
+            sent[1234] ^= 0xff;
+        }
+        common::Reply {
+            bytes: common::file_response(request, &sent),
+            delay: if get { Duration::from_millis(300) } else { Duration::ZERO },
+            stall: false,
+        }
+    })
+    .await;
+    let url = format!("http://{}/slow.bin", addr);
+    let file_path = std::env::temp_dir().join("test_deadline_checksum.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    // either transfer fits in the deadline, both together don't
+    let config = ChunkConfig {
+        deadline_ms: Some(500),
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let good =
+        Checksum::from_hex("328de8f1895f8bb09f6e6b4c2012ef2b2a6f067cd002794b750aa040a6f6d8bd")
+            .unwrap();
+    let err = downloader.download_verified(&url, &file_path, &good).await.unwrap_err();
+    assert_eq!(err, DownloadError::DeadlineExceeded(500));
+    assert_eq!(gets.load(Ordering::SeqCst), 2);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_write_buffer_stall_reconnects() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    let body: Vec<u8> = (0..2000u32).map(|i| (i % 229) as u8).collect();
+    let attempts = Arc::new(AtomicU32::new(0));
+
+    let served = body.clone();
+    let seen = attempts.clone();
+    let addr = common::serve_stalling(move |request| {
+        let raw = common::file_response(request, &served);
+        let first_attempt = request.starts_with("GET") && seen.fetch_add(1, Ordering::SeqCst) == 0;
+        if first_attempt {
+            // part of the body, then the socket goes quiet
+            let cut = raw.len() - 1200;
+            return (raw[..cut].to_vec(), true);
+        }
+        (raw, false)
+    })
+    .await;
+
+    let config = ChunkConfig {
+        chunk_count: 1,
+        stall_timeout_ms: Some(200),
+        retry_delay_ms: 10,
+        write_buffer_chunks: 4,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/buffered_stall.bin", addr);
+    let file_path = std::env::temp_dir().join("test_write_buffer_stall.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    // the stall timeout still applies with the buffer between network and disk
+    let outcome = downloader.download_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.bytes_transferred, 2000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+    assert_eq!(outcome.retries, 1);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_download_metalink_falls_back_to_next_mirror() {
+    let body = b"abc".repeat(1000);
+
+    let served = body.clone();
+    let addr = common::serve(move |request| {
+        if request.starts_with("GET /broken") || request.starts_with("HEAD /broken") {
+            return common::response("404 Not Found", &[("Content-Length", "0".to_string())], b"");
+        }
+        common::file_response(request, &served)
+    })
+    .await;
+
+    // sha256 of "abc" repeated 1000 times
+    let xml = format!(
+        r#"<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+          <file name="mirrored.bin">
+            <size>3000</size>
+            <hash type="sha-256">328de8f1895f8bb09f6e6b4c2012ef2b2a6f067cd002794b750aa040a6f6d8bd</hash>
+            <url priority="2">http://{addr}/good/mirrored.bin</url>
+            <url priority="1">http://{addr}/broken/mirrored.bin</url>
+          </file>
+        </metalink>"#
+    );
+    let file = parse_metalink(&xml).unwrap().remove(0);
+
+    let downloader = ChunkedDownloader::new();
+    let file_path = std::env::temp_dir().join("test_download_metalink.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    let bytes = downloader.download_metalink(&file, &file_path).await.unwrap();
+    assert_eq!(bytes, 3000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    // a size no mirror serves fails after trying them all
+    let wrong_size = engine::MetalinkFile {
+        size: Some(2999),
+        checksums: Vec::new(),
+        ..file
+    };
+    let err = downloader.download_metalink(&wrong_size, &file_path).await.unwrap_err();
+    assert!(matches!(err, DownloadError::LengthMismatch { expected: 2999, actual: 3000 }));
+    assert!(!file_path.exists());
+}
+
+#[tokio::test]
+async fn test_resume_check_refetches_bad_seam() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    let body: Vec<u8> = (0..4000u32).map(|i| (i % 233) as u8).collect();
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+    let cut_check = Arc::new(AtomicBool::new(false));
+
+    let served = body.clone();
+    let seen = ranges.clone();
+    let cut = cut_check.clone();
+    let addr = common::serve(move |request| {
+        let range = common::header(request, "range");
+        if let Some(range) = range {
+            seen.lock().unwrap().push(range.to_string());
+        }
+        let mut raw = common::file_response(request, &served);
+        if range == Some("bytes=936-999") && cut.swap(false, Ordering::SeqCst) {
+            // the connection drops before the whole check arrives
+            raw.truncate(raw.len() - 10);
+        }
+        raw
+    })
+    .await;
+
+    let config = ChunkConfig {
+        chunk_count: 1,
+        resume_check_bytes: Some(64),
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/seam.bin", addr);
+    let file_path = std::env::temp_dir().join("test_resume_check_seam.bin");
+
+    // an intact partial file passes the check and resumes where it stopped
+    fs::write(&file_path, &body[..1000]).await.unwrap();
+    downloader.download_resumable(&url, &file_path).await.unwrap();
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+    assert_eq!(*ranges.lock().unwrap(), ["bytes=936-999", "bytes=1000-3999"]);
+
+    // a byte off just before the resume point means starting over
+    let mut corrupt = body[..1000].to_vec();
+    corrupt[990] ^= 0xff;
+    fs::write(&file_path, &corrupt).await.unwrap();
+    ranges.lock().unwrap().clear();
+
+    downloader.download_resumable(&url, &file_path).await.unwrap();
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+    assert_eq!(*ranges.lock().unwrap(), ["bytes=936-999", "bytes=0-3999"]);
+
+    // a check that fails to arrive leaves the partial data as it is
+    fs::write(&file_path, &body[..1000]).await.unwrap();
+    ranges.lock().unwrap().clear();
+    cut_check.store(true, Ordering::SeqCst);
+
+    downloader.download_resumable(&url, &file_path).await.unwrap();
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+    assert_eq!(*ranges.lock().unwrap(), ["bytes=936-999", "bytes=1000-3999"]);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_remote_change_detected_mid_download() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let old: Vec<u8> = vec![1u8; 4000];
+    let new: Vec<u8> = (0..5000u32).map(|i| (i % 211) as u8).collect();
+    let changed = Arc::new(AtomicBool::new(false));
+
+    // the file is replaced right after the first probe
+    let (v1, v2, flag) = (old.clone(), new.clone(), changed.clone());
+    let addr = common::serve(move |request| {
+        let current = if flag.load(Ordering::SeqCst) { &v2 } else { &v1 };
+        let reply = common::file_response(request, current);
+        flag.store(true, Ordering::SeqCst);
+        reply
+    })
+    .await;
+
+    let config = ChunkConfig {
+        chunk_count: 2,
+        min_chunk_size: 100,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config.clone());
+    let url = format!("http://{}/replaced.bin", addr);
+    let file_path = std::env::temp_dir().join("test_remote_change.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    let err = downloader.download(&url, &file_path).await.unwrap_err();
+    assert_eq!(err, DownloadError::ResourceChanged { expected: 4000, actual: 5000 });
+    assert!(!file_path.exists(), "mixed partial data should be discarded");
+
+    // with restarts on, the new version is downloaded from scratch
+    changed.store(false, Ordering::SeqCst);
+    let downloader = ChunkedDownloader::with_config(ChunkConfig {
+        restart_on_change: true,
+        ..config
+    });
+
+    let bytes = downloader.download_resumable(&url, &file_path).await.unwrap();
+    assert_eq!(bytes, 5000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), new);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_download_planned_after_replan() {
+    use std::sync::{Arc, Mutex};
+
+    let body: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+
+    let served = body.clone();
+    let seen = ranges.clone();
+    let addr = common::serve(move |request| {
+        if let Some(range) = common::header(request, "range") {
+            seen.lock().unwrap().push(range.to_string());
+        }
+        common::file_response(request, &served)
+    })
+    .await;
+
+    // a paused 4-chunk download: chunk 0 done, 1 and 3 partly, 2 untouched
+    let progress = [10_000u64, 5000, 0, 2000];
+    let mut partial = vec![0u8; 40_000];
+    let chunks: Vec<Chunk> = (0..4u8)
+        .map(|i| {
+            let start = i as u64 * 10_000;
+            let done = progress[i as usize] as usize;
+            partial[start as usize..start as usize + done]
+                .copy_from_slice(&body[start as usize..start as usize + done]);
+            Chunk {
+                index: i,
+                start,
+                end: start + 9999,
+                downloaded: done as u64,
+            }
+        })
+        .collect();
+
+    let file_path = std::env::temp_dir().join("test_download_planned.bin");
+    fs::write(&file_path, &partial).await.unwrap();
+
+    let config = ChunkConfig {
+        chunk_count: 4,
+        min_chunk_size: 1024,
+        rebalance_chunks: false,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let url = format!("http://{}/planned.bin", addr);
+
+    // bump to 8 connections for what's left
+    let replanned = downloader.replan_chunks(&chunks, 8);
+    let bytes = downloader.download_planned(&url, &file_path, replanned).await.unwrap();
+
+    assert_eq!(bytes, 40_000 - 17_000);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    // only missing bytes were asked for, over 8 connections
+    let ranges = ranges.lock().unwrap().clone();
+    assert_eq!(ranges.len(), 8, "ranges: {:?}", ranges);
+    assert!(ranges.iter().any(|r| r.starts_with("bytes=15000-")));
+    assert!(ranges.iter().all(|r| !r.starts_with("bytes=0-")));
+
+    // a plan for a different size is refused
+    let stale = downloader.calculate_chunks(50_000);
+    let err = downloader.download_planned(&url, &file_path, stale).await.unwrap_err();
+    assert_eq!(err, DownloadError::ResourceChanged { expected: 50_000, actual: 40_000 });
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_pool_reuses_connections_across_downloads() {
+    use engine::HttpDownloader;
+    use std::sync::atomic::Ordering;
+
+    let body = vec![3u8; 2000];
+    let (addr, connections) = common::serve_keep_alive(body.clone()).await;
+    let file_path = std::env::temp_dir().join("test_pool_reuse.bin");
+
+    // many small files from one host share a connection
+    let downloader = ChunkedDownloader::new();
+    for i in 0..10 {
+        let url = format!("http://{}/small{}.bin", addr, i);
+        downloader.download(&url, &file_path).await.unwrap();
+    }
+    let pooled = connections.swap(0, Ordering::SeqCst);
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+
+    // without an idle pool every request connects again
+    let no_pool = ClientConfig {
+        pool_max_idle_per_host: 0,
+        ..ClientConfig::default()
+    };
+    let downloader = HttpDownloader::with_client_config(&no_pool).unwrap();
+    for i in 0..10 {
+        let url = format!("http://{}/small{}.bin", addr, i);
+        downloader.download(&url, &file_path).await.unwrap();
+    }
+    let unpooled = connections.load(Ordering::SeqCst);
+
+    assert!(pooled <= 2, "pooled downloads used {} connections", pooled);
+    assert_eq!(unpooled, 10);
+
+    let _ = fs::remove_file(&file_path).await;
+}
+
+#[tokio::test]
+async fn test_preserve_timestamps() {
+    use std::time::{Duration, SystemTime};
+
+    let body: Vec<u8> = (0..20_000u32).map(|i| (i % 241) as u8).collect();
+    let serve_modified = |last_modified: &'static str| {
+        let body = body.clone();
+        common::serve(move |request| {
+            if !request.starts_with("HEAD") {
+                return common::file_response(request, &body);
+            }
+            let headers = [
+                ("Accept-Ranges", "bytes".to_string()),
+                ("Content-Length", body.len().to_string()),
+                ("Last-Modified", last_modified.to_string()),
+            ];
+            common::response("200 OK", &headers, b"")
+        })
+    };
+    let config = ChunkConfig {
+        chunk_count: 4,
+        min_chunk_size: 1024,
+        preserve_timestamps: true,
+        ..ChunkConfig::default()
+    };
+    let downloader = ChunkedDownloader::with_config(config);
+    let file_path = std::env::temp_dir().join("test_preserve_timestamps.bin");
+    let _ = fs::remove_file(&file_path).await;
+
+    // the file carries the server's time, not the time it was written
+    let addr = serve_modified("Wed, 21 Oct 2015 07:28:00 GMT").await;
+    let url = format!("http://{}/file.bin", addr);
+    let outcome = downloader.download_outcome(&url, &file_path).await.unwrap();
+    let expected = SystemTime::UNIX_EPOCH + Duration::from_secs(1_445_412_480);
+    assert_eq!(outcome.last_modified, Some(expected));
+    assert_eq!(fs::read(&file_path).await.unwrap(), body);
+    let mtime = fs::metadata(&file_path).await.unwrap().modified().unwrap();
+    assert_eq!(mtime, expected);
+
+    // a header that doesn't parse leaves the file as written
+    let addr = serve_modified("last tuesday").await;
+    let url = format!("http://{}/file.bin", addr);
+    let _ = fs::remove_file(&file_path).await;
+    let outcome = downloader.download_outcome(&url, &file_path).await.unwrap();
+    assert_eq!(outcome.last_modified, None);
+    let mtime = fs::metadata(&file_path).await.unwrap().modified().unwrap();
+    assert!(mtime > expected);
+
+    let _ = fs::remove_file(&file_path).await;
+}
@@ -0,0 +1,225 @@
+//! Minimal local HTTP server for offline integration tests
+//!
+//! Each connection serves a single request and is then closed, which keeps
+//! the server simple while still exercising the real HTTP client.
+//! `serve_keep_alive` is the exception, for tests about connection reuse.
+
+#![allow(dead_code)] // not every test binary uses every helper
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Starts a server that answers each request with `handler(request_head)`
+///
+/// The handler receives the raw request line and headers and returns the
+/// raw response bytes.
+pub async fn serve<F>(handler: F) -> SocketAddr
+where
+    F: Fn(&str) -> Vec<u8> + Send + Sync + 'static,
+{
+    serve_stalling(move |request| (handler(request), false)).await
+}
+
+/// Like `serve`, but the handler can ask to stall after writing its bytes
+///
+/// A stalled connection stays open without sending anything more, like a
+/// dead-but-not-closed socket.
+pub async fn serve_stalling<F>(handler: F) -> SocketAddr
+where
+    F: Fn(&str) -> (Vec<u8>, bool) + Send + Sync + 'static,
+{
+    serve_with(move |request| {
+        let (bytes, stall) = handler(request);
+        Reply {
+            bytes,
+            stall,
+            ..Reply::default()
+        }
+    })
+    .await
+}
+
+/// How the server answers one request
+#[derive(Default)]
+pub struct Reply {
+    /// Raw response bytes
+    pub bytes: Vec<u8>,
+    /// Wait this long before sending anything
+    pub delay: Duration,
+    /// Keep the connection open and silent after sending
+    pub stall: bool,
+}
+
+/// Starts a server that answers each request with a `Reply`
+pub async fn serve_with<F>(handler: F) -> SocketAddr
+where
+    F: Fn(&str) -> Reply + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let handler = handler.clone();
+
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+
+                // read until the end of the request headers
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match socket.read(&mut buffer).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buffer[..n]),
+                    }
+                }
+
+                let reply = handler(&String::from_utf8_lossy(&request));
+
+                tokio::time::sleep(reply.delay).await;
+                let _ = socket.write_all(&reply.bytes).await;
+
+                if reply.stall {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                }
+                let _ = socket.shutdown().await;
+            });
+        }
+    });
+
+    addr
+}
+
+/// Serves `body` like `serve_file`, keeping connections open between requests
+///
+/// Returns the address and a count of accepted connections.
+pub async fn serve_keep_alive(body: Vec<u8>) -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let body = Arc::new(body);
+
+    let accepted = connections.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            let body = body.clone();
+
+            tokio::spawn(async move {
+                let mut pending = Vec::new();
+                let mut buffer = [0u8; 1024];
+
+                loop {
+                    // requests have no body, so each ends with the headers
+                    let end = match pending.windows(4).position(|w| w == b"\r\n\r\n") {
+                        Some(pos) => pos + 4,
+                        None => match socket.read(&mut buffer).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => {
+                                pending.extend_from_slice(&buffer[..n]);
+                                continue;
+                            }
+                        },
+                    };
+
+                    let request: Vec<u8> = pending.drain(..end).collect();
+                    let raw = file_response(&String::from_utf8_lossy(&request), &body);
+
+                    // same response, minus the header asking to close
+                    let close = b"Connection: close\r\n";
+                    let at = raw.windows(close.len()).position(|w| w == close).unwrap();
+                    let raw = [&raw[..at], &raw[at + close.len()..]].concat();
+
+                    if socket.write_all(&raw).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    (addr, connections)
+}
+
+/// Answers a GET for `body`, honouring a single byte range if present
+pub fn file_response(request: &str, body: &[u8]) -> Vec<u8> {
+    let total = body.len() as u64;
+    let mut headers = vec![("Accept-Ranges", "bytes".to_string())];
+
+    if request.starts_with("HEAD") {
+        headers.push(("Content-Length", total.to_string()));
+        return response("200 OK", &headers, b"");
+    }
+
+    match header(request, "range").and_then(|r| parse_range(r, total)) {
+        Some((start, end)) => {
+            let slice = &body[start as usize..=end as usize];
+            headers.push(("Content-Length", slice.len().to_string()));
+            headers.push((
+                "Content-Range",
+                format!("bytes {}-{}/{}", start, end, total),
+            ));
+            response("206 Partial Content", &headers, slice)
+        }
+        None => {
+            headers.push(("Content-Length", total.to_string()));
+            response("200 OK", &headers, body)
+        }
+    }
+}
+
+/// Starts a server for `body` that supports HEAD and single byte ranges
+pub async fn serve_file(body: Vec<u8>) -> SocketAddr {
+    serve(move |request| file_response(request, &body)).await
+}
+
+/// Parses `bytes=start-end` or `bytes=start-` against a total length
+pub fn parse_range(value: &str, total: u64) -> Option<(u64, u64)> {
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    let start = start.parse().ok()?;
+    let end = match end {
+        "" => total - 1,
+        end => end.parse::<u64>().ok()?.min(total - 1),
+    };
+    (start <= end).then_some((start, end))
+}
+
+/// Returns the value of a request header, matched case-insensitively
+pub fn header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    request.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Builds a raw response with the given status line, headers and body
+pub fn response(status: &str, headers: &[(&str, String)], body: &[u8]) -> Vec<u8> {
+    let mut raw = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+
+    for (name, value) in headers {
+        raw.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    raw.push_str("\r\n");
+
+    let mut raw = raw.into_bytes();
+    raw.extend_from_slice(body);
+    raw
+}
+
+/// Encodes a body using chunked transfer encoding
+pub fn chunked_body(body: &[u8], piece: usize) -> Vec<u8> {
+    let mut raw = Vec::new();
+
+    for part in body.chunks(piece) {
+        raw.extend_from_slice(format!("{:x}\r\n", part.len()).as_bytes());
+        raw.extend_from_slice(part);
+        raw.extend_from_slice(b"\r\n");
+    }
+    raw.extend_from_slice(b"0\r\n\r\n");
+    raw
+}
@@ -1,9 +1,11 @@
 //! Integration tests for HTTP downloader
 //!
-//! These tests require network access and use external services.
+//! The ignored tests require network access and use external services.
 //! Run with: cargo test -p engine --test http_integration -- --ignored
 
-use engine::HttpDownloader;
+mod common;
+
+use engine::{DownloadError, HttpDownloader};
 use tokio::fs;
 
 #[tokio::test]
@@ -59,3 +61,72 @@ async fn test_download_404_error() {
         assert!(matches!(e, engine::DownloadError::HttpError(404)));
     }
 }
+
+#[tokio::test]
+#[ignore] // requires network, may be flaky
+async fn test_download_bytes() {
+    let downloader = HttpDownloader::new();
+
+    let bytes = downloader
+        .download_bytes("https://httpbin.org/bytes/2048")
+        .await
+        .expect("download into memory failed");
+
+    assert_eq!(bytes.len(), 2048, "Should hold exactly 2KB");
+}
+
+#[tokio::test]
+#[ignore] // requires network, may be flaky
+async fn test_download_bytes_over_limit() {
+    let downloader = HttpDownloader::new().with_max_in_memory_size(1024);
+
+    // 2KB body against a 1KB limit
+    let result = downloader
+        .download_bytes("https://httpbin.org/bytes/2048")
+        .await;
+
+    assert!(matches!(result, Err(engine::DownloadError::TooLarge(1024))));
+}
+
+#[tokio::test]
+async fn test_download_bytes_local() {
+    let addr = common::serve(|_| {
+        common::response("200 OK", &[("Content-Length", "2048".to_string())], &[7; 2048])
+    })
+    .await;
+    let downloader = HttpDownloader::new().with_max_in_memory_size(2048);
+
+    // exactly at the limit is still accepted
+    let bytes = downloader
+        .download_bytes(&format!("http://{}/small.bin", addr))
+        .await
+        .unwrap();
+    assert_eq!(bytes, vec![7; 2048]);
+}
+
+#[tokio::test]
+async fn test_download_bytes_content_length_over_limit() {
+    let addr = common::serve(|_| {
+        common::response("200 OK", &[("Content-Length", "2048".to_string())], &[7; 2048])
+    })
+    .await;
+    let downloader = HttpDownloader::new().with_max_in_memory_size(1024);
+
+    let result = downloader
+        .download_bytes(&format!("http://{}/big.bin", addr))
+        .await;
+    assert!(matches!(result, Err(DownloadError::TooLarge(1024))));
+}
+
+#[tokio::test]
+async fn test_download_bytes_unsized_body_over_limit() {
+    // no Content-Length, so the body runs until the connection closes and
+    // only the bytes counted while streaming can catch it
+    let addr = common::serve(|_| common::response("200 OK", &[], &[7; 4096])).await;
+    let downloader = HttpDownloader::new().with_max_in_memory_size(1024);
+
+    let result = downloader
+        .download_bytes(&format!("http://{}/stream.bin", addr))
+        .await;
+    assert!(matches!(result, Err(DownloadError::TooLarge(1024))));
+}